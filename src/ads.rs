@@ -0,0 +1,82 @@
+use std::io;
+use std::path::Path;
+
+/// An alternate data stream of an NTFS file, for --include-ads
+pub struct Stream {
+    /// Without the leading `:` and the `:$DATA` type, e.g. `Zone.Identifier`
+    pub name: String,
+    pub size: u64,
+}
+
+/// The alternate data streams of the file at `path`, without its unnamed main stream
+#[cfg(windows)]
+pub fn streams(path: &Path) -> io::Result<Vec<Stream>> {
+    use std::os::windows::ffi::OsStrExt;
+
+    /// WIN32_FIND_STREAM_DATA, the name holds MAX_PATH + 36 characters
+    #[repr(C)]
+    struct FindStreamData {
+        size: i64,
+        name: [u16; 296],
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn FindFirstStreamW(
+            file_name: *const u16,
+            info_level: i32,
+            data: *mut FindStreamData,
+            flags: u32,
+        ) -> isize;
+        fn FindNextStreamW(handle: isize, data: *mut FindStreamData) -> i32;
+        fn FindClose(handle: isize) -> i32;
+    }
+    const FIND_STREAM_INFO_STANDARD: i32 = 0;
+    const INVALID_HANDLE_VALUE: isize = -1;
+    const ERROR_HANDLE_EOF: i32 = 38;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut data = FindStreamData {
+        size: 0,
+        name: [0; 296],
+    };
+    // SAFETY: wide is NUL terminated and data is valid for the call
+    let handle =
+        unsafe { FindFirstStreamW(wide.as_ptr(), FIND_STREAM_INFO_STANDARD, &mut data, 0) };
+    if handle == INVALID_HANDLE_VALUE {
+        let e = io::Error::last_os_error();
+        // A file without any stream, not even the main one
+        return match e.raw_os_error() {
+            Some(ERROR_HANDLE_EOF) => Ok(Vec::new()),
+            _ => Err(e),
+        };
+    }
+
+    let mut streams = Vec::new();
+    loop {
+        let len = data.name.iter().position(|c| *c == 0).unwrap_or(data.name.len());
+        let name = String::from_utf16_lossy(&data.name[..len]);
+        // Names look like `:Zone.Identifier:$DATA`, the main stream is `::$DATA`
+        let name = name.strip_suffix(":$DATA").unwrap_or(&name).trim_start_matches(':');
+        if !name.is_empty() {
+            streams.push(Stream {
+                name: name.to_owned(),
+                size: data.size.max(0) as u64,
+            });
+        }
+        // SAFETY: handle came from FindFirstStreamW and is not closed yet
+        if unsafe { FindNextStreamW(handle, &mut data) } == 0 {
+            let e = io::Error::last_os_error();
+            unsafe { FindClose(handle) };
+            return match e.raw_os_error() {
+                Some(ERROR_HANDLE_EOF) => Ok(streams),
+                _ => Err(e),
+            };
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn streams(_path: &Path) -> io::Result<Vec<Stream>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "only supported on Windows"))
+}
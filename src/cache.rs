@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// First line of every cache file, bumped when the line format changes
+const HEADER: &str = "lff-cache 1";
+
+/// What an earlier scan recorded about one file
+pub struct CachedFile {
+    pub size: u64,
+    pub mtime: u64,
+}
+
+/// Modification time in nanoseconds since the epoch, 0 when the platform has none
+pub fn mtime(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Read a cache written by `save`, keyed by the path the file was reported under
+pub fn load(path: &Path) -> io::Result<HashMap<String, CachedFile>> {
+    let mut lines = BufReader::new(fs::File::open(path)?).lines();
+    match lines.next() {
+        Some(Ok(header)) if header == HEADER => {}
+        Some(Err(e)) => return Err(e),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not an lff cache file")),
+    }
+
+    let mut files = HashMap::new();
+    for line in lines {
+        let line = line?;
+        let mut fields = line.splitn(3, '\t');
+        let parsed = match (fields.next(), fields.next(), fields.next()) {
+            (Some(size), Some(mtime), Some(file)) => size
+                .parse()
+                .and_then(|size| mtime.parse().map(|mtime| (size, mtime)))
+                .ok()
+                .map(|(size, mtime)| (file.to_owned(), CachedFile { size, mtime })),
+            _ => None,
+        };
+        match parsed {
+            Some((file, cached)) => {
+                files.insert(file, cached);
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed cache line: {}", line),
+                ))
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Write one `size<TAB>mtime<TAB>path` line per file. Paths with a newline can not be
+/// written to this format and are left out, they are simply scanned again next time.
+pub fn save<'a>(path: &Path, files: impl Iterator<Item = (&'a str, u64, u64)>) -> io::Result<()> {
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    writeln!(out, "{}", HEADER)?;
+    for (file, size, mtime) in files {
+        if file.contains('\n') {
+            continue;
+        }
+        writeln!(out, "{}\t{}\t{}", size, mtime, file)?;
+    }
+    out.flush()
+}
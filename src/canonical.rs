@@ -0,0 +1,45 @@
+use std::io::{self, Write};
+
+/// Digits the size is zero padded to, enough for any size below 8 PiB
+const SIZE_WIDTH: usize = 16;
+
+/// Write one `size path` line per entry for --format canonical, sorted by the line's path. The
+/// size is in bytes, zero padded to 16 digits. Directories end in `/`. Backslashes and control
+/// characters are escaped, so every entry takes exactly one line.
+pub fn write<'a>(
+    out: &mut dyn Write,
+    entries: impl Iterator<Item = (&'a str, u64, bool)>,
+) -> io::Result<()> {
+    let mut lines: Vec<(String, u64)> = entries
+        .map(|(path, size, is_dir)| {
+            let mut path = escape(path);
+            if is_dir && !path.ends_with('/') {
+                path.push('/');
+            }
+            (path, size)
+        })
+        .collect();
+    lines.sort_unstable();
+    let mut out = io::BufWriter::new(out);
+    for (path, size) in lines {
+        writeln!(out, "{:0width$} {}", size, path, width = SIZE_WIDTH)?;
+    }
+    out.flush()
+}
+
+/// `\` as `\\`, newline, tab and carriage return as `\n`, `\t` and `\r`, other control
+/// characters as `\xHH`
+fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
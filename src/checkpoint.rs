@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// First line of every checkpoint file, bumped when the line format changes
+const HEADER: &str = "lff-checkpoint 1";
+
+/// Everything found below one immediate child of a root, once its walk completed
+#[derive(Default)]
+pub struct Subtree {
+    /// Size, mtime and path of every file
+    pub files: Vec<(u64, u64, String)>,
+    /// Own size and path of every directory
+    pub dirs: Vec<(u64, String)>,
+}
+
+/// Read the completed subtrees of a checkpoint written by `Writer`, keyed by the path of the
+/// child. Records after the last `done` line belong to a walk that was interrupted and are
+/// dropped, that child is scanned again.
+pub fn load(path: &Path) -> io::Result<HashMap<String, Subtree>> {
+    let mut lines = BufReader::new(fs::File::open(path)?).lines();
+    match lines.next() {
+        Some(Ok(header)) if header == HEADER => {}
+        Some(Err(e)) => return Err(e),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not an lff checkpoint file")),
+    }
+
+    let mut done = HashMap::new();
+    let mut pending = Subtree::default();
+    for line in lines {
+        let line = line?;
+        let (kind, rest) = line.split_once('\t').unwrap_or((&line, ""));
+        let parsed = match kind {
+            "done" => {
+                done.insert(rest.to_owned(), std::mem::take(&mut pending));
+                Some(())
+            }
+            "f" => {
+                let mut fields = rest.splitn(3, '\t');
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(size), Some(mtime), Some(file)) => size
+                        .parse()
+                        .and_then(|size| mtime.parse().map(|mtime| (size, mtime)))
+                        .ok()
+                        .map(|(size, mtime)| pending.files.push((size, mtime, file.to_owned()))),
+                    _ => None,
+                }
+            }
+            "d" => rest.split_once('\t').and_then(|(own_size, dir)| {
+                own_size
+                    .parse()
+                    .ok()
+                    .map(|own_size| pending.dirs.push((own_size, dir.to_owned())))
+            }),
+            _ => None,
+        };
+        if parsed.is_none() {
+            // The last line may have been cut off by the interruption
+            break;
+        }
+    }
+    Ok(done)
+}
+
+/// Appends every completed subtree to the checkpoint file
+pub struct Writer {
+    out: BufWriter<fs::File>,
+}
+
+impl Writer {
+    /// Start a checkpoint at `path` holding the subtrees that are already done
+    pub fn create(path: &Path, done: &HashMap<String, Subtree>) -> io::Result<Writer> {
+        let mut writer = Writer {
+            out: BufWriter::new(fs::File::create(path)?),
+        };
+        writeln!(writer.out, "{}", HEADER)?;
+        for (child, subtree) in done {
+            writer.add(child, subtree)?;
+        }
+        Ok(writer)
+    }
+
+    /// Record `child` as done. Paths with a newline can not be written to this format, such a
+    /// subtree is left out and simply scanned again on resume.
+    pub fn add(&mut self, child: &str, subtree: &Subtree) -> io::Result<()> {
+        let files = subtree.files.iter().map(|(_, _, file)| file);
+        let dirs = subtree.dirs.iter().map(|(_, dir)| dir);
+        if child.contains('\n') || files.chain(dirs).any(|path| path.contains('\n')) {
+            return Ok(());
+        }
+        for (own_size, dir) in &subtree.dirs {
+            writeln!(self.out, "d\t{}\t{}", own_size, dir)?;
+        }
+        for (size, mtime, file) in &subtree.files {
+            writeln!(self.out, "f\t{}\t{}\t{}", size, mtime, file)?;
+        }
+        writeln!(self.out, "done\t{}", child)?;
+        self.out.flush()
+    }
+}
@@ -0,0 +1,202 @@
+use std::fs::Metadata;
+use std::time::SystemTime;
+
+/// A column of the text listing, picked and ordered with --columns
+#[derive(Clone, Copy, PartialEq)]
+pub enum Column {
+    Size,
+    /// Only the files directly inside a directory
+    SelfSize,
+    Kind,
+    Type,
+    Files,
+    Percent,
+    /// Size as a bar relative to the largest listed entry
+    Bar,
+    Mtime,
+    Perms,
+    Owner,
+    Path,
+}
+
+/// Column names as accepted by --columns
+pub const NAMES: &[(&str, Column)] = &[
+    ("size", Column::Size),
+    ("self", Column::SelfSize),
+    ("kind", Column::Kind),
+    ("type", Column::Type),
+    ("files", Column::Files),
+    ("percent", Column::Percent),
+    ("bar", Column::Bar),
+    ("mtime", Column::Mtime),
+    ("perms", Column::Perms),
+    ("owner", Column::Owner),
+    ("path", Column::Path),
+];
+
+impl Column {
+    /// Parse a comma separated list like `size,path,mtime`
+    pub fn parse_list(s: &str) -> Result<Vec<Column>, String> {
+        s.split(',')
+            .map(|name| {
+                let name = name.trim();
+                NAMES
+                    .iter()
+                    .find(|(known, _)| *known == name)
+                    .map(|(_, column)| *column)
+                    .ok_or_else(|| {
+                        let valid: Vec<&str> = NAMES.iter().map(|(known, _)| *known).collect();
+                        format!("unknown column: {} (valid columns: {})", name, valid.join(", "))
+                    })
+            })
+            .collect()
+    }
+
+    /// The name --columns knows the column by
+    pub fn name(self) -> &'static str {
+        NAMES.iter().find(|(_, column)| *column == self).unwrap().0
+    }
+
+    /// Whether the cell is read from the entry's metadata rather than the scan
+    pub fn needs_metadata(self) -> bool {
+        matches!(self, Column::Mtime | Column::Perms | Column::Owner)
+    }
+
+    /// Cell for a metadata column, `-` when the metadata could not be read. With
+    /// `relative_time` the mtime reads like `3 days ago`.
+    pub fn metadata_cell(self, metadata: Option<&Metadata>, relative_time: bool) -> String {
+        match (self, metadata) {
+            (Column::Mtime, Some(metadata)) if relative_time => match metadata.modified() {
+                Ok(modified) => relative(modified, SystemTime::now()),
+                Err(_) => "-".to_owned(),
+            },
+            (Column::Mtime, Some(metadata)) => mtime(metadata),
+            (Column::Perms, Some(metadata)) => perms(metadata),
+            (Column::Owner, Some(metadata)) => owner(metadata),
+            _ => "-".to_owned(),
+        }
+    }
+}
+
+/// Characters of a full bar of the bar column
+const BAR_WIDTH: usize = 20;
+
+/// How the bar column maps sizes to bar lengths, picked with --bar-scale
+#[derive(Clone, Copy, PartialEq)]
+pub enum BarScale {
+    Linear,
+    /// By the logarithm of the size, so small entries stay visible next to huge ones
+    Log,
+}
+
+/// A bar of `#` for `size` out of `largest`, padded to the full width. Empty for a size of 0 on
+/// either scale.
+pub fn bar(size: u64, largest: u64, scale: BarScale) -> String {
+    let fraction = match scale {
+        _ if size == 0 || largest == 0 => 0.0,
+        BarScale::Linear => size as f64 / largest as f64,
+        // ln(1 + x) keeps a 1 byte entry from getting an empty bar while 0 bytes has one
+        BarScale::Log => (size as f64).ln_1p() / (largest as f64).ln_1p(),
+    };
+    let filled = ((fraction.min(1.0) * BAR_WIDTH as f64).round() as usize).max((size > 0) as usize);
+    format!("[{}{}]", "#".repeat(filled), " ".repeat(BAR_WIDTH - filled))
+}
+
+/// Local modification time to the minute, e.g. `2024-01-31 17:05`
+#[cfg(unix)]
+fn mtime(metadata: &Metadata) -> String {
+    use std::os::unix::fs::MetadataExt;
+
+    let time = metadata.mtime() as libc::time_t;
+    // SAFETY: tm is plain data that localtime_r fills in, both pointers outlive the call
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return "-".to_owned();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+#[cfg(not(unix))]
+fn mtime(_metadata: &Metadata) -> String {
+    "-".to_owned()
+}
+
+/// How long before `now` a file was modified, in the largest whole unit, e.g. `2 months ago`.
+/// Times after `now`, as left behind by clock skew, are `in the future`.
+fn relative(modified: SystemTime, now: SystemTime) -> String {
+    const UNITS: &[(u64, &str)] = &[
+        (365 * 24 * 60 * 60, "year"),
+        (30 * 24 * 60 * 60, "month"),
+        (7 * 24 * 60 * 60, "week"),
+        (24 * 60 * 60, "day"),
+        (60 * 60, "hour"),
+        (60, "minute"),
+    ];
+
+    let age = match now.duration_since(modified) {
+        Ok(age) => age.as_secs(),
+        // Less than a minute ahead is just the two clocks disagreeing a little
+        Err(e) if e.duration().as_secs() < 60 => 0,
+        Err(_) => return "in the future".to_owned(),
+    };
+    for (seconds, unit) in UNITS {
+        let count = age / seconds;
+        if count > 0 {
+            let plural = if count == 1 { "" } else { "s" };
+            return format!("{} {}{} ago", count, unit, plural);
+        }
+    }
+    "just now".to_owned()
+}
+
+/// Permission bits like `ls -l` shows them, e.g. `rwxr-xr-x`
+#[cfg(unix)]
+fn perms(metadata: &Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = metadata.permissions().mode();
+    (0..9)
+        .map(|bit| {
+            if mode & (0o400 >> bit) == 0 {
+                '-'
+            } else {
+                ['r', 'w', 'x'][bit % 3]
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn perms(metadata: &Metadata) -> String {
+    if metadata.permissions().readonly() { "r-" } else { "rw" }.to_owned()
+}
+
+/// The owning user's name, or the numeric uid when it has no passwd entry
+#[cfg(unix)]
+fn owner(metadata: &Metadata) -> String {
+    use std::ffi::CStr;
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = metadata.uid();
+    let mut buf = vec![0 as libc::c_char; 1024];
+    // SAFETY: passwd is plain data that getpwuid_r fills in, its strings point into buf
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() {
+        return uid.to_string();
+    }
+    unsafe { CStr::from_ptr(pwd.pw_name) }.to_string_lossy().into_owned()
+}
+
+#[cfg(not(unix))]
+fn owner(_metadata: &Metadata) -> String {
+    "-".to_owned()
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// First line of every checksum cache file, bumped when the line format changes
+const HEADER: &str = "lff-checksums 1";
+
+const OFFSET_BASIS: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+/// The contents of the file at `path` hashed with 128 bit FNV-1a. Not collision resistant
+/// against files made to collide, but plenty to tell accidental copies apart.
+pub fn hash_file(path: &Path) -> io::Result<u128> {
+    let mut file = fs::File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut hash = OFFSET_BASIS;
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => return Ok(hash),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for byte in &buf[..n] {
+            hash ^= *byte as u128;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+}
+
+/// The hash of a file as long as its size and mtime stay the same
+#[derive(Clone, Copy)]
+pub struct Checksum {
+    pub size: u64,
+    pub mtime: u64,
+    pub hash: u128,
+}
+
+/// Hashes of earlier runs for --checksum-cache, keyed by path
+#[derive(Default)]
+pub struct ChecksumCache {
+    known: HashMap<String, Checksum>,
+    /// Everything looked up or added during this run, what `save` writes
+    used: HashMap<String, Checksum>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl ChecksumCache {
+    /// Read a cache written by `save`, an empty one when `path` does not exist yet
+    pub fn load(path: &Path) -> io::Result<ChecksumCache> {
+        let mut cache = ChecksumCache::default();
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(e),
+        };
+        let mut lines = BufReader::new(file).lines();
+        match lines.next() {
+            Some(Ok(header)) if header == HEADER => {}
+            Some(Err(e)) => return Err(e),
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not an lff checksum cache"))
+            }
+        }
+        for line in lines {
+            let line = line?;
+            let mut fields = line.splitn(4, '\t');
+            let parsed = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(size), Some(mtime), Some(hash), Some(file)) => {
+                    match (size.parse(), mtime.parse(), u128::from_str_radix(hash, 16)) {
+                        (Ok(size), Ok(mtime), Ok(hash)) => {
+                            Some((file, Checksum { size, mtime, hash }))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match parsed {
+                Some((file, checksum)) => {
+                    cache.known.insert(file.to_owned(), checksum);
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("malformed checksum cache line: {}", line),
+                    ))
+                }
+            }
+        }
+        Ok(cache)
+    }
+
+    /// The cached hash of `path`, only while its size and mtime are the ones it was hashed with
+    pub fn get(&mut self, path: &str, size: u64, mtime: u64) -> Option<u128> {
+        match self.known.get(path) {
+            Some(checksum) if checksum.size == size && checksum.mtime == mtime && mtime != 0 => {
+                self.hits += 1;
+                self.used.insert(path.to_owned(), *checksum);
+                Some(checksum.hash)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Remember a freshly computed hash
+    pub fn insert(&mut self, path: &str, checksum: Checksum) {
+        self.used.insert(path.to_owned(), checksum);
+    }
+
+    /// Write one `size<TAB>mtime<TAB>hash<TAB>path` line per file used in this run, files that
+    /// are gone or no longer candidates drop out. Paths with a newline are left out.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        writeln!(out, "{}", HEADER)?;
+        for (file, checksum) in &self.used {
+            if file.contains('\n') {
+                continue;
+            }
+            let Checksum { size, mtime, hash } = checksum;
+            writeln!(out, "{}\t{}\t{:032x}\t{}", size, mtime, hash, file)?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,55 @@
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Glob patterns matched against entries, for --exclude, --exclude-from and --count-only-matches
+pub struct Excludes {
+    /// Patterns without a `/` match the name of an entry at any depth
+    names: GlobSet,
+    /// Patterns with a `/` match the path relative to the scanned directory
+    paths: GlobSet,
+    patterns: Vec<String>,
+}
+
+impl Excludes {
+    pub fn new(patterns: &[String]) -> Result<Excludes, globset::Error> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns {
+            if pattern.contains('/') {
+                let pattern = pattern.trim_start_matches('/');
+                paths.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+            } else {
+                names.add(Glob::new(pattern)?);
+            }
+        }
+        Ok(Excludes {
+            names: names.build()?,
+            paths: paths.build()?,
+            patterns: patterns.to_vec(),
+        })
+    }
+
+    /// The patterns as given
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    pub fn is_match(&self, relative: &Path) -> bool {
+        let name_matches = relative
+            .file_name()
+            .is_some_and(|name| self.names.is_match(name));
+        name_matches || self.paths.is_match(relative)
+    }
+}
+
+/// Read one pattern per line, skipping blank lines and `#` comments
+pub fn read_patterns(path: &Path) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect())
+}
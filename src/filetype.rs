@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Enough to reach the tar magic at offset 257
+const HEADER_LEN: usize = 512;
+
+/// Magic numbers at a fixed offset and the MIME type they identify
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"BZh", "application/x-bzip2"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz"),
+    (0, b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    (257, b"ustar", "application/x-tar"),
+    (0, b"!<arch>\n", "application/x-archive"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (0, b"\xfe\xed\xfa\xcf", "application/x-mach-binary"),
+    (0, b"MZ", "application/vnd.microsoft.portable-executable"),
+    (0, b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (0, b"QFI\xfb", "application/x-qemu-disk"),
+    (0, b"KDMV", "application/x-vmdk"),
+    (0, b"conectix", "application/x-vhd"),
+    (4, b"ftypqt", "video/quicktime"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"\x00\x00\x01\xba", "video/mpeg"),
+];
+
+/// RIFF containers carry their real type at offset 8
+const RIFF_TYPES: &[(&[u8], &str)] = &[
+    (b"AVI ", "video/x-msvideo"),
+    (b"WAVE", "audio/wav"),
+    (b"WEBP", "image/webp"),
+];
+
+/// Extensions of disk and filesystem images and the format they usually hold
+const IMAGE_EXTENSIONS: &[(&str, &str)] = &[
+    ("img", "raw"),
+    ("raw", "raw"),
+    ("iso", "iso"),
+    ("qcow2", "qcow2"),
+    ("vmdk", "vmdk"),
+    ("vhd", "vhd"),
+    ("vhdx", "vhdx"),
+    ("vdi", "vdi"),
+];
+
+/// The format of a disk image, from its extension, with the magic number taking precedence
+/// where it names one, e.g. for a .img that is really a qcow2 image. None for other files.
+pub fn disk_image(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    let (_, format) = IMAGE_EXTENSIONS.iter().find(|(known, _)| *known == ext)?;
+    Some(match detect(path) {
+        "application/x-qemu-disk" => "qcow2",
+        "application/x-vmdk" => "vmdk",
+        "application/x-vhd" => "vhd",
+        _ => format,
+    })
+}
+
+/// Identify a file from its first bytes. Anything unreadable or unrecognized is "unknown".
+pub fn detect(path: &Path) -> &'static str {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    let read = File::open(path).and_then(|file| file.take(HEADER_LEN as u64).read_to_end(&mut header));
+    if read.is_err() {
+        return "unknown";
+    }
+    from_header(&header)
+}
+
+fn from_header(header: &[u8]) -> &'static str {
+    if header.starts_with(b"RIFF") {
+        if let Some(kind) = header.get(8..12) {
+            for (magic, mime) in RIFF_TYPES {
+                if kind == *magic {
+                    return mime;
+                }
+            }
+        }
+    }
+    for (offset, magic, mime) in SIGNATURES {
+        if header.get(*offset..offset + magic.len()) == Some(*magic) {
+            return mime;
+        }
+    }
+    "unknown"
+}
@@ -0,0 +1,35 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Fixed so the same arguments always generate the same tree
+const SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// xorshift64*, plenty for picking file sizes
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+/// Create `files` files of random sizes up to `max_size` below `dir`, spread over a few
+/// levels of directories. The files are sparse where the filesystem supports it, so even
+/// huge trees take next to no disk space. Returns the total size of the files.
+pub fn generate(dir: &Path, files: u64, max_size: u64) -> io::Result<u64> {
+    let mut rng = Rng(SEED);
+    let mut total = 0;
+    for i in 0..files {
+        let parent = dir.join(format!("dir{}", i % 10)).join(format!("sub{}", i / 10 % 10));
+        fs::create_dir_all(&parent)?;
+        let size = rng.next() % max_size.saturating_add(1);
+        let file = fs::File::create(parent.join(format!("file{}.dat", i)))?;
+        file.set_len(size)?;
+        total += size;
+    }
+    Ok(total)
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// Space on the filesystem holding a path, in bytes
+pub struct Usage {
+    pub total: u64,
+    pub used: u64,
+    /// Available to unprivileged users, which can be less than total minus used
+    pub free: u64,
+}
+
+/// Query the filesystem holding `path`
+#[cfg(unix)]
+pub fn usage(path: &Path) -> io::Result<Usage> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: statvfs is plain data filled in by the call, c_path is NUL terminated
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let block = stat.f_frsize as u64;
+    Ok(Usage {
+        total: stat.f_blocks as u64 * block,
+        used: (stat.f_blocks as u64).saturating_sub(stat.f_bfree as u64) * block,
+        free: stat.f_bavail as u64 * block,
+    })
+}
+
+/// Inodes of the filesystem holding a path
+pub struct Inodes {
+    pub total: u64,
+    pub used: u64,
+    /// Available to unprivileged users, which can be less than total minus used
+    pub free: u64,
+}
+
+/// Query the inodes of the filesystem holding `path`. Filesystems that allocate inodes on
+/// demand, like btrfs, report a total of 0.
+#[cfg(unix)]
+pub fn inodes(path: &Path) -> io::Result<Inodes> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: statvfs is plain data filled in by the call, c_path is NUL terminated
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Inodes {
+        total: stat.f_files as u64,
+        used: (stat.f_files as u64).saturating_sub(stat.f_ffree as u64),
+        free: stat.f_favail as u64,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn inodes(_path: &Path) -> io::Result<Inodes> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "only supported on Unix"))
+}
+
+#[cfg(windows)]
+pub fn usage(path: &Path) -> io::Result<Usage> {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory: *const u16,
+            free_to_caller: *mut u64,
+            total: *mut u64,
+            total_free: *mut u64,
+        ) -> i32;
+    }
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let (mut free, mut total, mut total_free) = (0u64, 0u64, 0u64);
+    // SAFETY: wide is NUL terminated and the out pointers are valid for the call
+    if unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut free, &mut total, &mut total_free) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Usage {
+        total,
+        used: total.saturating_sub(total_free),
+        free,
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn usage(_path: &Path) -> io::Result<Usage> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform"))
+}
+
+/// Mount point of every mounted device id, from /proc/mounts. The mount listed last wins where
+/// one is mounted over another. Empty where that is not available.
+#[cfg(target_os = "linux")]
+pub fn mount_points() -> HashMap<u64, String> {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    let mut mounts = HashMap::new();
+    for line in fs::read_to_string("/proc/mounts").unwrap_or_default().lines() {
+        let Some(mount_point) = line.split(' ').nth(1) else {
+            continue;
+        };
+        let mount_point = unescape_mount_point(mount_point);
+        if let Ok(metadata) = fs::metadata(&mount_point) {
+            mounts.insert(metadata.dev(), mount_point);
+        }
+    }
+    mounts
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn mount_points() -> HashMap<u64, String> {
+    HashMap::new()
+}
+
+/// /proc/mounts writes space, tab, newline and backslash as octal escapes like `\040`
+#[cfg(target_os = "linux")]
+fn unescape_mount_point(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 4).filter(|_| bytes[i] == b'\\');
+        match escape.and_then(|octal| u8::from_str_radix(std::str::from_utf8(octal).ok()?, 8).ok()) {
+            Some(byte) => {
+                out.push(byte);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Bytes in use on the filesystem holding `path` when `path` is the top of that
+/// filesystem, None for any other directory or when the usage can not be queried.
+#[cfg(unix)]
+pub fn used_at_mount_point(path: &Path) -> Option<u64> {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    let path = fs::canonicalize(path).ok()?;
+    if let Some(parent) = path.parent() {
+        if fs::metadata(&path).ok()?.dev() == fs::metadata(parent).ok()?.dev() {
+            return None;
+        }
+    }
+    usage(&path).ok().map(|usage| usage.used)
+}
+
+#[cfg(not(unix))]
+pub fn used_at_mount_point(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// _IOR(0x12, 114, size_t) from linux/fs.h, these architectures put the direction bits lower
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc64"
+    )
+))]
+const BLKGETSIZE64: u32 = 0x4008_1272;
+
+#[cfg(all(
+    target_os = "linux",
+    not(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc64"
+    ))
+))]
+const BLKGETSIZE64: u32 = 0x8008_1272;
+
+/// Capacity of the block device at `path` in bytes. Needs read access to the device, which
+/// usually only root has.
+#[cfg(target_os = "linux")]
+pub fn block_device_size(path: &Path) -> io::Result<u64> {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    // Non-blocking so a drive without a medium fails rather than waits
+    let device = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+    let mut size: u64 = 0;
+    // SAFETY: BLKGETSIZE64 writes one u64 to the pointer
+    if unsafe { libc::ioctl(device.as_raw_fd(), BLKGETSIZE64 as libc::Ioctl, &mut size) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn block_device_size(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "only supported on Linux"))
+}
@@ -0,0 +1,133 @@
+use std::io::{self, Write};
+
+/// A minimal JSON value, enough for the reports this tool writes
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Json::Null => write!(out, "null"),
+            Json::Bool(b) => write!(out, "{}", b),
+            Json::Number(n) => write!(out, "{}", n),
+            Json::Float(f) => write!(out, "{}", f),
+            Json::String(s) => write_string(out, s),
+            Json::Array(values) => {
+                write!(out, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(out, ",")?;
+                    }
+                    value.write(out)?;
+                }
+                write!(out, "]")
+            }
+            Json::Object(fields) => {
+                write!(out, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(out, ",")?;
+                    }
+                    write_string(out, key)?;
+                    write!(out, ":")?;
+                    value.write(out)?;
+                }
+                write!(out, "}}")
+            }
+        }
+    }
+
+    /// Like `write`, but with every array element and object field on its own line, indented
+    /// by two spaces per level
+    pub fn write_pretty(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.write_indented(out, 0)
+    }
+
+    fn write_indented(&self, out: &mut dyn Write, level: usize) -> io::Result<()> {
+        let indent = |out: &mut dyn Write, level: usize| write!(out, "{:1$}", "", level * 2);
+        match self {
+            Json::Array(values) if !values.is_empty() => {
+                writeln!(out, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(out, ",")?;
+                    }
+                    indent(out, level + 1)?;
+                    value.write_indented(out, level + 1)?;
+                }
+                writeln!(out)?;
+                indent(out, level)?;
+                write!(out, "]")
+            }
+            Json::Object(fields) if !fields.is_empty() => {
+                writeln!(out, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(out, ",")?;
+                    }
+                    indent(out, level + 1)?;
+                    write_string(out, key)?;
+                    write!(out, ": ")?;
+                    value.write_indented(out, level + 1)?;
+                }
+                writeln!(out)?;
+                indent(out, level)?;
+                write!(out, "}}")
+            }
+            value => value.write(out),
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::String(s.to_owned())
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Json {
+        Json::Number(n)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(f: f64) -> Json {
+        Json::Float(f)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+fn write_string(out: &mut dyn Write, s: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")
+}
@@ -0,0 +1,34 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Writes log records to stderr as `level: message`, keeping stdout for results
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        eprintln!("{}: {}", level, record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+pub fn init(level: LevelFilter) {
+    // Only fails if a logger is already set, which would be a bug
+    log::set_logger(&LOGGER).expect("logger already initialized");
+    log::set_max_level(level);
+}
@@ -1,147 +1,5047 @@
-use clap::{App, Arg};
-use ignore::WalkBuilder;
+mod ads;
+mod cache;
+mod canonical;
+mod checkpoint;
+mod dedupe;
+mod exclude;
+mod columns;
+mod filetype;
+mod fixture;
+mod fsusage;
+mod json;
+mod logger;
+mod output;
+mod pager;
+mod prometheus;
+mod redirect;
+mod scanid;
+mod sftp;
+mod sqlite;
+mod stat;
+mod syslog;
+mod terminal;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use columns::{BarScale, Column};
+use dedupe::{Checksum, ChecksumCache};
+use exclude::Excludes;
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use json::Json;
+use log::LevelFilter;
+use output::{FlushInterval, Output};
+use pager::Pager;
+use redirect::Redirect;
+use scanid::ScanId;
+use stat::Stat;
+use checkpoint::Subtree;
 use rayon::prelude::*;
-use std::borrow::{BorrowMut, Cow};
-use std::cmp::max;
+use std::borrow::Cow;
+use std::cmp::{max, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{self, Path, PathBuf};
+use std::process;
+use std::rc::Rc;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-fn main() {
-    let matches = App::new("Large file finder")
-        .version("0.2.0")
-        .arg(
-            Arg::with_name("directory")
-                .value_name("DIRECTORY")
-                .help("The directory to scan for files and directories")
-                .required(true)
-                .index(1)
+/// Set by the --timeout watcher, walks stop taking entries once it is
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+/// Set once a timed out walk has returned, so the watcher no longer needs to force an exit
+static WOUND_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Cleared by --no-parallel, or when no worker threads could be started
+static PARALLEL: AtomicBool = AtomicBool::new(true);
+
+/// Set when a listed file reaches its --ext-threshold
+static THRESHOLD_EXCEEDED: AtomicBool = AtomicBool::new(false);
+
+/// Entries that could not be read, across all roots, for --strict
+static SKIPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Print names as they are, control characters included, for --raw-names
+static RAW_NAMES: AtomicBool = AtomicBool::new(false);
+
+/// Binary units sizes are shown in, largest last
+const SIZE_UNITS: &[(&str, u64)] = &[
+    ("B", 1),
+    ("KiB", 1024),
+    ("MiB", 1024 * 1024),
+    ("GiB", 1024 * 1024 * 1024),
+    ("TiB", 1024 * 1024 * 1024 * 1024),
+];
+
+/// Decimal units of --size-format si, largest last
+const SI_UNITS: &[(&str, u64)] = &[
+    ("B", 1),
+    ("kB", 1000),
+    ("MB", 1000 * 1000),
+    ("GB", 1000 * 1000 * 1000),
+    ("TB", 1000 * 1000 * 1000 * 1000),
+];
+
+/// Presets of --size-format
+const SIZE_FORMATS: &[&str] = &["binary", "si", "bytes", "du"];
+
+/// How sizes are shown, resolved from --size-format, --unit and --precision
+struct SizeFormat {
+    style: SizeStyle,
+    /// Unit names and factors for SizeStyle::Units, smallest first
+    units: &'static [(&'static str, u64)],
+    /// Index into `units` that every size is shown in, the largest that fits when None
+    unit: Option<usize>,
+    /// Decimals, sizes are truncated to whole units when None
+    precision: Option<usize>,
+}
+
+enum SizeStyle {
+    /// A number and a unit, like `21 GiB`
+    Units,
+    /// The number of bytes with thousands separators, like `22,624,221,184`
+    Bytes,
+    /// The number of 1 KiB blocks, rounded up, like du shows them
+    Blocks,
+}
+
+/// Set once the options are parsed, sizes are shown in binary units until then
+static SIZE_FORMAT: OnceLock<SizeFormat> = OnceLock::new();
+const DEFAULT_SIZE_FORMAT: SizeFormat = SizeFormat {
+    style: SizeStyle::Units,
+    units: SIZE_UNITS,
+    unit: None,
+    precision: None,
+};
+
+/// Exit code with --fail-on-threshold when a file exceeded its extension's threshold
+const THRESHOLD_EXIT_CODE: i32 = 2;
+
+/// Exit code with --verify-total when the tree total does not add up
+const VERIFY_EXIT_CODE: i32 = 3;
+
+/// Exit code with --strict when entries were skipped
+const INCOMPLETE_EXIT_CODE: i32 = 4;
+
+/// How long a timed out walk gets to wind down before the process exits without results,
+/// for calls that are stuck on an unresponsive mount
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+/// Exit code after a timeout, the same as timeout(1) uses
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+fn app() -> App<'static, 'static> {
+    scan_app()
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .setting(AppSettings::DisableHelpSubcommand)
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("List the large files and directories, the same as lff without a subcommand")
+                .args(&scan_args()),
         )
-        .arg(
-            Arg::with_name("percent")
-                .env("PERCENT")
-                .long("percent")
-                .short("p")
-                .takes_value(true)
-                .default_value("50")
-                .help("Show files and dirs larger than this percentage of the largest file")
-                .long_help("Show all files and directories that are larger than X% of the largest found file."),
+        .subcommand(
+            SubCommand::with_name("dedupe")
+                .about("List groups of files with the same contents, like lff --duplicates")
+                .arg(
+                    Arg::with_name("directory")
+                        .value_name("DIRECTORY")
+                        .help("The directories to look for duplicates in")
+                        .default_value(".")
+                        .multiple(true),
+                )
+                .arg(checksum_cache_arg())
+                .args(&walk_args()),
         )
-        .arg(
-            Arg::with_name("ignore")
-                .env("IGNORE")
-                .long("ignore")
-                .short("i")
-                .takes_value(true)
-                .default_value("false")
-                .help("Respect ignore files")
-                .long_help("Respects ignore files when true, counts all files when false (default)."),
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about("List the files that changed in size since an earlier scan")
+                .arg(
+                    Arg::with_name("old")
+                        .value_name("OLD")
+                        .required(true)
+                        .help("A file written by --cache in the earlier scan"),
+                )
+                .arg(
+                    Arg::with_name("directory")
+                        .value_name("DIRECTORY")
+                        .help("The directories to compare, scanned the same way as back then")
+                        .default_value(".")
+                        .multiple(true),
+                )
+                .args(&change_args())
+                .args(&walk_args()),
         )
-        .get_matches();
+}
+
+/// The plain scan, without subcommands
+fn scan_app() -> App<'static, 'static> {
+    App::new("Large file finder")
+        .version("0.2.0")
+        .args(&scan_args())
+}
+
+/// Everything a scan takes, lff without a subcommand and lff scan
+fn scan_args() -> Vec<Arg<'static, 'static>> {
+    let mut args = walk_args();
+    args.extend(vec![
+        Arg::with_name("generate-fixture")
+            .long("generate-fixture")
+            .takes_value(true)
+            .value_name("DIR")
+            .hidden(true)
+            .help("Create a tree of sparse files with random sizes in DIR to try lff on"),
+        Arg::with_name("files")
+            .long("files")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("100")
+            .hidden(true)
+            .help("Number of files --generate-fixture creates"),
+        Arg::with_name("max-size")
+            .long("max-size")
+            .takes_value(true)
+            .value_name("SIZE")
+            .default_value("1G")
+            .hidden(true)
+            .help("Largest file --generate-fixture creates"),
+        Arg::with_name("walker")
+            .long("walker")
+            .takes_value(true)
+            .value_name("WALKER")
+            .possible_values(&["ignore", "std"])
+            .hidden(true)
+            .help("Walk with the ignore crate or plain std::fs::read_dir, to compare the two"),
+        Arg::with_name("directory")
+            .value_name("DIRECTORY")
+            .help("The directories to scan for files and directories")
+            .long_help("The directories to scan for files and directories. Every directory gets its own report unless --merge-roots is given. A directory on another machine can be given as sftp://[user@]host[:port]/path, which is read over SFTP through the ssh command.")
+            .default_value(".")
+            .multiple(true)
+            .index(1),
+        Arg::with_name("merge-roots")
+            .long("merge-roots")
+            .help("Report all directories as one combined tree")
+            .long_help("Report all given directories as one combined tree with a single total. The percentage cutoff is then relative to the largest file across all directories."),
+        Arg::with_name("allow-overlap")
+            .long("allow-overlap")
+            .help("Scan roots that are inside other roots too")
+            .long_help("By default a root inside another given root, like /data/logs next to /data, or a root given twice, is left out with a warning, so no file is counted twice. With this every root is scanned and reported as given."),
+        Arg::with_name("percent")
+            .env("PERCENT")
+            .long("percent")
+            .short("p")
+            .takes_value(true)
+            .default_value("50")
+            .help("Show files and dirs at least this percentage of the largest file")
+            .long_help("Show all files and directories that are at least X% of the largest found file. The cutoff is inclusive, an entry exactly at the cutoff is shown. Use `auto` to pick the cutoff from the distribution of file sizes, see --auto-percentile and --auto-count."),
+        Arg::with_name("percent-of")
+            .long("percent-of")
+            .takes_value(true)
+            .value_name("BASE")
+            .possible_values(&["largest", "free"])
+            .conflicts_with("merge-roots")
+            .help("What --percent is a percentage of, the largest file or the free space")
+            .long_help("What the --percent cutoff is a percentage of. largest, the default, is the largest file found. free is the space left on the filesystem holding the scanned directory, so only files whose deletion makes a real difference are shown. free needs the filesystem to answer a free space query, and a percentage rather than auto."),
+        Arg::with_name("min-size")
+            .long("min-size")
+            .takes_value(true)
+            .value_name("SIZE")
+            .help("Also require files and dirs to be at least SIZE, e.g. 100M")
+            .long_help("An absolute cutoff next to the --percent one, e.g. 100M or 1.5G. By default an entry has to reach both to be shown, see --threshold-mode to show entries that reach either."),
+        Arg::with_name("relative-to-file")
+            .long("relative-to-file")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("min-size")
+            .help("Use the size of FILE as --min-size, see --relative-factor")
+            .long_help("Use the size of FILE, times --relative-factor, as the absolute cutoff of --min-size, to list everything at least as large as a file known to be large, e.g. a video. FILE has to exist, it does not have to be inside the scanned directory."),
+        Arg::with_name("relative-factor")
+            .long("relative-factor")
+            .takes_value(true)
+            .value_name("FACTOR")
+            .requires("relative-to-file")
+            .help("Multiply the size of --relative-to-file by FACTOR, e.g. 2 or 0.5")
+            .long_help("Multiply the size of the --relative-to-file FILE by FACTOR for the cutoff, e.g. 2 for at least twice its size or 0.5 for at least half of it. [default: 1]"),
+        Arg::with_name("threshold-mode")
+            .long("threshold-mode")
+            .takes_value(true)
+            .value_name("MODE")
+            .possible_values(&["and", "or"])
+            .requires("min-size")
+            .help("Show entries reaching both cutoffs (and, the default) or either (or)")
+            .long_help("How --min-size combines with the --percent cutoff. and shows an entry only when it is at least SIZE and at least the percentage, e.g. over 100 MiB and over 10% of the largest file. or shows it when it reaches one of the two."),
+        Arg::with_name("auto-percentile")
+            .long("auto-percentile")
+            .takes_value(true)
+            .default_value("95")
+            .help("Percentile of file sizes used as the cutoff with --percent auto"),
+        Arg::with_name("above-percentile")
+            .long("above-percentile")
+            .takes_value(true)
+            .value_name("P")
+            .conflicts_with("auto-count")
+            .help("Show only files above the Pth percentile of file sizes, e.g. 99 for the top 1%")
+            .long_help("Show only files and directories at least as large as the Pth percentile of the sizes of all scanned files, e.g. 99 for roughly the largest 1% of files. The same as --percent auto --auto-percentile P, and replaces --percent and PERCENT. Unlike --percent the cutoff does not depend on the single largest file, so one huge outlier does not hide everything else."),
+        Arg::with_name("auto-count")
+            .long("auto-count")
+            .takes_value(true)
+            .value_name("N")
+            .help("With --percent auto, pick the cutoff so roughly the N largest files are shown"),
+        Arg::with_name("count-dir-overhead")
+            .long("count-dir-overhead")
+            .alias("dir-size-includes-self")
+            .help("Add the size of every directory itself to its total")
+            .long_help("Add the size of every directory itself, the space its list of entries takes, to its total like du does. Off by default so totals are the apparent size of the file contents."),
+        Arg::with_name("include-ads")
+            .long("include-ads")
+            .help("Add the size of NTFS alternate data streams to files (Windows only)")
+            .long_help("Add the size of the alternate data streams of every file, like the Zone.Identifier of downloads, to its size. A plain length check does not see the space these hidden streams take. --log-level info lists every stream found. Files whose streams can not be read count with their plain size. Only available on Windows."),
+        Arg::with_name("device-sizes")
+            .long("device-sizes")
+            .help("Count block devices with their capacity (Linux only)")
+            .long_help("Count block devices like /dev/sda with the capacity of the device, asked for with the BLKGETSIZE64 ioctl, instead of the 0 bytes their metadata says. Reading the capacity needs read access to the device, usually root, devices that can not be opened count as 0 with a warning. Character devices always count as 0. Only available on Linux."),
+        Arg::with_name("skip-newer-than")
+            .long("skip-newer-than")
+            .takes_value(true)
+            .value_name("DURATION")
+            .help("Leave out files modified within DURATION, e.g. 7d, counted by --time-filter-mode")
+            .long_help("Leave out files modified within DURATION before the scan started, e.g. 30m, 12h, 7d or 2w, as they are likely still in use. Only older candidates for clean up remain. Whether they still count towards the totals is up to --time-filter-mode. Directories are never left out, nor files whose modification time can not be read."),
+        Arg::with_name("time-filter-mode")
+            .long("time-filter-mode")
+            .takes_value(true)
+            .value_name("MODE")
+            .possible_values(&["aggregate", "display"])
+            .requires("skip-newer-than")
+            .help("Whether --skip-newer-than also changes the totals [default: aggregate]")
+            .long_help("With aggregate, the default, files left out by --skip-newer-than are not counted at all, the totals and directory sizes are those of the older files only. With display they are counted as usual and only not listed, so directories keep their real size. --top lists the largest older files either way. [default: aggregate]"),
+        Arg::with_name("count-only-matches")
+            .long("count-only-matches")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("GLOB")
+            .conflicts_with_all(&["top", "by-ext"])
+            .help("Count only files matching GLOB towards the totals, can be given more than once")
+            .long_help("Count only the files matching GLOB towards the totals and the directory sizes, e.g. --count-only-matches '*.mp4' for how much space videos take. Patterns are matched like --exclude ones. The other files in the directories that are listed are still shown for context, marked (not counted), while adding nothing to any size. The JSON and other machine readable formats leave them out. Can be given more than once."),
+        Arg::with_name("include-zero")
+            .long("include-zero")
+            .overrides_with("no-zero")
+            .help("List empty files too")
+            .long_help("List files of zero bytes regardless of the cutoff, e.g. to spot empty leftovers. Without it empty files are never listed, not even with --percent 0."),
+        Arg::with_name("no-zero")
+            .long("no-zero")
+            .overrides_with("include-zero")
+            .help("Do not list empty files, the default"),
+        Arg::with_name("collapse-pattern")
+            .long("collapse-pattern")
+            .conflicts_with_all(&["name-only", "fields-null-separated"])
+            .help("List runs of similarly named files in a directory as one entry")
+            .long_help("List sibling files whose names only differ in numbers or hashes, like chunk-0001.dat to chunk-9999.dat, as one entry chunk-####.dat (9999 files) with their total size. Such a group is listed when its total reaches the cutoff, even when none of its files does. Needs at least 3 files with the same pattern, only the text listing is collapsed."),
+        Arg::with_name("min-files")
+            .long("min-files")
+            .takes_value(true)
+            .value_name("N")
+            .help("Only show directories containing at least N files")
+            .long_help("Only show directories containing at least N files, counted recursively. Useful to find directories bloated by many small files. Files are not affected."),
+        Arg::with_name("block-size")
+            .long("block-size")
+            .takes_value(true)
+            .value_name("SIZE")
+            .help("Round every file up to a multiple of SIZE, e.g. 4K")
+            .long_help("Round every file up to a whole number of SIZE blocks before adding it up, like du --block-size. This models the space small files really occupy, totals are then allocated sizes rather than apparent sizes."),
+        Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["text", "json", "treemap", "prometheus", "sqlite", "canonical"])
+            .default_value("text")
+            .help("Output format of the listing")
+            .long_help("Output format of the listing. json is a flat list of the listed entries, treemap is the whole directory hierarchy as nested {name, size, children} objects for treemap visualizers. prometheus prints the totals of every directory as metrics in the Prometheus text exposition format, for the node_exporter textfile collector. sqlite writes every file and directory into a files table of the SQLite database given with --output, e.g. `lff --format sqlite --output scan.db /srv`. canonical prints the listed entries sorted by path, one `0000000012345678 /path` line each, for snapshots kept in version control whose diffs show only real changes. --top can be written as text, json or canonical. Reports that print a table of their own, like --by-ext, --group-by-dir, --largest-dirs or --duplicates, only as text."),
+        Arg::with_name("json-pretty")
+            .long("json-pretty")
+            .help("Indent the JSON of --format json and treemap")
+            .long_help("Indent the JSON of --format json and treemap, and of --print-config, with every field and array element on its own line, to read it by eye. The default stays a single line, which is smaller and quicker to pipe. Either way the whole report is put together in memory before it is written."),
+        Arg::with_name("errors-json")
+            .long("errors-json")
+            .help("Report skipped entries as JSON lines on stderr")
+            .long_help("Report entries that could not be read as one JSON object per line on stderr, e.g. {\"path\":\"/root\",\"error\":\"Permission denied (os error 13)\",\"kind\":\"permission\"}. kind is one of permission, not-found or io."),
+        Arg::with_name("strict")
+            .long("strict")
+            .help("Exit with code 4 when any entry could not be read")
+            .long_help("Fail when the scan is incomplete: when any file or directory was skipped because it could not be read, e.g. for lack of permission, print how many and exit with code 4 after reporting. Without it skipped entries are only warned about."),
+        Arg::with_name("group-by-dir")
+            .long("group-by-dir")
+            .help("List every directory by size instead of the tree")
+            .long_help("List every directory as a flat table with its total size, its recursive file count and the number of files directly inside it, sorted by size descending, or by file count with --sort count."),
+        Arg::with_name("group-by-depth")
+            .long("group-by-depth")
+            .help("Sum up the files at every depth below the root instead of the tree")
+            .long_help("Print one row per depth below the root with the total size and number of the files at that depth, the files directly inside the root being at depth 1. Shows whether the space sits near the top of the tree or is buried deep down. Depths without files are listed with zeros."),
+        Arg::with_name("duplicates")
+            .long("duplicates")
+            .conflicts_with("top")
+            .help("List groups of files with the same contents instead of the tree")
+            .long_help("List every group of files with the same contents, with how much space all but one copy of them take, the groups that would free the most first. Only files that have the same size as another one are read and hashed, empty files are never listed. Hard links to the same file show up as copies too, check before deleting anything."),
+        checksum_cache_arg().requires("duplicates"),
+        Arg::with_name("sort")
+            .long("sort")
+            .takes_value(true)
+            .value_name("KEY")
+            .possible_values(&["size", "count"])
+            .default_value("size")
+            .help("Rank directories by size or by file count")
+            .long_help("What --group-by-dir, --largest-dirs, --dirs-first and --files-first rank directories by. size is the total size, count the recursive number of files, to find the most crowded directories that slow down backups even when they are small. Ties are broken by the other key."),
+        Arg::with_name("dirs-first")
+            .long("dirs-first")
+            .conflicts_with("files-first")
+            .help("List the subdirectories of every directory before its files, each largest first")
+            .long_help("Inside every directory of the listing, show its subdirectories before its files, like ls --group-directories-first. Each group is ordered largest first, or by file count with --sort count, ties in path order. Without --dirs-first or --files-first everything is listed in path order."),
+        Arg::with_name("files-first")
+            .long("files-first")
+            .help("List the files of every directory before its subdirectories, each largest first")
+            .long_help("Inside every directory of the listing, show its files before its subdirectories. Each group is ordered largest first, or by file count with --sort count, ties in path order."),
+        Arg::with_name("min-parent-percent")
+            .long("min-parent-percent")
+            .takes_value(true)
+            .value_name("P")
+            .help("Hide directories smaller than P percent of their parent")
+            .long_help("Hide directories, and everything below them, that take up less than P percent of their parent directory, so only the branches where space concentrates are shown. Their size still counts towards the parent."),
+        Arg::with_name("largest-dirs")
+            .long("largest-dirs")
+            .takes_value(true)
+            .value_name("N")
+            .conflicts_with("top")
+            .help("List only the N largest directories")
+            .long_help("List the N directories with the largest total size, largest first, with their depth below the scanned directory. Unlike --top this finds the heaviest folders even when no single file in them is large."),
+        Arg::with_name("largest-per-dir")
+            .long("largest-per-dir")
+            .alias("report-largest-per-dir")
+            .conflicts_with_all(&["top", "largest-dirs"])
+            .help("List every directory with its largest file instead of the tree")
+            .long_help("List every directory that holds a file, in path order, each followed by the largest file at any depth below it and the share of the directory that file takes. Shows at a glance what makes each folder big. With --min-size smaller directories are left out."),
+        Arg::with_name("max-name-length")
+            .long("max-name-length")
+            .takes_value(true)
+            .value_name("N")
+            .conflicts_with_all(&["top", "largest-dirs", "min-links"])
+            .help("List only files and dirs whose name is longer than N bytes")
+            .long_help("List every file and directory whose own name, not its whole path, is longer than N bytes, longest first, with its size and name length. Names over 255 bytes can not be stored on most filesystems, and encrypted or FAT filesystems allow fewer, so this finds what a copy or backup would fail on. The length is that of the UTF-8 name."),
+        Arg::with_name("min-links")
+            .long("min-links")
+            .takes_value(true)
+            .value_name("N")
+            .conflicts_with_all(&["top", "largest-dirs"])
+            .help("List only files with at least N hard links (Unix only)")
+            .long_help("List the files that have N or more hard links, most links first, with their link count. A file linked many times is counted once per link in the totals, so this finds what fills package caches or deduplicating backup stores. Link counts are not known on Windows, the option is only available on Unix."),
+        Arg::with_name("by-ext")
+            .long("by-ext")
+            .help("List the total size and file count per file extension")
+            .long_help("List the total size and number of files for every file extension, largest first. Extensions are compared ignoring case, so photo.JPG and photo.jpg count as jpg, unless --case-sensitive-ext is given."),
+        Arg::with_name("top-per-ext")
+            .long("top-per-ext")
+            .takes_value(true)
+            .value_name("N")
+            .conflicts_with_all(&["top", "by-ext", "largest-dirs"])
+            .help("List the N largest files of every file extension")
+            .long_help("List the N largest files of every file extension under a header with the extension, its number of files and its total size. Extensions with the largest total come first, extensions with fewer than N files list all of them. Extensions are compared ignoring case unless --case-sensitive-ext is given."),
+        Arg::with_name("free-space")
+            .long("free-space")
+            .help("Show the size, used and free space of the scanned filesystem")
+            .long_help("After the listing show the total, used and free space of the filesystem holding the directory, in human readable form and in bytes, and how much of the used space deleting the listed files would free."),
+        Arg::with_name("unique-inodes")
+            .long("unique-inodes")
+            .conflicts_with("top")
+            .help("List files with several hard links once, with the number of other links")
+            .long_help("List a file that is reached through several hard links only once, at the first of its paths, followed by how many more of its links were listed, e.g. /srv/a.iso (+2 more links). Only the text listing changes, the totals still count every link. Unix only."),
+        Arg::with_name("inodes")
+            .long("inodes")
+            .conflicts_with_all(&["top", "by-ext", "largest-dirs", "top-per-ext", "group-by-dir"])
+            .help("List directories by file count with the inode usage of the filesystem")
+            .long_help("List every directory by its recursive file count, most files first, with the share of the used inodes of the filesystem its files take, and end with the total, used and free inodes of the filesystem holding the directory. For filesystems that run out of inodes before space, like mail spools or build caches full of tiny files. Filesystems that allocate inodes on demand, like btrfs, report a total of 0. Unix only."),
+        Arg::with_name("image-summary")
+            .long("image-summary")
+            .conflicts_with_all(&["top", "by-ext", "largest-dirs", "top-per-ext", "inodes"])
+            .help("List disk images like .img and .qcow2 files with their format")
+            .long_help("List the disk and filesystem images below the directory, largest first, with their format and size, and end with their number and total size. Images are recognized by their extension, .img, .raw, .iso, .qcow2, .vmdk, .vhd, .vhdx or .vdi, and their first bytes are read to tell qcow2, vmdk and vhd images apart whatever their extension says. Images are reported as single files, what they hold is not scanned."),
+        Arg::with_name("no-cross-device-warning")
+            .long("no-cross-device-warning")
+            .help("Do not show the size per device when the scan spans several")
+            .long_help("When the scanned tree spans more than one device, e.g. because another filesystem is mounted below it, the text listing ends with how much of the total is on each device, named by its mount point where that is known. This leaves that summary out."),
+        Arg::with_name("ext-threshold")
+            .long("ext-threshold")
+            .takes_value(true)
+            .value_name("EXT=SIZE,...")
+            .help("Per extension cutoffs, e.g. mp4=2G,log=100M")
+            .long_help("Per extension cutoffs like mp4=2G,log=100M. Files with one of these extensions are listed only when they reach their own threshold, all other files still use the percentage cutoff."),
+        Arg::with_name("fail-on-threshold")
+            .long("fail-on-threshold")
+            .requires("ext-threshold")
+            .help("Exit with code 2 when a file reaches its --ext-threshold"),
+        Arg::with_name("verify-total")
+            .long("verify-total")
+            .conflicts_with("top")
+            .help("Check that the tree total matches the sum of all scanned sizes")
+            .long_help("After reporting, add up the sizes of all files and directories of the tree in a second, flat pass and compare the sum and the byte count of the walk with the total the tree computed. A mismatch points at a bug in building or sizing the tree, both numbers are printed and the exit code is 3. Meant for debugging and regression tests."),
+        Arg::with_name("case-sensitive-ext")
+            .long("case-sensitive-ext")
+            .help("Treat extensions that differ only in case as different"),
+        Arg::with_name("heavy-path")
+            .long("heavy-path")
+            .help("Follow the largest child from the root down to a file")
+            .long_help("Print only the chain of entries where each one is the largest child of its parent, from the root down to the first file, with sizes and their percentage of the total."),
+        Arg::with_name("reverse-tree")
+            .long("reverse-tree")
+            .conflicts_with_all(&[
+                "top",
+                "heavy-path",
+                "tiered",
+                "name-only",
+                "fields-null-separated",
+            ])
+            .help("List the files largest first, each with the directories it rolls up into")
+            .long_help("List the files above the cutoff largest first instead of the tree, each followed by the chain of its parent directories up to the root with their sizes and the share the file takes up of each. Shows where the biggest things are and how they add up, bottom to top. Text output only."),
+        Arg::with_name("top")
+            .long("top")
+            .takes_value(true)
+            .value_name("N")
+            .help("List only the N largest files")
+            .long_help("List only the N largest files, largest first. Only N files are kept in memory during the walk, so this works on trees of any size. The percentage cutoff is not used."),
+        Arg::with_name("print-config")
+            .long("print-config")
+            .help("Print the resolved options as JSON and exit without scanning")
+            .long_help("Print the options lff would run with as JSON, after defaults, environment variables and the command line are combined, and exit without scanning. For --percent and --ignore it also shows where the value came from, the command line, the environment (PERCENT, IGNORE) or the default."),
+        Arg::with_name("timing")
+            .long("timing")
+            .help("Print how long the scan of every root took to stderr")
+            .long_help("Print how long the scan of every root took to stderr once it is done, counting the walk and building the tree but not the report, e.g. `timing: /srv took 1.52s with the ignore walker`. Pass --no-parallel as well to compare single threaded walks."),
+        Arg::with_name("cache")
+            .long("cache")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("top")
+            .help("Save the size and modification time of every scanned file to FILE"),
+        Arg::with_name("size-only-cache")
+            .long("size-only-cache")
+            .requires("cache")
+            .help("Refresh the --cache file, reusing sizes of files that did not change")
+            .long_help("Load the --cache file before scanning and reuse the cached size of every file whose modification time is unchanged, only files that changed are measured again. The cache is then rewritten with the refreshed tree. How many files were refreshed and reused is printed to stderr."),
+        Arg::with_name("dump-dirs")
+            .long("dump-dirs")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("top")
+            .help("Write the paths of all scanned directories to FILE, largest first")
+            .long_help("After the scan write the path of every scanned directory to FILE, one per line, sorted by total size descending across all roots. Meant for shell helpers and fuzzy finders, e.g. cd \"$(fzf < FILE)\" to jump to one of the biggest directories."),
+        Arg::with_name("checkpoint")
+            .long("checkpoint")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["top", "resume"])
+            .help("Record each finished subtree of the root in FILE, for --resume")
+            .long_help("Walk the immediate children of each root one after the other and append everything found below a child to FILE as soon as its walk is done. When the scan is interrupted, --resume FILE picks up at the first child that was not finished. Meant for scans that take hours, the children are no longer walked in parallel with each other."),
+        Arg::with_name("resume")
+            .long("resume")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("top")
+            .help("Continue an interrupted --checkpoint scan, skipping finished subtrees")
+            .long_help("Load the subtrees that a --checkpoint scan finished from FILE and only walk the children of the root that are not in it. The results are the same as for a full scan, provided nothing changed below the finished subtrees in between. FILE keeps being updated, so a scan can be resumed again after another interruption."),
+        Arg::with_name("detect-type")
+            .long("detect-type")
+            .help("Show the type of listed files detected from their contents")
+            .long_help("Read the first bytes of every listed file and show the type identified by its magic number, e.g. video/mp4, regardless of the file name. Costs an open and a read per listed file, unreadable or unrecognized files show as unknown."),
+        Arg::with_name("columns")
+            .long("columns")
+            .takes_value(true)
+            .value_name("COLUMNS")
+            .conflicts_with("detect-type")
+            .help("Comma separated columns of the text listing, e.g. size,path,mtime,owner")
+            .long_help("Pick and order the columns of the text listing, e.g. size,path,mtime,owner. Valid columns are size, self (size of the files directly inside a directory, without subdirectories), kind (d or f), type (as with --detect-type), files (number of files below), percent (of the total), bar (the size as a bar relative to the largest listed entry, see --bar-scale), mtime, perms, owner and path. The default is size,kind,path."),
+        Arg::with_name("bar-scale")
+            .long("bar-scale")
+            .takes_value(true)
+            .value_name("SCALE")
+            .possible_values(&["linear", "log"])
+            .default_value("linear")
+            .help("Scale of the bar column, linear or log")
+            .long_help("How the bar column of --columns maps sizes to bar lengths. With linear, the default, a bar is as long as the share the entry has of the largest listed one, so small entries next to a huge one get a bar of a single #. With log the bars follow the logarithm of the sizes, which keeps entries of wildly different sizes apart. Empty entries get an empty bar either way."),
+        Arg::with_name("show-self-size")
+            .long("show-self-size")
+            .help("Add a column with the size of the files directly inside each directory")
+            .long_help("Add the self column after the size: the bytes of the files directly inside a directory, without its subdirectories, next to its total size. A directory that is big because of one bloated file of its own then stands apart from one that is big because of deep subtrees. For files both columns are the same. Same as adding self to --columns."),
+        Arg::with_name("tiered")
+            .long("tiered")
+            .help("Group the listed files into size tiers")
+            .long_help("Group the listed files under size tier headers, Huge (>1 GiB), Large (>100 MiB), Medium (>10 MiB), Small (>1 MiB) and Tiny, each sorted by size with a subtotal. Empty tiers are omitted."),
+        Arg::with_name("tiers")
+            .long("tiers")
+            .takes_value(true)
+            .value_name("TIERS")
+            .help("Custom tier boundaries for --tiered, e.g. Big=10G,Mid=1G,500M"),
+        Arg::with_name("oneline")
+            .long("oneline")
+            .help("Print a single summary line instead of the listing")
+            .long_help("Print a single machine readable summary line instead of the listing, e.g. `total=123456789 largest=45678901 files=3421 dirs=102`. Sizes are in bytes and the percentage cutoff is not used."),
+        Arg::with_name("summary-json")
+            .long("summary-json")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Also write the totals of every directory as JSON to FILE")
+            .long_help("Also write the totals of every directory to FILE, one JSON object per line, e.g. {\"root\":\"/data\",\"total_bytes\":123456789,\"files\":3421,\"dirs\":102,\"largest_file_bytes\":45678901}, while the listing goes to stdout in the selected --format as usual. Both come from the same scan, so logs can keep the readable listing and dashboards read the numbers without a second scan."),
+        Arg::with_name("syslog")
+            .long("syslog")
+            .help("Also send the totals to the system log")
+            .long_help("Also send the totals of every directory to the system log, with the host name and directory, e.g. `host=web1 root=/data total=123456789 largest=45678901 files=3421 dirs=102`. With --top every listed file is sent too. Printed to stderr instead where there is no syslog."),
+        Arg::with_name("syslog-facility")
+            .long("syslog-facility")
+            .takes_value(true)
+            .possible_values(syslog::FACILITIES)
+            .default_value("user")
+            .help("The syslog facility used by --syslog"),
+        Arg::with_name("pager")
+            .long("pager")
+            .takes_value(true)
+            .possible_values(&["auto", "always", "never"])
+            .default_value("auto")
+            .help("Show text output in $PAGER, or less")
+            .long_help("Show text output in $PAGER, or less when it is not set. With auto the pager is used when stdout is a terminal, and like git LESS defaults to FRX so output that fits on the screen is just printed. Never used with --format json, treemap, prometheus or sqlite."),
+        Arg::with_name("relative-time")
+            .long("relative-time")
+            .help("Show the mtime column as the age of the entry, e.g. 3 days ago")
+            .long_help("Show the mtime column of --columns as how long ago the entry was modified, in the largest whole unit, e.g. 3 days ago or 2 months ago, instead of a timestamp. Modification times ahead of the clock show as in the future."),
+        Arg::with_name("output")
+            .long("output")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("output-dir")
+            .help("Write the database of --format sqlite to FILE")
+            .long_help("Write the database of --format sqlite to FILE, replacing it when it exists. The files and directories of all roots go into the one database, with --output-dir every root gets its own."),
+        Arg::with_name("output-dir")
+            .long("output-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("Write the report of every root to its own file in DIR")
+            .long_help("Write the report of every root, in the selected --format, to its own file in DIR instead of stdout, e.g. DIR/srv_data.txt for /srv/data. Path separators in the root become _, json and treemap reports end in .json, prometheus ones in .prom and sqlite ones in .db. DIR is created when missing. Only the paths of the written files are printed. Unix only."),
+        Arg::with_name("truncate-paths")
+            .long("truncate-paths")
+            .overrides_with("no-truncate-paths")
+            .help("Shorten long paths in the middle to fit the terminal width, the default on a terminal")
+            .long_help("Shorten paths that would not fit the terminal width by replacing directories in the middle with ..., e.g. /very/long/.../file.bin. The file name and the directories closest to it are kept, and the path column gets all the width the other columns leave. This is the default when stdout is a terminal, with this also when it is not. Only the text listing is shortened, JSON always has the full paths."),
+        Arg::with_name("no-truncate-paths")
+            .long("no-truncate-paths")
+            .overrides_with("truncate-paths")
+            .help("Always print full paths, also on a terminal"),
+        Arg::with_name("flush-interval")
+            .long("flush-interval")
+            .takes_value(true)
+            .value_name("INTERVAL")
+            .help("Flush the listing every N entries, or every 500ms, 2s, ...")
+            .long_help("The listing is written through a buffer that is only flushed once it is complete. With a number the buffer is also flushed after that many entries, with a time like 500ms or 2s whenever that much time passed, so a consumer reading from a pipe sees progress."),
+        Arg::with_name("scan-id")
+            .long("scan-id")
+            .help("Print an ID that is the same for scans with identical results")
+            .long_help("Print a short ID after the totals, derived from the directory, the options that change what is counted and every file path and size found. Two scans with the same ID found exactly the same, which is handy to key caches or skip comparing full outputs."),
+        Arg::with_name("files-only")
+            .long("files-only")
+            .conflicts_with("dirs-only")
+            .help("List only files"),
+        Arg::with_name("dirs-only")
+            .long("dirs-only")
+            .conflicts_with("top")
+            .help("List only directories"),
+        Arg::with_name("no-root")
+            .long("no-root")
+            .help("Leave the line of the scanned directory itself out of the listing")
+            .long_help("Leave the line of the scanned directory itself out of the listing, its size is the total printed below anyway. Everything inside it is listed as usual and the totals are unchanged."),
+        Arg::with_name("normalize-paths")
+            .long("normalize-paths")
+            .help("Show paths with forward slashes, also on Windows")
+            .long_help("Show the paths of the listing and of --format json with forward slashes, also on Windows, so the output of scans on different platforms can be diffed and merged. Only how paths are shown changes. On Unix paths already use forward slashes and a backslash in a file name is kept as it is."),
+        Arg::with_name("raw-names")
+            .long("raw-names")
+            .help("Print file names as they are, control characters included")
+            .long_help("Print file names in the text output exactly as they are. By default control characters in names are written as C-style escapes, e.g. a newline as \\n, a tab as \\t and the escape character as \\x1b, so a crafted file name can neither break the listing into extra lines nor send escape sequences to the terminal. JSON, SQLite and --fields-null-separated output, as well as --name-only with --print0, are always written as they are since they cannot be broken by any name."),
+        Arg::with_name("name-only")
+            .long("name-only")
+            .help("Print only the paths of the listed files, one per line")
+            .long_help("Print only the paths of the listed entries, one per line, without sizes, types or totals. Directories are left out unless --dirs-only is given."),
+        Arg::with_name("print0")
+            .long("print0")
+            .requires("name-only")
+            .help("End every path printed by --name-only with a NUL instead of a newline"),
+        Arg::with_name("fields-null-separated")
+            .long("fields-null-separated")
+            .conflicts_with_all(&["name-only", "columns", "oneline"])
+            .help("Print size, path and type of every entry, each ended by a NUL")
+            .long_help("Print every listed entry as three fields, the size in bytes, the path and the type (d or f), each followed by a NUL, i.e. size\\0path\\0type\\0. No character of a file name can break the records apart. Nothing else is printed, totals included. Read them in a shell with e.g. while IFS= read -r -d '' size && IFS= read -r -d '' path && IFS= read -r -d '' type; do ...; done."),
+        Arg::with_name("compare")
+            .long("compare")
+            .hidden(true)
+            .help("Compare with the --cache file given as the first directory, for lff compare"),
+    ]);
+    args.extend(change_args().into_iter().map(|arg| arg.requires("compare").hidden(true)));
+    args
+}
+
+/// What is walked and how, shared by all subcommands
+fn walk_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("ignore")
+            .env("IGNORE")
+            .long("ignore")
+            .short("i")
+            .takes_value(true)
+            .default_value("false")
+            .help("Respect ignore files")
+            .long_help("Respects ignore files when true, counts all files when false (default)."),
+        Arg::with_name("exclude-hidden")
+            .long("exclude-hidden")
+            .conflicts_with("include-hidden")
+            .help("Skip hidden files and directories")
+            .long_help("Skip hidden files and directories regardless of --ignore. By default hidden entries are counted unless --ignore is true."),
+        Arg::with_name("include-hidden")
+            .long("include-hidden")
+            .help("Count hidden files and directories")
+            .long_help("Count hidden files and directories even when --ignore is true, while still respecting ignore files."),
+        Arg::with_name("resolve-dir-symlinks")
+            .long("resolve-dir-symlinks")
+            .help("Count symlinked directories as the size of their target")
+            .long_help("When a symlink points to a directory, scan the target and attribute its size to the location of the link. This can inflate totals if the target is also scanned directly."),
+        Arg::with_name("exclude")
+            .long("exclude")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("GLOB")
+            .help("Leave out entries matching GLOB, can be given more than once")
+            .long_help("Leave out files and directories matching GLOB, they are neither listed nor counted and excluded directories are not descended into. A pattern without a / matches the name at any depth, e.g. *.tmp or node_modules, one with a / matches the path relative to the scanned directory, e.g. build/cache. Can be given more than once."),
+        Arg::with_name("skip-dir")
+            .long("skip-dir")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("PATH")
+            .help("Leave out the directory PATH and all below it, can be given more than once")
+            .long_help("Leave out the directory at PATH with everything below it, it is not walked and counts nothing towards the totals. Unlike --exclude this names one exact directory, e.g. --skip-dir /data/datasets/imagenet, rather than a pattern that could match elsewhere too. Relative paths are taken from the current directory. Paths are compared as given, without resolving symlinks. Can be given more than once."),
+        Arg::with_name("exclude-vcs")
+            .long("exclude-vcs")
+            .help("Leave out version control directories (.git, .hg, .svn)")
+            .long_help("Leave out the directories version control systems keep their data in, named .git, .hg or .svn at any depth. They are neither listed nor counted and not descended into, also when --ignore is off. Files with those names, like the .git file of a submodule, are still counted."),
+        Arg::with_name("exclude-from")
+            .long("exclude-from")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("FILE")
+            .help("Read --exclude patterns from FILE, one per line")
+            .long_help("Read --exclude patterns from FILE, one per line. Blank lines and lines starting with # are skipped. Can be given more than once and combines with --exclude."),
+        Arg::with_name("no-symlinks")
+            .long("no-symlinks")
+            .conflicts_with_all(&["follow-links", "resolve-dir-symlinks"])
+            .help("Leave symlinks out of the listing and the totals")
+            .long_help("Leave every symlink out of the listing and the totals, so only real data is counted. Without this a symlink that is not followed counts as a small file of the size of the link itself."),
+        Arg::with_name("size-format")
+            .long("size-format")
+            .takes_value(true)
+            .value_name("PRESET")
+            .possible_values(SIZE_FORMATS)
+            .help("How sizes are shown: binary (default), si, bytes or du")
+            .long_help("How sizes are shown. binary, the default, is whole binary units up to GiB like 21 GiB or 512 KiB. si is whole decimal units up to GB like 22 GB or 524 kB, kB being 1000 bytes. bytes is the exact byte count with thousands separators like 22,624,221,184. du is the number of 1 KiB blocks, rounded up, like du -k shows them, e.g. 22094162. --unit and --precision override the preset: --unit always shows binary units, --precision sets the decimals of binary and si sizes."),
+        Arg::with_name("unit")
+            .long("unit")
+            .takes_value(true)
+            .value_name("UNIT")
+            .possible_values(&["B", "KiB", "MiB", "GiB", "TiB"])
+            .case_insensitive(true)
+            .help("Show all sizes in UNIT, with two decimals by default")
+            .long_help("Show every size, of entries and totals alike, in UNIT instead of the largest unit that fits it, so the numbers of a column can be compared directly. Sizes get two decimals unless --precision says otherwise, a 500 byte file shows as 0.00 MiB."),
+        Arg::with_name("precision")
+            .long("precision")
+            .takes_value(true)
+            .value_name("N")
+            .help("Show sizes with N decimals")
+            .long_help("Show sizes with N decimals, rounded, e.g. 1.46 GiB with --precision 2. Without it sizes are whole units, rounded down, or have two decimals with --unit. Sizes in bytes never have decimals."),
+        Arg::with_name("follow-links")
+            .long("follow-links")
+            .short("L")
+            .help("Follow symbolic links while walking")
+            .long_help("Descend into symlinked directories while walking. Symlink cycles and symlink chains longer than --max-symlink-depth are reported and skipped."),
+        Arg::with_name("dereference-root")
+            .long("dereference-root")
+            .help("Scan the target when DIRECTORY is a symlink")
+            .long_help("When DIRECTORY is a symlink, resolve it and scan its target without following any other links. Entries are still reported below DIRECTORY."),
+        Arg::with_name("max-symlink-depth")
+            .long("max-symlink-depth")
+            .takes_value(true)
+            .default_value("40")
+            .help("Longest chain of symlinks to follow with --follow-links"),
+        Arg::with_name("log-level")
+            .long("log-level")
+            .takes_value(true)
+            .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+            .default_value("warn")
+            .help("How much to log to stderr")
+            .long_help("How much to log to stderr. warn reports skipped entries and symlink problems, info adds timing milestones, debug and trace add per entry details."),
+        Arg::with_name("no-parallel")
+            .long("no-parallel")
+            .help("Walk and read files on the main thread only")
+            .long_help("Walk and read files on the main thread only instead of a pool of worker threads. The results are the same, only slower to get. This happens by itself, with a warning, where no worker threads can be started, e.g. in containers with a low thread limit."),
+        Arg::with_name("progress-to")
+            .long("progress-to")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write how far the scan got to FILE every two seconds")
+            .long_help("Write how far the scan got to FILE every two seconds, e.g. `elapsed=12s files=3421 dirs=102 bytes=123456789 (117 MiB)`, counting all directories scanned so far. The last line, written once every directory is scanned, ends in done. For runs under nohup or a job scheduler, where nobody watches a terminal, follow it with tail -F FILE. See --progress-mode for whether lines replace or add to what FILE holds."),
+        Arg::with_name("progress-mode")
+            .long("progress-mode")
+            .takes_value(true)
+            .value_name("MODE")
+            .possible_values(&["overwrite", "append"])
+            .requires("progress-to")
+            .help("Whether --progress-to replaces the file with every line or appends to it")
+            .long_help("With overwrite, the default, every line of --progress-to replaces what the file held, so it always shows the latest state. With append every line is added to the end of it, keeping a history of the run. [default: overwrite]"),
+        Arg::with_name("wait-for-mount")
+            .long("wait-for-mount")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help("Keep checking a missing or unreadable directory for up to SECONDS")
+            .long_help("When a directory to scan is missing or can not be read, check it again with growing pauses for up to SECONDS before giving up, for autofs or network mounts that may not be ready yet when a cron job starts. Without it a missing directory is an error right away. When it is still missing and the closest directory above it that exists is empty, the error says that it looks like a mount point that is not mounted yet. --log-level info shows every retry."),
+        Arg::with_name("timeout")
+            .long("timeout")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help("Stop scanning after SECONDS and report what was found so far")
+            .long_help("Stop scanning after SECONDS and report what was found so far with a warning. The partial totals are lower bounds. The exit code is 124 after a timeout, and if the walk is stuck, e.g. on an unresponsive network mount, the process exits without results shortly after the deadline."),
+    ]
+}
+
+/// --checksum-cache, of lff dedupe and of a scan with --duplicates
+fn checksum_cache_arg() -> Arg<'static, 'static> {
+    Arg::with_name("checksum-cache")
+        .long("checksum-cache")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("Keep the hashes of --duplicates in FILE, rehashing only changed files")
+        .long_help("Load the file hashes of earlier --duplicates runs from FILE and write the ones of this run back. A file whose size and modification time are unchanged is not read again, so repeated runs over mostly static archives only hash what changed. Files that are gone, or no longer share their size with another, drop out of the cache. The number of hits and misses is printed to stderr.")
+}
+
+/// The thresholds of lff compare
+fn change_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("min-change")
+            .long("min-change")
+            .takes_value(true)
+            .value_name("SIZE")
+            .help("Only list files whose size changed by at least SIZE, e.g. 10M")
+            .long_help("Only list the files whose size grew or shrank by at least SIZE, e.g. 512K or 10M, so a log that gained a few bytes does not bury the changes that matter. Files that are new or gone are always listed. Together with --min-percent-change a file has to pass both."),
+        Arg::with_name("min-percent-change")
+            .long("min-percent-change")
+            .takes_value(true)
+            .value_name("P")
+            .help("Only list files whose size changed by at least P percent")
+            .long_help("Only list the files whose size grew or shrank by at least P percent of their earlier size. Files that are new or gone, or were empty before, are always listed. Together with --min-change a file has to pass both."),
+    ]
+}
+
+/// The options of the scan to run. dedupe and compare are scans with --duplicates or --compare,
+/// once their own options are checked they are parsed once more as those of a scan, so all the
+/// options they do not take keep their defaults.
+fn scan_matches() -> ArgMatches<'static> {
+    let matches = app().get_matches();
+    let implied = match matches.subcommand() {
+        ("scan", Some(scan)) => return scan.clone(),
+        ("dedupe", _) => "--duplicates",
+        ("compare", _) => "--compare",
+        _ => return matches,
+    };
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    args[1] = implied.into();
+    scan_app().get_matches_from(args)
+}
+
+fn main() {
+    let matches = scan_matches();
+
+    if let Some(dir) = matches.value_of("generate-fixture") {
+        generate_fixture(Path::new(dir), matches.value_of("files"), matches.value_of("max-size"));
+        return;
+    }
+
+    let above_percentile = matches.value_of("above-percentile").map(|percentile| {
+        if matches.occurrences_of("percent") > 0 {
+            eprintln!("error: --above-percentile replaces --percent, pass only one of them");
+            process::exit(1);
+        }
+        match f64::from_str(percentile) {
+            Ok(percentile) if (0.0..=100.0).contains(&percentile) => percentile,
+            _ => {
+                eprintln!("error: --above-percentile must be between 0 and 100");
+                process::exit(1);
+            }
+        }
+    });
+
+    let percent = match matches.value_of("percent") {
+        _ if above_percentile.is_some() => Percent::Auto {
+            percentile: above_percentile.unwrap_or_default(),
+            count: None,
+        },
+        Some("auto") => {
+            let auto_count = match matches.value_of("auto-count").map(usize::from_str) {
+                None => None,
+                Some(Ok(count)) if count > 0 => Some(count),
+                Some(_) => {
+                    eprintln!("error: --auto-count must be a whole number larger than zero");
+                    process::exit(1);
+                }
+            };
+            match matches.value_of("auto-percentile").map(f64::from_str) {
+                Some(Ok(percentile)) if (0.0..=100.0).contains(&percentile) => Percent::Auto {
+                    percentile,
+                    count: auto_count,
+                },
+                _ => {
+                    eprintln!("error: --auto-percentile must be between 0 and 100");
+                    process::exit(1);
+                }
+            }
+        }
+        Some(percent_str) => match f64::from_str(percent_str) {
+            Ok(pct) => Percent::Fixed(pct),
+            Err(_) => {
+                eprintln!("error: --percent must be a number or auto");
+                process::exit(1);
+            }
+        },
+        None => Percent::Fixed(50.0),
+    };
+    let percent_of_free = matches.value_of("percent-of") == Some("free");
+    if percent_of_free && matches!(percent, Percent::Auto { .. }) {
+        eprintln!("error: --percent-of free needs a percentage, not auto");
+        process::exit(1);
+    }
+
+    let ignore = match matches.value_of("ignore") {
+        Some(ignore) => bool::from_str(ignore).unwrap_or(false),
+        None => false,
+    };
+
+    let skip_newer = matches.value_of("skip-newer-than").map(|window| match parse_duration(window) {
+        Ok(window) => {
+            let aggregate = matches.value_of("time-filter-mode") != Some("display");
+            RecentWindow::ending_now(window, aggregate)
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    });
+
+    let std_walker = matches.value_of("walker") == Some("std");
+    let unsupported = [
+        ("ignore true", ignore),
+        ("exclude-hidden", matches.is_present("exclude-hidden")),
+        ("follow-links", matches.is_present("follow-links")),
+    ];
+    if let Some((option, _)) = unsupported.iter().find(|(_, on)| std_walker && *on) {
+        eprintln!("error: --walker std does not support --{}", option);
+        process::exit(1);
+    }
+
+    let max_symlink_depth = match matches.value_of("max-symlink-depth").map(usize::from_str) {
+        Some(Ok(depth)) => depth,
+        _ => {
+            eprintln!("error: --max-symlink-depth must be a whole number");
+            process::exit(1);
+        }
+    };
+
+    let min_files = match matches.value_of("min-files").map(u64::from_str) {
+        None => 0,
+        Some(Ok(min_files)) => min_files,
+        Some(Err(_)) => {
+            eprintln!("error: --min-files must be a whole number");
+            process::exit(1);
+        }
+    };
+
+    let tiers = if matches.is_present("tiered") || matches.is_present("tiers") {
+        match matches.value_of("tiers").map(parse_tiers) {
+            None => Some(default_tiers()),
+            Some(Ok(tiers)) => Some(tiers),
+            Some(Err(e)) => {
+                eprintln!("error: invalid --tiers: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let block_size = match matches.value_of("block-size").map(parse_size) {
+        None => None,
+        Some(Ok(block_size)) if block_size > 0 => Some(block_size),
+        Some(Ok(_)) => {
+            eprintln!("error: --block-size must be larger than zero");
+            process::exit(1);
+        }
+        Some(Err(e)) => {
+            eprintln!("error: invalid --block-size: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut size_format = match matches.value_of("size-format") {
+        Some("si") => SizeFormat {
+            units: SI_UNITS,
+            ..DEFAULT_SIZE_FORMAT
+        },
+        Some("bytes") => SizeFormat {
+            style: SizeStyle::Bytes,
+            ..DEFAULT_SIZE_FORMAT
+        },
+        Some("du") => SizeFormat {
+            style: SizeStyle::Blocks,
+            ..DEFAULT_SIZE_FORMAT
+        },
+        _ => DEFAULT_SIZE_FORMAT,
+    };
+    if let Some(unit) = matches.value_of("unit") {
+        let index = SIZE_UNITS
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(unit))
+            .unwrap();
+        size_format = SizeFormat {
+            style: SizeStyle::Units,
+            units: SIZE_UNITS,
+            unit: Some(index),
+            precision: Some(2),
+        };
+    }
+    match matches.value_of("precision").map(usize::from_str) {
+        None => {}
+        Some(Ok(precision)) if precision <= 9 => size_format.precision = Some(precision),
+        Some(_) => {
+            eprintln!("error: --precision must be a whole number from 0 to 9");
+            process::exit(1);
+        }
+    }
+    let _ = SIZE_FORMAT.set(size_format);
+    RAW_NAMES.store(matches.is_present("raw-names"), Ordering::Relaxed);
+
+    let top = match matches.value_of("top").map(usize::from_str) {
+        None => None,
+        Some(Ok(top)) if top > 0 => Some(top),
+        Some(_) => {
+            eprintln!("error: --top must be a whole number larger than zero");
+            process::exit(1);
+        }
+    };
+
+    let cache_path = matches.value_of("cache").map(PathBuf::from);
+    let previous = match &cache_path {
+        Some(path) if matches.is_present("size-only-cache") && path.exists() => {
+            match cache::load(path) {
+                Ok(previous) => Some(previous),
+                Err(e) => {
+                    eprintln!("error: cannot read cache {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            }
+        }
+        // Nothing to refresh yet, every file is measured and counted as refreshed
+        Some(_) if matches.is_present("size-only-cache") => Some(HashMap::new()),
+        _ => None,
+    };
+
+    // lff compare passes the --cache file of the earlier scan ahead of the directories
+    let mut directories: Vec<&str> = matches.values_of("directory").into_iter().flatten().collect();
+    let compare = matches.is_present("compare").then(|| {
+        let path = directories.remove(0);
+        match cache::load(Path::new(path)) {
+            Ok(previous) => previous,
+            Err(e) => {
+                eprintln!("error: cannot read cache {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    });
+
+    let min_change = ChangeThreshold {
+        bytes: match matches.value_of("min-change").map(parse_size) {
+            None => 0,
+            Some(Ok(size)) => size,
+            Some(Err(e)) => {
+                eprintln!("error: --min-change: {}", e);
+                process::exit(1);
+            }
+        },
+        percent: match matches.value_of("min-percent-change").map(f64::from_str) {
+            None => 0.0,
+            Some(Ok(percent)) if percent >= 0.0 => percent,
+            Some(_) => {
+                eprintln!("error: --min-percent-change must be a number of at least 0");
+                process::exit(1);
+            }
+        },
+    };
+
+    let checksum_cache_path = matches.value_of("checksum-cache").map(PathBuf::from);
+    let checksum_cache = checksum_cache_path.as_ref().map(|path| match ChecksumCache::load(path) {
+        Ok(cache) => cache,
+        Err(e) => {
+            eprintln!("error: cannot read checksum cache {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    });
+
+    let resume = matches.value_of("resume");
+    let (checkpoint_path, resumed) = match (matches.value_of("checkpoint"), resume) {
+        (Some(path), _) => (Some(Path::new(path)), Some(HashMap::new())),
+        (None, Some(path)) => match checkpoint::load(Path::new(path)) {
+            Ok(done) => (Some(Path::new(path)), Some(done)),
+            Err(e) => {
+                eprintln!("error: cannot read checkpoint {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        (None, None) => (None, None),
+    };
+    // Rewritten up front, which also drops the records of a subtree that was cut off
+    let checkpoint = match (checkpoint_path, &resumed) {
+        (Some(path), Some(done)) => match checkpoint::Writer::create(path, done) {
+            Ok(writer) => Some(Mutex::new(writer)),
+            Err(e) => {
+                eprintln!("error: cannot write checkpoint {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        _ => None,
+    };
+
+    let mut exclude_patterns: Vec<String> = matches
+        .values_of("exclude")
+        .map(|values| values.map(str::to_owned).collect())
+        .unwrap_or_default();
+    for file in matches.values_of("exclude-from").into_iter().flatten() {
+        match exclude::read_patterns(Path::new(file)) {
+            Ok(patterns) => exclude_patterns.extend(patterns),
+            Err(e) => {
+                eprintln!("error: cannot read exclude patterns from {}: {}", file, e);
+                process::exit(1);
+            }
+        }
+    }
+    let exclude = if exclude_patterns.is_empty() {
+        None
+    } else {
+        match Excludes::new(&exclude_patterns) {
+            Ok(excludes) => Some(Arc::new(excludes)),
+            Err(e) => {
+                eprintln!("error: invalid exclude pattern: {}", e);
+                process::exit(1);
+            }
+        }
+    };
+
+    let count_only = matches.values_of("count-only-matches").map(|values| {
+        let patterns: Vec<String> = values.map(str::to_owned).collect();
+        Excludes::new(&patterns).unwrap_or_else(|e| {
+            eprintln!("error: invalid --count-only-matches pattern: {}", e);
+            process::exit(1);
+        })
+    });
+
+    let skip_dirs: Vec<PathBuf> = matches
+        .values_of("skip-dir")
+        .into_iter()
+        .flatten()
+        .map(|dir| {
+            if !Path::new(dir).is_dir() {
+                log::warn!("--skip-dir {} is not a directory", dir);
+            }
+            match path::absolute(dir) {
+                Ok(dir) => dir,
+                Err(e) => {
+                    eprintln!("error: --skip-dir {}: {}", dir, e);
+                    process::exit(1);
+                }
+            }
+        })
+        .collect();
+
+    if matches.is_present("unique-inodes") && cfg!(not(unix)) {
+        eprintln!("error: --unique-inodes is only supported on Unix");
+        process::exit(1);
+    }
+
+    if matches.is_present("inodes") && cfg!(not(unix)) {
+        eprintln!("error: --inodes is only supported on Unix");
+        process::exit(1);
+    }
+
+    if matches.is_present("device-sizes") && cfg!(not(target_os = "linux")) {
+        eprintln!("error: --device-sizes is only supported on Linux");
+        process::exit(1);
+    }
+    if matches.is_present("include-ads") && cfg!(not(windows)) {
+        eprintln!("error: --include-ads is only supported on Windows");
+        process::exit(1);
+    }
+
+    // The other formats are written from the whole tree, which --top does not list
+    let format = matches.value_of("format").unwrap_or("text");
+    if matches.is_present("top") && !matches!(format, "text" | "json" | "canonical") {
+        eprintln!("error: --top can not be written as --format {}", format);
+        process::exit(1);
+    }
+    // Reports that only print a table, a program asking for another format could not parse them
+    let text_only = [
+        "by-ext",
+        "group-by-dir",
+        "largest-dirs",
+        "largest-per-dir",
+        "duplicates",
+        "heavy-path",
+        "image-summary",
+        "inodes",
+        "compare",
+        "group-by-depth",
+        "max-name-length",
+        "min-links",
+        "top-per-ext",
+    ];
+    if let Some(arg) = text_only.iter().find(|arg| matches.is_present(arg)) {
+        if format != "text" {
+            eprintln!("error: --{} can only be written as text, not as --format {}", arg, format);
+            process::exit(1);
+        }
+    }
+    // A database is no stream, it is written to a file of its own
+    if format == "sqlite" && !matches.is_present("output") && !matches.is_present("output-dir") {
+        eprintln!("error: --format sqlite needs --output FILE or --output-dir DIR");
+        process::exit(1);
+    }
+    if matches.is_present("output") && format != "sqlite" {
+        eprintln!("error: --output is only used with --format sqlite");
+        process::exit(1);
+    }
+
+    let mut options = ScanOptions {
+        ignore,
+        resolve_dir_symlinks: matches.is_present("resolve-dir-symlinks"),
+        no_symlinks: matches.is_present("no-symlinks"),
+        count_dir_overhead: matches.is_present("count-dir-overhead"),
+        include_ads: matches.is_present("include-ads"),
+        device_sizes: matches.is_present("device-sizes"),
+        exclude_vcs: matches.is_present("exclude-vcs"),
+        follow_links: matches.is_present("follow-links"),
+        max_symlink_depth,
+        block_size,
+        dereference_root: matches.is_present("dereference-root"),
+        top,
+        // Decided once the report options are known
+        ext_totals: None,
+        exclude_hidden: if matches.is_present("exclude-hidden") {
+            Some(true)
+        } else if matches.is_present("include-hidden") {
+            Some(false)
+        } else {
+            None
+        },
+        previous,
+        exclude,
+        skip_dirs: Arc::new(skip_dirs),
+        count_only,
+        skip_newer,
+        std_walker,
+        timing: matches.is_present("timing"),
+        checkpoint,
+        resumed: resumed.unwrap_or_default(),
+    };
+
+    let log_level = match matches.value_of("log-level").map(LevelFilter::from_str) {
+        Some(Ok(level)) => level,
+        _ => LevelFilter::Warn,
+    };
+    logger::init(log_level);
+    log::debug!("respecting ignore files: {}", options.ignore);
+
+    if matches.is_present("no-parallel") {
+        PARALLEL.store(false, Ordering::Relaxed);
+    } else if let Err(e) = rayon::ThreadPoolBuilder::new().build_global() {
+        // Restricted containers may not allow the threads, the scan then simply takes longer
+        log::warn!("cannot start worker threads, scanning sequentially: {}", e);
+        PARALLEL.store(false, Ordering::Relaxed);
+    }
+
+    let progress_to = matches.value_of("progress-to").map(PathBuf::from);
+    let progress_append = matches.value_of("progress-mode") == Some("append");
+    let progress_started = Instant::now();
+    if let Some(path) = &progress_to {
+        start_progress_writer(path.clone(), progress_append, progress_started);
+    }
+
+    match matches.value_of("timeout").map(u64::from_str) {
+        Some(Ok(seconds)) if seconds > 0 => start_timeout_watcher(Duration::from_secs(seconds)),
+        Some(_) => {
+            eprintln!("error: --timeout must be a whole number of seconds larger than zero");
+            process::exit(1);
+        }
+        None => {}
+    }
+
+    if options.resolve_dir_symlinks {
+        log::warn!("--resolve-dir-symlinks counts symlinked directories at the link location; totals are inflated if a target is also scanned directly");
+    }
+
+    let columns = match matches.value_of("columns").map(Column::parse_list) {
+        Some(Ok(columns)) => columns,
+        Some(Err(e)) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        None if matches.is_present("detect-type") => {
+            vec![Column::Size, Column::Kind, Column::Type, Column::Path]
+        }
+        None => vec![Column::Size, Column::Kind, Column::Path],
+    };
+    let columns = if matches.is_present("show-self-size") && !columns.contains(&Column::SelfSize) {
+        let at = columns.iter().position(|c| *c == Column::Size).map_or(0, |i| i + 1);
+        let mut columns = columns;
+        columns.insert(at, Column::SelfSize);
+        columns
+    } else {
+        columns
+    };
+
+    let case_sensitive_ext = matches.is_present("case-sensitive-ext");
+    let min_size = match matches.value_of("min-size").map(parse_size) {
+        None => None,
+        Some(Ok(size)) => Some(size),
+        Some(Err(e)) => {
+            eprintln!("error: --min-size: {}", e);
+            process::exit(1);
+        }
+    };
+    let min_size = match matches.value_of("relative-to-file") {
+        None => min_size,
+        Some(file) => {
+            let factor = match matches.value_of("relative-factor").map(f64::from_str) {
+                None => 1.0,
+                Some(Ok(factor)) if factor.is_finite() && factor > 0.0 => factor,
+                Some(_) => {
+                    eprintln!("error: --relative-factor must be a number larger than zero");
+                    process::exit(1);
+                }
+            };
+            match fs::metadata(file) {
+                Ok(metadata) => {
+                    let size = (metadata.len() as f64 * factor) as u64;
+                    log::info!("cutoff: {} from {}", bytes_to_nice(size), file);
+                    Some(size)
+                }
+                Err(e) => {
+                    eprintln!("error: --relative-to-file {}: {}", file, e);
+                    process::exit(1);
+                }
+            }
+        }
+    };
+
+    let ext_thresholds = match matches.value_of("ext-threshold") {
+        Some(s) => match parse_ext_thresholds(s, case_sensitive_ext) {
+            Ok(thresholds) => thresholds,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        },
+        None => HashMap::new(),
+    };
+
+    let min_parent_percent = match matches.value_of("min-parent-percent").map(f64::from_str) {
+        None => None,
+        Some(Ok(percent)) if (0.0..=100.0).contains(&percent) => Some(percent),
+        Some(_) => {
+            eprintln!("error: --min-parent-percent must be a number between 0 and 100");
+            process::exit(1);
+        }
+    };
+
+    let largest_dirs = match matches.value_of("largest-dirs").map(usize::from_str) {
+        None => None,
+        Some(Ok(n)) if n > 0 => Some(n),
+        Some(_) => {
+            eprintln!("error: --largest-dirs must be a whole number larger than zero");
+            process::exit(1);
+        }
+    };
+
+    let min_links = match matches.value_of("min-links").map(u64::from_str) {
+        None => None,
+        Some(_) if cfg!(not(unix)) => {
+            eprintln!("error: --min-links is only supported on Unix");
+            process::exit(1);
+        }
+        Some(Ok(n)) if n > 1 => Some(n),
+        Some(_) => {
+            eprintln!("error: --min-links must be a whole number larger than one");
+            process::exit(1);
+        }
+    };
+
+    let max_name_length = match matches.value_of("max-name-length").map(usize::from_str) {
+        None => None,
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => {
+            eprintln!("error: --max-name-length must be a whole number");
+            process::exit(1);
+        }
+    };
+
+    let top_per_ext = match matches.value_of("top-per-ext").map(usize::from_str) {
+        None => None,
+        Some(Ok(n)) if n > 0 => Some(n),
+        Some(_) => {
+            eprintln!("error: --top-per-ext must be a whole number larger than zero");
+            process::exit(1);
+        }
+    };
+
+    let writes_json = matches!(matches.value_of("format"), Some("json" | "treemap"));
+    if matches.is_present("json-pretty") && !writes_json && !matches.is_present("print-config") {
+        eprintln!("error: --json-pretty needs --format json or treemap");
+        process::exit(1);
+    }
+
+    let flush_interval = match matches.value_of("flush-interval").map(FlushInterval::from_str) {
+        Some(Ok(interval)) => interval,
+        Some(Err(e)) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        None => FlushInterval::End,
+    };
+
+    let report_options = ReportOptions {
+        percent,
+        percent_of_free,
+        min_size,
+        either_threshold: matches.value_of("threshold-mode") == Some("or"),
+        min_files,
+        tiers,
+        columns,
+        format: matches.value_of("format").unwrap_or("text").to_owned(),
+        json_pretty: matches.is_present("json-pretty"),
+        oneline: matches.is_present("oneline"),
+        name_only: matches.is_present("name-only"),
+        print0: matches.is_present("print0"),
+        fields0: matches.is_present("fields-null-separated"),
+        flush_interval,
+        // Output that is not read on the terminal keeps full paths, as do report files
+        truncate_paths: matches.is_present("truncate-paths")
+            || (!matches.is_present("no-truncate-paths")
+                && !matches.is_present("output-dir")
+                && terminal::stdout_is_tty()),
+        relative_time: matches.is_present("relative-time"),
+        scan_id: matches.is_present("scan-id").then(|| options.id_key()),
+        kinds: if matches.is_present("dirs-only") {
+            Kinds::Dirs
+        } else if matches.is_present("files-only") || matches.is_present("name-only") {
+            Kinds::Files
+        } else {
+            Kinds::All
+        },
+        heavy_path: matches.is_present("heavy-path"),
+        group_by_dir: matches.is_present("group-by-dir"),
+        group_by_depth: matches.is_present("group-by-depth"),
+        duplicates: matches.is_present("duplicates"),
+        checksum_cache: checksum_cache.map(Mutex::new),
+        compare,
+        min_change,
+        sort_by_count: matches.value_of("sort") == Some("count"),
+        count_only_matches: matches.is_present("count-only-matches"),
+        child_order: if matches.is_present("dirs-first") {
+            ChildOrder::DirsFirst
+        } else if matches.is_present("files-first") {
+            ChildOrder::FilesFirst
+        } else {
+            ChildOrder::Path
+        },
+        by_ext: matches.is_present("by-ext"),
+        top_per_ext,
+        largest_dirs,
+        largest_per_dir: matches.is_present("largest-per-dir"),
+        min_links,
+        max_name_length,
+        min_parent_percent,
+        include_zero: matches.is_present("include-zero"),
+        hide_newer: skip_newer.filter(|window| !window.aggregate).map(|window| window.since),
+        collapse_pattern: matches.is_present("collapse-pattern"),
+        reverse_tree: matches.is_present("reverse-tree"),
+        device_summary: !matches.is_present("no-cross-device-warning"),
+        free_space: matches.is_present("free-space"),
+        inodes: matches.is_present("inodes"),
+        no_root: matches.is_present("no-root"),
+        image_summary: matches.is_present("image-summary"),
+        normalize_paths: matches.is_present("normalize-paths"),
+        unique_inodes: matches.is_present("unique-inodes"),
+        bar_scale: if matches.value_of("bar-scale") == Some("log") {
+            BarScale::Log
+        } else {
+            BarScale::Linear
+        },
+        case_sensitive_ext,
+        ext_thresholds,
+        errors_json: matches.is_present("errors-json"),
+        block_size: options.block_size,
+    };
+
+    let wait_for_mount = match matches.value_of("wait-for-mount").map(u64::from_str) {
+        None => None,
+        Some(Ok(seconds)) if seconds > 0 => Some(Duration::from_secs(seconds)),
+        Some(_) => {
+            eprintln!("error: --wait-for-mount must be a whole number of seconds larger than zero");
+            process::exit(1);
+        }
+    };
+    let roots: Vec<String> = if directories.is_empty() {
+        vec![expand_root(".", wait_for_mount)]
+    } else {
+        directories.into_iter().map(|root| expand_root(root, wait_for_mount)).collect()
+    };
+    let roots = if matches.is_present("allow-overlap") {
+        roots
+    } else {
+        drop_nested_roots(roots)
+    };
+
+    if matches.is_present("print-config") {
+        let config = resolved_config(&matches, &roots, &options, &report_options);
+        write_json(&config, &report_options);
+        return;
+    }
+
+    let output_dir = matches.value_of("output-dir").map(PathBuf::from);
+    if let Some(dir) = &output_dir {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("error: cannot create {}: {}", dir.display(), e);
+            process::exit(1);
+        }
+    }
+    // Report files that were written, so two roots never end up in the same file
+    let mut report_files = HashSet::new();
+
+    let use_pager = match matches.value_of("pager") {
+        Some("always") => true,
+        Some("never") => false,
+        _ => terminal::stdout_is_tty(),
+    };
+    let pager = if use_pager && report_options.format == "text" && output_dir.is_none() {
+        Pager::start()
+    } else {
+        None
+    };
+
+    // Files to write back to the cache, gathered before each tree is reported
+    let mut cached_files: Vec<File> = Vec::new();
+    let dump_dirs = matches.value_of("dump-dirs").map(PathBuf::from);
+    let mut dumped_dirs: Vec<(u64, String)> = Vec::new();
+    // With --format prometheus every metric lists all roots, so they are printed at the end
+    let prometheus = report_options.format == "prometheus";
+    let syslog_facility = matches
+        .is_present("syslog")
+        .then(|| matches.value_of("syslog-facility").unwrap_or("user"));
+    let mut metrics = Vec::new();
+    // Likewise the rows of --format sqlite, which go into one database
+    let sqlite = report_options.format == "sqlite";
+    let mut rows = Vec::new();
+    let summary_json = matches.value_of("summary-json").map(PathBuf::from);
+    let mut summaries = Vec::new();
+    let verify = matches.is_present("verify-total");
+    let mut verified = true;
+
+    // --by-ext on its own needs no tree, the totals can be summed up during the walk
+    let tree_needed = options.top.is_some()
+        || options.checkpoint.is_some()
+        || !options.resumed.is_empty()
+        || cache_path.is_some()
+        || dump_dirs.is_some()
+        || syslog_facility.is_some()
+        || summary_json.is_some()
+        || verify;
+    if report_options.only_ext_totals() && !tree_needed {
+        options.ext_totals = Some(report_options.case_sensitive_ext);
+    }
+    if matches.is_present("merge-roots") {
+        let stats = ScanStats::default();
+        let mut merged = Dir::new(&roots.join(" + "));
+        let mut skipped = Vec::new();
+        let mut top: Option<Vec<File>> = None;
+        let mut ext_totals: Option<ExtTotals> = None;
+        let mut devices = HashMap::new();
+        for root in roots.iter() {
+            let mut dir = Dir::new(root);
+            let output = scan(&mut dir, &options, &stats);
+            skipped.extend(output.skipped);
+            add_device_bytes(&mut devices, output.devices);
+            if let Some(files) = output.top {
+                top.get_or_insert_with(Vec::new).extend(files);
+            }
+            if let Some(totals) = output.ext_totals {
+                match ext_totals.as_mut() {
+                    Some(merged) => merged.merge(totals),
+                    None => ext_totals = Some(totals),
+                }
+            }
+            if cache_path.is_some() {
+                cached_files.extend(dir.all_files().into_iter().cloned());
+            }
+            if dump_dirs.is_some() {
+                dumped_dirs.extend(dir_sizes(&mut dir));
+            }
+            merged.children.push(FsItem::Dir(dir));
+        }
+        if let (Some(files), Some(n)) = (top.as_mut(), options.top) {
+            files.sort_by_key(|file| Reverse(file.size));
+            files.truncate(n);
+        }
+        let output = ScanOutput {
+            skipped,
+            top,
+            ext_totals,
+            devices,
+        };
+        if let Some(facility) = syslog_facility {
+            let summary = summarize(&mut merged, &output, &stats);
+            send_to_syslog(&summary, output.top.as_deref(), facility);
+        }
+        if summary_json.is_some() {
+            summaries.push(summarize(&mut merged, &output, &stats));
+        }
+        let report_file = output_dir.as_ref().map(|dir| {
+            let stems: Vec<String> = roots.iter().map(|root| redirect::file_stem(root)).collect();
+            open_report_file(dir, &stems.join("+"), &report_options.format, &mut report_files)
+        });
+        if prometheus {
+            metrics.push(root_metrics(&mut merged, output, &stats, &report_options));
+        } else if sqlite {
+            report_skipped(&output.skipped, report_options.errors_json);
+            merged.calc_size();
+            merged.sqlite_rows(&Rc::from(merged.path.as_str()), &mut rows);
+        } else {
+            report(&mut merged, output, &stats, &report_options);
+        }
+        if let Some(report_file) = report_file {
+            finish_report_file(report_file, &mut metrics, &mut rows);
+        }
+        if verify {
+            verified &= verify_total(&mut merged, &stats);
+        }
+        if options.previous.is_some() {
+            print_cache_stats(&stats);
+        }
+    } else {
+        for (i, root) in roots.iter().enumerate() {
+            // Only separate listings meant for reading, not line per record output
+            let readable = report_options.format == "text"
+                && !report_options.oneline
+                && !report_options.name_only
+                && !report_options.fields0;
+            if i > 0 && readable && output_dir.is_none() {
+                println!();
+            }
+            let stats = ScanStats::default();
+            let mut dir = Dir::new(root);
+            let output = scan(&mut dir, &options, &stats);
+            if cache_path.is_some() {
+                cached_files.extend(dir.all_files().into_iter().cloned());
+            }
+            if dump_dirs.is_some() {
+                dumped_dirs.extend(dir_sizes(&mut dir));
+            }
+            if let Some(facility) = syslog_facility {
+                let summary = summarize(&mut dir, &output, &stats);
+                send_to_syslog(&summary, output.top.as_deref(), facility);
+            }
+            if summary_json.is_some() {
+                summaries.push(summarize(&mut dir, &output, &stats));
+            }
+            let report_file = output_dir.as_ref().map(|dir| {
+                let stem = redirect::file_stem(root);
+                open_report_file(dir, &stem, &report_options.format, &mut report_files)
+            });
+            if prometheus {
+                metrics.push(root_metrics(&mut dir, output, &stats, &report_options));
+            } else if sqlite {
+                report_skipped(&output.skipped, report_options.errors_json);
+                dir.calc_size();
+                dir.sqlite_rows(&Rc::from(root.as_str()), &mut rows);
+            } else {
+                report(&mut dir, output, &stats, &report_options);
+            }
+            if let Some(report_file) = report_file {
+                finish_report_file(report_file, &mut metrics, &mut rows);
+            }
+            if verify {
+                verified &= verify_total(&mut dir, &stats);
+            }
+            if options.previous.is_some() {
+                print_cache_stats(&stats);
+            }
+        }
+    }
+
+    if prometheus && output_dir.is_none() {
+        let mut out = io::stdout().lock();
+        if let Err(e) = prometheus::write(&mut out, &metrics) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(path) = matches.value_of("output").map(Path::new) {
+        if let Err(e) = write_database(path, &rows) {
+            eprintln!("error: cannot write {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(path) = summary_json {
+        if let Err(e) = write_summaries(&path, &summaries) {
+            eprintln!("error: cannot write {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(path) = &progress_to {
+        let mut finished = PROGRESS_FINISHED.lock().unwrap();
+        if let Err(e) = write_progress(path, progress_append, progress_started, true) {
+            log::warn!("cannot write progress to {}: {}", path.display(), e);
+        }
+        *finished = true;
+    }
+
+    if let Some(pager) = pager {
+        pager.finish();
+    }
+
+    if TIMED_OUT.load(Ordering::Relaxed) {
+        // A partial tree would make the next refresh miss files, keep the old cache
+        process::exit(TIMEOUT_EXIT_CODE);
+    }
+
+    if let Some(path) = cache_path {
+        let files = cached_files.iter().map(|file| (file.path.as_str(), file.size, file.mtime));
+        if let Err(e) = cache::save(&path, files) {
+            eprintln!("error: cannot write cache {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+
+    if let (Some(path), Some(cache)) = (checksum_cache_path, &report_options.checksum_cache) {
+        let cache = cache.lock().unwrap();
+        eprintln!("checksum cache: {} hits, {} misses", cache.hits, cache.misses);
+        if let Err(e) = cache.save(&path) {
+            eprintln!("error: cannot write checksum cache {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(path) = dump_dirs {
+        dumped_dirs.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        if let Err(e) = write_dir_list(&path, &dumped_dirs) {
+            eprintln!("error: cannot write {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+
+    let skipped = SKIPPED.load(Ordering::Relaxed);
+    if matches.is_present("strict") && skipped > 0 {
+        let entries = if skipped == 1 { "entry" } else { "entries" };
+        eprintln!("error: scan incomplete, {} {} could not be read", skipped, entries);
+        process::exit(INCOMPLETE_EXIT_CODE);
+    }
+
+    if !verified {
+        process::exit(VERIFY_EXIT_CODE);
+    }
+
+    if matches.is_present("fail-on-threshold") && THRESHOLD_EXCEEDED.load(Ordering::Relaxed) {
+        process::exit(THRESHOLD_EXIT_CODE);
+    }
+}
+
+/// Compare the total of `base_dir` with a flat sum over the tree and with what the walk counted,
+/// for --verify-total
+fn verify_total(base_dir: &mut Dir, stats: &ScanStats) -> bool {
+    base_dir.calc_size();
+    let tree = base_dir.size();
+    let files: u64 = base_dir.all_files().iter().map(|file| file.size).sum();
+    let dirs: u64 = base_dir.all_dirs().iter().map(|dir| dir.own_size).sum();
+    let walked = stats.bytes.load(Ordering::Relaxed);
+    if files + dirs == tree && walked == tree {
+        log::info!("verified total of {}: {} bytes", base_dir.path, tree);
+        return true;
+    }
+    eprintln!(
+        "error: total of {} does not add up: tree {} bytes, sum of entries {} bytes, walk {} bytes",
+        base_dir.path,
+        tree,
+        files + dirs,
+        walked
+    );
+    false
+}
+
+/// Point stdout at the file for `stem` in --output-dir, numbering names that are taken
+fn open_report_file(
+    dir: &Path,
+    stem: &str,
+    format: &str,
+    taken: &mut HashSet<PathBuf>,
+) -> (Redirect, PathBuf) {
+    let extension = match format {
+        "text" => "txt",
+        "prometheus" => "prom",
+        "sqlite" => "db",
+        "canonical" => "txt",
+        _ => "json",
+    };
+    let mut path = dir.join(format!("{}.{}", stem, extension));
+    let mut n = 2;
+    while !taken.insert(path.clone()) {
+        path = dir.join(format!("{}-{}.{}", stem, n, extension));
+        n += 1;
+    }
+    match Redirect::to_file(&path) {
+        Ok(redirect) => (redirect, path),
+        Err(e) => {
+            eprintln!("error: cannot write report {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Complete a report file, writing out the metrics or the database of its root when there are
+/// any. The database is written to the path, it needs to seek back to its first page.
+fn finish_report_file(
+    (redirect, path): (Redirect, PathBuf),
+    metrics: &mut Vec<prometheus::RootMetrics>,
+    rows: &mut Vec<sqlite::Row>,
+) {
+    let mut written = Ok(());
+    if !metrics.is_empty() {
+        written = prometheus::write(&mut io::stdout().lock(), metrics);
+    }
+    if !rows.is_empty() {
+        written = write_database(&path, rows);
+    }
+    if let Err(e) = written.and_then(|_| redirect.restore()) {
+        eprintln!("error: cannot write report {}: {}", path.display(), e);
+        process::exit(1);
+    }
+    metrics.clear();
+    rows.clear();
+    println!("Wrote {}", path.display());
+}
+
+/// Write `rows` to a new SQLite database at `path`
+fn write_database(path: &Path, rows: &[sqlite::Row]) -> io::Result<()> {
+    let file = fs::File::create(path)?;
+    sqlite::write(io::BufWriter::new(file), rows)
+}
+
+/// Set once the last line of --progress-to is written
+static PROGRESS_FINISHED: Mutex<bool> = Mutex::new(false);
+
+/// Pause between two lines of --progress-to
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Write how far the scan got to `path` every PROGRESS_INTERVAL, replacing what the file held
+/// unless `append`, for runs without a terminal to watch
+fn start_progress_writer(path: PathBuf, append: bool, started: Instant) {
+    thread::spawn(move || loop {
+        thread::sleep(PROGRESS_INTERVAL);
+        // Held while writing, so no line can follow the last one
+        let finished = PROGRESS_FINISHED.lock().unwrap();
+        if *finished {
+            return;
+        }
+        if let Err(e) = write_progress(&path, append, started, false) {
+            log::warn!("cannot write progress to {}: {}", path.display(), e);
+            return;
+        }
+    });
+}
+
+/// One line of --progress-to, e.g. `elapsed=12s files=3421 dirs=102 bytes=123456789 (117 MiB)`,
+/// with ` done` at the end of the last one
+fn write_progress(path: &Path, append: bool, started: Instant, done: bool) -> io::Result<()> {
+    let bytes = PROGRESS.bytes.load(Ordering::Relaxed);
+    let line = format!(
+        "elapsed={}s files={} dirs={} bytes={} ({}){}\n",
+        started.elapsed().as_secs(),
+        PROGRESS.files.load(Ordering::Relaxed),
+        PROGRESS.dirs.load(Ordering::Relaxed),
+        bytes,
+        bytes_to_nice(bytes),
+        if done { " done" } else { "" }
+    );
+    if append {
+        fs::OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())
+    } else {
+        fs::write(path, line)
+    }
+}
+
+/// Stop the walks once `timeout` has passed. Should a walk not wind down, e.g. because a stat
+/// hangs, exit after a grace period since no partial results are coming either.
+fn start_timeout_watcher(timeout: Duration) {
+    thread::spawn(move || {
+        thread::sleep(timeout);
+        TIMED_OUT.store(true, Ordering::Relaxed);
+        thread::sleep(TIMEOUT_GRACE);
+        if !WOUND_DOWN.load(Ordering::Relaxed) {
+            log::error!("scan did not stop within {:?} of the timeout, giving up", TIMEOUT_GRACE);
+            process::exit(TIMEOUT_EXIT_CODE);
+        }
+    });
+}
+
+/// How much of a --size-only-cache refresh came from the cache, on stderr to keep stdout clean
+fn print_cache_stats(stats: &ScanStats) {
+    eprintln!(
+        "cache: {} refreshed, {} reused",
+        stats.refreshed.load(Ordering::Relaxed),
+        stats.reused.load(Ordering::Relaxed)
+    );
+}
+
+/// Handle --generate-fixture, refusing to add files to a directory that has some already
+fn generate_fixture(dir: &Path, files: Option<&str>, max_size: Option<&str>) {
+    let files = match files.map(u64::from_str) {
+        Some(Ok(files)) => files,
+        _ => {
+            eprintln!("error: --files must be a whole number");
+            process::exit(1);
+        }
+    };
+    let max_size = match max_size.map(parse_size) {
+        Some(Ok(max_size)) => max_size,
+        Some(Err(e)) => {
+            eprintln!("error: --max-size: {}", e);
+            process::exit(1);
+        }
+        None => unreachable!("--max-size has a default"),
+    };
+    if fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        eprintln!("error: {} is not empty", dir.display());
+        process::exit(1);
+    }
+    match fixture::generate(dir, files, max_size) {
+        Ok(total) => println!(
+            "Created {} files in {}, total size: {} ({} bytes)",
+            files,
+            dir.display(),
+            bytes_to_nice(total),
+            total
+        ),
+        Err(e) => {
+            eprintln!("error: cannot generate fixture in {}: {}", dir.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Expand `~` and environment variables in a root and make sure it can be scanned, retrying for
+/// up to `wait` while it can not
+fn expand_root(path_str: &str, wait: Option<Duration>) -> String {
+    let expanded_path_str = match shellexpand::full(path_str) {
+        Ok(eps) => eps,
+        Err(e) => {
+            eprintln!("error: cannot expand {}: {}", path_str, e);
+            process::exit(1);
+        }
+    };
+
+    let checked = match wait {
+        Some(wait) => wait_for_root(&expanded_path_str, wait),
+        None => check_root(&expanded_path_str),
+    };
+    if let Err(e) = checked {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+    expanded_path_str.into_owned()
+}
+
+/// First pause between two checks of --wait-for-mount, doubled after every failed one
+const MOUNT_RETRY_FIRST: Duration = Duration::from_millis(250);
+
+/// Longest pause between two checks of --wait-for-mount
+const MOUNT_RETRY_MAX: Duration = Duration::from_secs(5);
+
+/// Check `path` until it can be scanned or `wait` is over, for mounts that are not ready yet
+/// when the scan starts, like autofs or network mounts at boot
+fn wait_for_root(path: &str, wait: Duration) -> Result<(), String> {
+    let deadline = Instant::now() + wait;
+    let mut pause = MOUNT_RETRY_FIRST;
+    loop {
+        let e = match check_root(path) {
+            Ok(()) => return Ok(()),
+            // Waiting does not make a remote URL or a file scannable
+            Err(e) if path.contains("://") || Path::new(path).is_file() => return Err(e),
+            Err(e) => e,
+        };
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(match unmounted_ancestor(Path::new(path)) {
+                Some(ancestor) => format!(
+                    "{}, still not mounted after {}s ({} is an empty directory)",
+                    e,
+                    wait.as_secs(),
+                    ancestor.display()
+                ),
+                None => format!("{}, after waiting {}s", e, wait.as_secs()),
+            });
+        }
+        let sleep = pause.min(deadline - now);
+        log::info!("{}, checking again in {:.2?}", e, sleep);
+        thread::sleep(sleep);
+        pause = (pause * 2).min(MOUNT_RETRY_MAX);
+    }
+}
+
+/// The closest existing ancestor of a missing `path` when that is an empty directory, which is
+/// what a mount point looks like before its filesystem is mounted
+fn unmounted_ancestor(path: &Path) -> Option<&Path> {
+    let ancestor = path.ancestors().skip(1).find(|ancestor| ancestor.exists())?;
+    let mut entries = fs::read_dir(ancestor).ok()?;
+    entries.next().is_none().then_some(ancestor)
+}
+
+/// Size and path of every directory of a scanned tree, for --dump-dirs
+fn dir_sizes(root: &mut Dir) -> Vec<(u64, String)> {
+    root.calc_size();
+    root.all_dirs().iter().map(|dir| (dir.size(), dir.path.clone())).collect()
+}
+
+/// Write one directory path per line
+fn write_dir_list(path: &Path, dirs: &[(u64, String)]) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    for (_, dir) in dirs {
+        writeln!(out, "{}", dir)?;
+    }
+    out.flush()
+}
+
+/// Leave out every root that is inside another one, or the same directory given twice, so
+/// nothing is counted twice. Roots that can not be resolved are kept.
+fn drop_nested_roots(roots: Vec<String>) -> Vec<String> {
+    let resolved: Vec<Option<PathBuf>> =
+        roots.iter().map(|root| fs::canonicalize(root).ok()).collect();
+    let mut kept = Vec::new();
+    for (i, root) in roots.iter().enumerate() {
+        let outer = resolved[i].as_ref().and_then(|path| {
+            resolved.iter().enumerate().find_map(|(j, other)| {
+                let other = other.as_ref()?;
+                // Of two equal roots the first one is kept
+                let contains = path.starts_with(other) && (path != other || j < i);
+                (j != i && contains).then_some(j)
+            })
+        });
+        match outer {
+            Some(j) => {
+                let relation = if resolved[i] == resolved[j] { "the same as" } else { "inside" };
+                log::warn!(
+                    "{} is {} {}, it is only scanned once (pass --allow-overlap to scan both)",
+                    root,
+                    relation,
+                    roots[j]
+                );
+            }
+            None => kept.push(root.clone()),
+        }
+    }
+    kept
+}
+
+/// Walk one root into `dir`, counting into `stats`
+fn scan(dir: &mut Dir, options: &ScanOptions, stats: &ScanStats) -> ScanOutput {
+    let started = Instant::now();
+    let bytes_before = stats.bytes.load(Ordering::Relaxed);
+    let used_before = fsusage::usage(Path::new(&dir.path)).map(|usage| usage.used);
+    let output = match find_all_files_and_directories(dir, options, stats) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("error: cannot scan {}: {}", dir.path, e);
+            process::exit(1);
+        }
+    };
+    log::info!(
+        "scanned {} files in {} in {:.2?}",
+        stats.files.load(Ordering::Relaxed),
+        dir.path,
+        started.elapsed()
+    );
+    if options.timing {
+        let walker = if options.std_walker { "std" } else { "ignore" };
+        eprintln!("timing: {} took {:.2?} with the {} walker", dir.path, started.elapsed(), walker);
+    }
+    stats.add_dir(0);
+    if TIMED_OUT.load(Ordering::Relaxed) {
+        WOUND_DOWN.store(true, Ordering::Relaxed);
+        log::warn!("timed out scanning {}, totals are lower bounds", dir.path);
+    } else {
+        check_fs_usage(&dir.path, stats.bytes.load(Ordering::Relaxed) - bytes_before);
+    }
+    if let (Ok(before), Ok(after)) = (used_before, fsusage::usage(Path::new(&dir.path))) {
+        check_fs_changed(before, after.used);
+    }
+    output
+}
+
+/// Smallest change in used space during a scan that is worth a note
+const MIN_USAGE_CHANGE: u64 = 16 * 1024 * 1024;
+
+/// Every file's size is taken when it is visited, so files written elsewhere on the filesystem
+/// while the walk runs make the results a mix of moments. Point it out when that was a lot.
+fn check_fs_changed(used_before: u64, used_after: u64) {
+    let change = used_before.abs_diff(used_after);
+    if change > MIN_USAGE_CHANGE && change > max(used_before, used_after) / 100 {
+        log::warn!(
+            "filesystem changed during scan: used space {} by {}, results are a point-in-time snapshot of each file",
+            if used_after > used_before { "grew" } else { "shrank" },
+            bytes_to_nice(change)
+        );
+    }
+}
+
+/// Below this the gap between the scan and the filesystem is not worth a note
+const MIN_USAGE_GAP: u64 = 1024 * 1024 * 1024;
+
+/// When a whole filesystem was scanned, point out if it reports much more space in use than
+/// was found. Deleted files that are still open take space no walk can see.
+fn check_fs_usage(root: &str, scanned: u64) {
+    let used = match fsusage::used_at_mount_point(Path::new(root)) {
+        Some(used) => used,
+        None => return,
+    };
+    let gap = used.saturating_sub(scanned);
+    if gap > MIN_USAGE_GAP && gap > used / 10 {
+        log::warn!(
+            "filesystem reports {} used but scan found only {}; possible deleted-but-open files or permission-skipped areas",
+            bytes_to_nice(used),
+            bytes_to_nice(scanned)
+        );
+    }
+}
+
+/// Write the totals of every root to `path` for --summary-json, one JSON object per line
+fn write_summaries(path: &Path, summaries: &[prometheus::RootMetrics]) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    for summary in summaries {
+        let json = Json::Object(vec![
+            ("root", summary.root.as_str().into()),
+            ("total_bytes", summary.total_bytes.into()),
+            ("files", summary.files.into()),
+            ("dirs", summary.dirs.into()),
+            ("largest_file_bytes", summary.largest_file_bytes.into()),
+        ]);
+        json.write(&mut out)?;
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+/// Send the totals of a root, and the --top files if any, to the system log
+fn send_to_syslog(summary: &prometheus::RootMetrics, top: Option<&[File]>, facility: &str) {
+    let mut lines = vec![format!(
+        "host={} root={} total={} largest={} files={} dirs={}",
+        syslog::hostname(),
+        summary.root,
+        summary.total_bytes,
+        summary.largest_file_bytes,
+        summary.files,
+        summary.dirs
+    )];
+    for file in top.unwrap_or_default() {
+        lines.push(format!("root={} size={} path={}", summary.root, file.size, file.path));
+    }
+    syslog::send(facility, &lines);
+}
+
+/// The options a scan runs with, for --print-config
+fn resolved_config(
+    matches: &clap::ArgMatches,
+    roots: &[String],
+    options: &ScanOptions,
+    report_options: &ReportOptions,
+) -> Json {
+    // Values that can come from the environment say which one won
+    let source = |arg: &str, var: &str| -> Json {
+        if matches.occurrences_of(arg) > 0 {
+            "command line".into()
+        } else if std::env::var_os(var).is_some() {
+            format!("environment {}", var).as_str().into()
+        } else {
+            "default".into()
+        }
+    };
+    let percent = match report_options.percent {
+        Percent::Fixed(percent) => Json::Float(percent),
+        Percent::Auto { percentile, count } => Json::Object(vec![
+            ("percentile", percentile.into()),
+            ("count", count.map(|count| count as u64).into()),
+        ]),
+    };
+    let kinds = match report_options.kinds {
+        Kinds::All => "all",
+        Kinds::Files => "files",
+        Kinds::Dirs => "dirs",
+    };
+    let strings = |values: &[String]| Json::Array(values.iter().map(|v| v.as_str().into()).collect());
+    let skip_dirs = options.skip_dirs.iter().map(|dir| dir.to_string_lossy().as_ref().into()).collect();
+    Json::Object(vec![
+        ("roots", strings(roots)),
+        ("merge_roots", matches.is_present("merge-roots").into()),
+        ("percent", percent),
+        (
+            "percent_source",
+            if matches.is_present("above-percentile") {
+                "command line".into()
+            } else {
+                source("percent", "PERCENT")
+            },
+        ),
+        ("percent_of", matches.value_of("percent-of").unwrap_or("largest").into()),
+        ("min_size", report_options.min_size.into()),
+        ("threshold_mode", matches.value_of("threshold-mode").unwrap_or("and").into()),
+        ("ignore", options.ignore.into()),
+        ("ignore_source", source("ignore", "IGNORE")),
+        ("exclude_hidden", options.exclude_hidden.into()),
+        ("exclude", strings(options.exclude.as_ref().map_or(&[], |exclude| exclude.patterns()))),
+        ("exclude_vcs", options.exclude_vcs.into()),
+        (
+            "count_only_matches",
+            strings(options.count_only.as_ref().map_or(&[], |count_only| count_only.patterns())),
+        ),
+        ("skip_dirs", Json::Array(skip_dirs)),
+        ("follow_links", options.follow_links.into()),
+        ("max_symlink_depth", (options.max_symlink_depth as u64).into()),
+        ("dereference_root", options.dereference_root.into()),
+        ("resolve_dir_symlinks", options.resolve_dir_symlinks.into()),
+        ("no_symlinks", options.no_symlinks.into()),
+        ("count_dir_overhead", options.count_dir_overhead.into()),
+        ("include_ads", options.include_ads.into()),
+        ("device_sizes", options.device_sizes.into()),
+        ("block_size", options.block_size.into()),
+        ("top", options.top.map(|top| top as u64).into()),
+        ("min_files", report_options.min_files.into()),
+        ("kinds", kinds.into()),
+        ("sort", matches.value_of("sort").into()),
+        ("dirs_first", matches.is_present("dirs-first").into()),
+        ("files_first", matches.is_present("files-first").into()),
+        ("format", report_options.format.as_str().into()),
+        ("json_pretty", report_options.json_pretty.into()),
+        ("size_format", matches.value_of("size-format").unwrap_or("binary").into()),
+        (
+            "columns",
+            Json::Array(report_options.columns.iter().map(|c| c.name().into()).collect()),
+        ),
+        ("cache", matches.value_of("cache").into()),
+        ("timeout", matches.value_of("timeout").into()),
+        ("pager", matches.value_of("pager").into()),
+        ("parallel", (!matches.is_present("no-parallel")).into()),
+    ])
+}
+
+/// The totals --format prometheus exports for one root
+fn root_metrics(
+    base_dir: &mut Dir,
+    output: ScanOutput,
+    stats: &ScanStats,
+    report_options: &ReportOptions,
+) -> prometheus::RootMetrics {
+    report_skipped(&output.skipped, report_options.errors_json);
+    summarize(base_dir, &output, stats)
+}
+
+/// The totals of one root, for --format prometheus and --syslog
+fn summarize(base_dir: &mut Dir, output: &ScanOutput, stats: &ScanStats) -> prometheus::RootMetrics {
+    let largest_file_bytes = match &output.top {
+        Some(top) => top.first().map_or(0, |file| file.size),
+        None => {
+            base_dir.calc_size();
+            base_dir.largest_child()
+        }
+    };
+    prometheus::RootMetrics {
+        root: base_dir.path.clone(),
+        total_bytes: stats.bytes.load(Ordering::Relaxed),
+        files: stats.files.load(Ordering::Relaxed),
+        dirs: stats.dirs.load(Ordering::Relaxed),
+        largest_file_bytes,
+    }
+}
+
+/// How scan results are reported, resolved from the command line
+struct ReportOptions {
+    percent: Percent,
+    /// The percentage is of the free space on the filesystem instead of the largest file
+    percent_of_free: bool,
+    /// Absolute cutoff combined with the percentage one
+    min_size: Option<u64>,
+    /// Reaching one of the two cutoffs is enough to be shown
+    either_threshold: bool,
+    min_files: u64,
+    tiers: Option<Vec<Tier>>,
+    columns: Vec<Column>,
+    format: String,
+    /// Indent the JSON of --format json and treemap
+    json_pretty: bool,
+    oneline: bool,
+    name_only: bool,
+    print0: bool,
+    /// Print records of NUL terminated fields instead of the listing
+    fields0: bool,
+    flush_interval: FlushInterval,
+    truncate_paths: bool,
+    relative_time: bool,
+    /// The scan options as --scan-id hashes them
+    scan_id: Option<String>,
+    kinds: Kinds,
+    heavy_path: bool,
+    group_by_dir: bool,
+    /// Sum up the files per depth below the root instead of listing the tree
+    group_by_depth: bool,
+    /// List groups of files with the same contents instead of the tree
+    duplicates: bool,
+    /// Hashes reused while a file is unchanged, for --checksum-cache
+    checksum_cache: Option<Mutex<ChecksumCache>>,
+    /// Files of an earlier --cache, list the ones that changed instead of the tree
+    compare: Option<HashMap<String, cache::CachedFile>>,
+    /// Changes smaller than this are not listed by compare
+    min_change: ChangeThreshold,
+    /// Rank directories by recursive file count instead of size
+    sort_by_count: bool,
+    /// Order of the entries inside each listed directory
+    child_order: ChildOrder,
+    /// Totals are those of the files matching --count-only-matches
+    count_only_matches: bool,
+    by_ext: bool,
+    top_per_ext: Option<usize>,
+    largest_dirs: Option<usize>,
+    /// List every directory with its largest file
+    largest_per_dir: bool,
+    min_links: Option<u64>,
+    /// List only the entries with a longer name
+    max_name_length: Option<usize>,
+    min_parent_percent: Option<f64>,
+    include_zero: bool,
+    /// Files modified after this are counted but not listed, for --time-filter-mode display
+    hide_newer: Option<u64>,
+    collapse_pattern: bool,
+    /// List the files largest first with their parent directories
+    reverse_tree: bool,
+    /// Show the bytes per device when a scan spans several
+    device_summary: bool,
+    free_space: bool,
+    /// List directories by file count with the inode usage of the filesystem
+    inodes: bool,
+    bar_scale: BarScale,
+    /// Leave the scanned directory's own entry out of the listing
+    no_root: bool,
+    /// List the disk images instead of the tree
+    image_summary: bool,
+    /// Show paths with forward slashes on every platform
+    normalize_paths: bool,
+    /// List files with several hard links once
+    unique_inodes: bool,
+    case_sensitive_ext: bool,
+    ext_thresholds: HashMap<String, u64>,
+    errors_json: bool,
+    block_size: Option<u64>,
+}
+
+impl ReportOptions {
+    /// Whether the report is the --by-ext totals and nothing else, which the walk can sum up
+    /// without a tree. Every field is named, so a new one does not compile until it is sorted
+    /// in here.
+    fn only_ext_totals(&self) -> bool {
+        let ReportOptions {
+            by_ext,
+            format,
+            // Reports of the tree that report() picks before --by-ext
+            oneline,
+            heavy_path,
+            group_by_dir,
+            group_by_depth,
+            duplicates,
+            compare,
+            top_per_ext,
+            largest_dirs,
+            largest_per_dir,
+            min_links,
+            max_name_length,
+            inodes,
+            image_summary,
+            // Not deciding which report is printed
+            percent: _,
+            percent_of_free: _,
+            min_size: _,
+            either_threshold: _,
+            min_files: _,
+            tiers: _,
+            columns: _,
+            json_pretty: _,
+            name_only: _,
+            print0: _,
+            fields0: _,
+            flush_interval: _,
+            truncate_paths: _,
+            relative_time: _,
+            scan_id: _,
+            kinds: _,
+            checksum_cache: _,
+            min_change: _,
+            sort_by_count: _,
+            child_order: _,
+            count_only_matches: _,
+            min_parent_percent: _,
+            include_zero: _,
+            hide_newer: _,
+            collapse_pattern: _,
+            reverse_tree: _,
+            device_summary: _,
+            free_space: _,
+            bar_scale: _,
+            no_root: _,
+            normalize_paths: _,
+            unique_inodes: _,
+            case_sensitive_ext: _,
+            ext_thresholds: _,
+            errors_json: _,
+            block_size: _,
+        } = self;
+        *by_ext
+            && format == "text"
+            && !(*oneline
+                || *heavy_path
+                || *group_by_dir
+                || *group_by_depth
+                || *duplicates
+                || compare.is_some()
+                || top_per_ext.is_some()
+                || largest_dirs.is_some()
+                || *largest_per_dir
+                || min_links.is_some()
+                || max_name_length.is_some()
+                || *inodes
+                || *image_summary)
+    }
+}
+
+/// Which entries the listing keeps, from --files-only and --dirs-only
+#[derive(PartialEq)]
+enum Kinds {
+    All,
+    Files,
+    Dirs,
+}
+
+impl Kinds {
+    fn keeps(&self, entry: &ListEntry) -> bool {
+        match self {
+            Kinds::All => true,
+            Kinds::Files => !entry.is_dir,
+            Kinds::Dirs => entry.is_dir,
+        }
+    }
+}
+
+/// `entries` with only the first listed path of every file with several hard links, noting how
+/// many more of its links were listed, for --unique-inodes
+fn collapse_links<'a>(root: &'a Dir, entries: Vec<ListEntry<'a>>) -> Vec<ListEntry<'a>> {
+    let files = root.all_files();
+    let inodes: HashMap<&str, (u64, u64)> = files
+        .iter()
+        .filter_map(|file| file.inode.map(|inode| (file.path.as_str(), inode)))
+        .collect();
+    if inodes.is_empty() {
+        return entries;
+    }
+    let inode_of = |entry: &ListEntry| inodes.get(&*entry.path).filter(|_| !entry.is_dir).copied();
+    let mut listed: HashMap<(u64, u64), usize> = HashMap::new();
+    for entry in entries.iter() {
+        if let Some(inode) = inode_of(entry) {
+            *listed.entry(inode).or_default() += 1;
+        }
+    }
+    let mut shown = HashSet::new();
+    entries
+        .into_iter()
+        .filter_map(|entry| match inode_of(&entry) {
+            Some(inode) if !shown.insert(inode) => None,
+            Some(inode) if listed[&inode] > 1 => {
+                let more = listed[&inode] - 1;
+                let plural = if more == 1 { "" } else { "s" };
+                Some(ListEntry {
+                    path: format!("{} (+{} more link{})", entry.path, more, plural).into(),
+                    ..entry
+                })
+            }
+            _ => Some(entry),
+        })
+        .collect()
+}
+
+/// `entries` with their paths shown with forward slashes, for --normalize-paths
+fn normalize_entry_paths(entries: Vec<ListEntry>) -> Vec<ListEntry> {
+    entries
+        .into_iter()
+        .map(|entry| match normalized_path(&entry.path) {
+            Cow::Owned(path) => ListEntry {
+                path: path.into(),
+                ..entry
+            },
+            Cow::Borrowed(_) => entry,
+        })
+        .collect()
+}
+
+/// Print the results of scanning `base_dir` in the requested format
+fn report(base_dir: &mut Dir, output: ScanOutput, stats: &ScanStats, report_options: &ReportOptions) {
+    report_skipped(&output.skipped, report_options.errors_json);
+    if let Some(top) = output.top {
+        let entries: Vec<ListEntry> = top
+            .iter()
+            .map(|file| ListEntry {
+                path: Cow::Borrowed(&file.path),
+                size: file.size,
+                is_dir: false,
+                files: 1,
+                self_size: file.size,
+            })
+            .collect();
+        let entries = if report_options.normalize_paths {
+            normalize_entry_paths(entries)
+        } else {
+            entries
+        };
+        if report_options.name_only {
+            print_names(&entries, report_options);
+            return;
+        }
+        if report_options.fields0 {
+            print_fields0(&entries, report_options);
+            return;
+        }
+        let total_size = stats.bytes.load(Ordering::Relaxed);
+        if report_options.format != "text" {
+            write_entries(&base_dir.path, total_size, entries, report_options);
+            return;
+        }
+        print_columns(&entries, report_options, total_size);
+        print_total(total_size, report_options);
+        println!("Largest child: {}", bytes_to_nice(top.first().map_or(0, |file| file.size)));
+        if let Some(key) = &report_options.scan_id {
+            let mut id = ScanId::start(&base_dir.path, key);
+            for file in top.iter() {
+                id.add(file.path.as_bytes());
+                id.add(&file.size.to_le_bytes());
+            }
+            println!("Scan ID: {}", id.finish());
+        }
+        if report_options.device_summary {
+            print_devices(&output.devices);
+        }
+        if report_options.free_space {
+            print_free_space(&base_dir.path, &entries);
+        }
+        return;
+    }
+    if let Some(totals) = output.ext_totals {
+        print_ext_totals(totals.totals);
+        print_total(stats.bytes.load(Ordering::Relaxed), report_options);
+        return;
+    }
+
+    base_dir.calc_size();
+    let total_size = base_dir.size();
+    let largest_child = base_dir.largest_child();
+
+    if report_options.oneline {
+        println!(
+            "total={} largest={} files={} dirs={}",
+            total_size,
+            largest_child,
+            stats.files.load(Ordering::Relaxed),
+            stats.dirs.load(Ordering::Relaxed)
+        );
+        return;
+    }
+
+    if report_options.heavy_path {
+        print_heavy_path(base_dir);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if report_options.group_by_dir {
+        print_dir_rollup(base_dir, report_options.sort_by_count);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if report_options.duplicates {
+        print_duplicates(base_dir, report_options.checksum_cache.as_ref());
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if report_options.image_summary {
+        print_disk_images(base_dir);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if report_options.inodes {
+        print_inode_usage(base_dir);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if let Some(previous) = &report_options.compare {
+        print_changes(base_dir, previous, report_options.min_change);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if report_options.group_by_depth {
+        print_depth_rollup(base_dir);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if let Some(n) = report_options.largest_dirs {
+        print_largest_dirs(base_dir, n, report_options.sort_by_count);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if report_options.largest_per_dir {
+        print_largest_per_dir(base_dir, report_options.min_size.unwrap_or(0));
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if let Some(n) = report_options.max_name_length {
+        print_long_names(base_dir, n);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if let Some(n) = report_options.min_links {
+        print_linked_files(base_dir, n);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if let Some(n) = report_options.top_per_ext {
+        print_top_per_ext(base_dir, n, report_options.case_sensitive_ext);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    if report_options.by_ext {
+        print_ext_rollup(base_dir, report_options.case_sensitive_ext);
+        print_total(total_size, report_options);
+        return;
+    }
+
+    let cutoff = match report_options.percent {
+        Percent::Fixed(percent) if report_options.percent_of_free => {
+            let free = match fsusage::usage(Path::new(&base_dir.path)) {
+                Ok(usage) => usage.free,
+                Err(e) => {
+                    eprintln!("error: cannot query the free space of {}: {}", base_dir.path, e);
+                    process::exit(1);
+                }
+            };
+            let cutoff = (free as f64 * (percent / 100.0)) as u64;
+            log::info!("cutoff: {} of {} free", bytes_to_nice(cutoff), bytes_to_nice(free));
+            cutoff
+        }
+        Percent::Fixed(percent) => (largest_child as f64 * (percent / 100.0)) as u64,
+        Percent::Auto { percentile, count } => {
+            let mut sizes: Vec<u64> = base_dir.all_files().iter().map(|file| file.size).collect();
+            sizes.sort_unstable();
+            let cutoff = auto_cutoff(&sizes, percentile, count);
+            log::info!("automatic cutoff: {}", bytes_to_nice(cutoff));
+            cutoff
+        }
+    };
+    let cutoff = Cutoff {
+        relative: cutoff,
+        absolute: report_options.min_size,
+        either: report_options.either_threshold,
+    };
+    let filter = ListFilter {
+        hide_newer: report_options.hide_newer,
+        mark_uncounted: report_options.format == "text",
+        order: report_options.child_order,
+        order_by_count: report_options.sort_by_count,
+        ..ListFilter::new(
+            cutoff,
+            report_options.min_files,
+            report_options.ext_thresholds.clone(),
+            report_options.case_sensitive_ext,
+            report_options.min_parent_percent,
+            report_options.include_zero,
+            report_options.collapse_pattern && report_options.format == "text",
+        )
+    };
+    if report_options.reverse_tree && report_options.format == "text" {
+        print_reverse_tree(base_dir, &filter);
+        print_total(total_size, report_options);
+        println!("Largest child: {}", bytes_to_nice(largest_child));
+        return;
+    }
+    let mut entries = Vec::new();
+    base_dir.list(&filter, &mut entries);
+    entries.retain(|entry| report_options.kinds.keeps(entry));
+    if report_options.no_root {
+        entries.retain(|entry| !entry.is_dir || entry.path != base_dir.path.as_str());
+    }
+    if report_options.unique_inodes && report_options.format == "text" {
+        entries = collapse_links(base_dir, entries);
+    }
+    if report_options.normalize_paths {
+        entries = normalize_entry_paths(entries);
+    }
+    if entries.iter().any(|entry| !entry.is_dir && filter.ext_threshold(&entry.path).is_some()) {
+        THRESHOLD_EXCEEDED.store(true, Ordering::Relaxed);
+    }
+
+    if report_options.name_only {
+        print_names(&entries, report_options);
+        return;
+    }
+    if report_options.fields0 {
+        print_fields0(&entries, report_options);
+        return;
+    }
+
+    let format = report_options.format.as_str();
+    if format == "treemap" {
+        let json = base_dir.to_treemap(&base_dir.path);
+        write_json(&json, report_options);
+        return;
+    }
+    if format != "text" {
+        write_entries(&base_dir.path, total_size, entries, report_options);
+        return;
+    }
+
+    if let Some(tiers) = &report_options.tiers {
+        print_tiered(&entries, tiers);
+        print_total(total_size, report_options);
+        println!("Largest child: {}", bytes_to_nice(largest_child));
+        if report_options.device_summary {
+            print_devices(&output.devices);
+        }
+        if report_options.free_space {
+            print_free_space(&base_dir.path, &entries);
+        }
+        return;
+    }
+
+    print_columns(&entries, report_options, total_size);
+    print_total(total_size, report_options);
+    println!("Largest child: {}", bytes_to_nice(largest_child));
+    if let Some(key) = &report_options.scan_id {
+        println!("Scan ID: {}", tree_scan_id(base_dir, key));
+    }
+    if report_options.device_summary {
+        print_devices(&output.devices);
+    }
+    if report_options.free_space {
+        print_free_space(&base_dir.path, &entries);
+    }
+}
+
+/// Hash every directory and file with its size, in the path order the tree is built in
+fn tree_scan_id(root: &Dir, key: &str) -> String {
+    let mut id = ScanId::start(&root.path, key);
+    for dir in root.all_dirs() {
+        id.add(dir.path.as_bytes());
+        id.add(&dir.size().to_le_bytes());
+        for child in dir.children.iter() {
+            if let FsItem::File(file) = child {
+                id.add(file.path.as_bytes());
+                id.add(&file.size.to_le_bytes());
+            }
+        }
+    }
+    id.finish()
+}
+
+/// Show how much of the total is on every device, when the scan crossed into other filesystems
+fn print_devices(devices: &HashMap<u64, u64>) {
+    if devices.len() < 2 {
+        return;
+    }
+    let mounts = fsusage::mount_points();
+    let mut devices: Vec<(u64, u64)> = devices.iter().map(|(dev, bytes)| (*dev, *bytes)).collect();
+    devices.sort_by_key(|(dev, bytes)| (Reverse(*bytes), *dev));
+    let width = size_column_width(devices.iter().map(|(_, bytes)| *bytes));
+    println!("Spans {} devices:", devices.len());
+    for (dev, bytes) in devices {
+        let name = match mounts.get(&dev) {
+            Some(mount_point) => format!("{} (device {})", mount_point, dev),
+            None => format!("device {}", dev),
+        };
+        println!("  {0: <width$} {1}", bytes_to_nice(bytes), name);
+    }
+}
+
+/// Show the filesystem holding `root` and what deleting the listed files would free
+fn print_free_space(root: &str, entries: &[ListEntry]) {
+    let usage = match fsusage::usage(Path::new(root)) {
+        Ok(usage) => usage,
+        Err(e) => {
+            log::warn!("cannot query the filesystem of {}: {}", root, e);
+            return;
+        }
+    };
+    println!(
+        "Filesystem: {} total ({}), {} used ({}), {} free ({})",
+        bytes_to_nice(usage.total),
+        usage.total,
+        bytes_to_nice(usage.used),
+        usage.used,
+        bytes_to_nice(usage.free),
+        usage.free
+    );
+    let files: Vec<&ListEntry> = entries.iter().filter(|entry| !entry.is_dir).collect();
+    let freed: u64 = files.iter().map(|entry| entry.size).sum();
+    println!(
+        "Deleting the {} listed files would free {} ({}), {:.1}% of used space",
+        files.len(),
+        bytes_to_nice(freed),
+        freed,
+        freed as f64 * 100.0 / max(usage.used, 1) as f64
+    );
+}
+
+/// Write `entries` as --format json or canonical, the formats made of the listed entries alone
+fn write_entries(root: &str, total_size: u64, entries: Vec<ListEntry>, report_options: &ReportOptions) {
+    if report_options.format == "canonical" {
+        // Directories are marked by a trailing /, a Windows path needs forward slashes for that
+        let paths: Vec<Cow<str>> = entries.iter().map(|entry| normalized_path(&entry.path)).collect();
+        let lines = entries
+            .iter()
+            .zip(paths.iter())
+            .map(|(entry, path)| (&**path, entry.size, entry.is_dir));
+        if let Err(e) = canonical::write(&mut io::stdout().lock(), lines) {
+            exit_on_write_error(e);
+        }
+        return;
+    }
+    let root = if report_options.normalize_paths {
+        normalized_path(root)
+    } else {
+        Cow::Borrowed(root)
+    };
+    let json = ScanReport {
+        schema_version: SCHEMA_VERSION,
+        root: &root,
+        total_bytes: total_size,
+        entries,
+    }
+    .to_json();
+    write_json(&json, report_options);
+}
+
+/// Write `json` to stdout on one line, or indented with --json-pretty
+fn write_json(json: &Json, report_options: &ReportOptions) {
+    let mut out = io::stdout().lock();
+    let written = if report_options.json_pretty {
+        json.write_pretty(&mut out)
+    } else {
+        json.write(&mut out)
+    };
+    if let Err(e) = written.and_then(|_| writeln!(out)) {
+        exit_on_write_error(e);
+    }
+}
+
+/// Stop when the listing can not be written. A closed pipe, e.g. from quitting the pager or
+/// `| head`, just means nobody wants the rest.
+fn exit_on_write_error(e: io::Error) -> ! {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        process::exit(0);
+    }
+    eprintln!("error: {}", e);
+    process::exit(1);
+}
+
+/// Print just the paths for --name-only, NUL terminated with --print0
+fn print_names(entries: &[ListEntry], report_options: &ReportOptions) {
+    let terminator = if report_options.print0 { '\0' } else { '\n' };
+    let mut out = Output::new(report_options.flush_interval);
+    let written = entries
+        .iter()
+        .try_for_each(|entry| {
+            // NUL terminated names are for xargs -0 and the like, no name can break them apart
+            let path = if report_options.print0 {
+                Cow::Borrowed(&*entry.path)
+            } else {
+                shown_path(&entry.path)
+            };
+            out.entry(format_args!("{}{}", path, terminator))
+        })
+        .and_then(|_| out.finish());
+    if let Err(e) = written {
+        exit_on_write_error(e);
+    }
+}
+
+/// Print `size\0path\0type\0` per entry for --fields-null-separated, the size in bytes
+fn print_fields0(entries: &[ListEntry], report_options: &ReportOptions) {
+    let mut out = Output::new(report_options.flush_interval);
+    let written = entries
+        .iter()
+        .try_for_each(|entry| {
+            let kind = if entry.is_dir { 'd' } else { 'f' };
+            out.entry(format_args!("{}\0{}\0{}\0", entry.size, entry.path, kind))
+        })
+        .and_then(|_| out.finish());
+    if let Err(e) = written {
+        exit_on_write_error(e);
+    }
+}
+
+/// Print one row per entry with the selected columns, every column but the last padded
+/// to its widest cell. `total` is what the percent column is relative to.
+fn print_columns(entries: &[ListEntry], report_options: &ReportOptions, total: u64) {
+    let columns = &report_options.columns;
+    let metadata: Vec<Option<fs::Metadata>> = if columns.iter().any(|c| c.needs_metadata()) {
+        entries
+            .iter()
+            .map(|entry| fs::symlink_metadata(&*entry.path).ok())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let cells: Vec<Vec<String>> = columns
+        .iter()
+        .map(|column| match column {
+            Column::Size => entries.iter().map(|entry| bytes_to_nice(entry.size)).collect(),
+            Column::SelfSize => entries.iter().map(|entry| bytes_to_nice(entry.self_size)).collect(),
+            Column::Kind => entries
+                .iter()
+                .map(|entry| if entry.is_dir { "d" } else { "f" }.to_owned())
+                .collect(),
+            // Files are read in parallel since every detection is an open and a read
+            Column::Type => {
+                let detect = |entry: &ListEntry| {
+                    if entry.is_dir { "" } else { filetype::detect(Path::new(&*entry.path)) }
+                        .to_owned()
+                };
+                if PARALLEL.load(Ordering::Relaxed) {
+                    entries.par_iter().map(detect).collect()
+                } else {
+                    entries.iter().map(detect).collect()
+                }
+            }
+            Column::Files => entries.iter().map(|entry| entry.files.to_string()).collect(),
+            Column::Percent => entries
+                .iter()
+                .map(|entry| format!("{:.1}%", entry.size as f64 * 100.0 / max(total, 1) as f64))
+                .collect(),
+            Column::Bar => {
+                let largest = entries.iter().map(|entry| entry.size).max().unwrap_or(0);
+                entries
+                    .iter()
+                    .map(|entry| columns::bar(entry.size, largest, report_options.bar_scale))
+                    .collect()
+            }
+            Column::Path => entries
+                .iter()
+                .map(|entry| shown_path(&entry.path).into_owned())
+                .collect(),
+            _ => metadata
+                .iter()
+                .map(|m| column.metadata_cell(m.as_ref(), report_options.relative_time))
+                .collect(),
+        })
+        .collect();
+
+    let width = report_options.truncate_paths.then(terminal::width);
+    let mut out = Output::new(report_options.flush_interval);
+    let written = layout_rows(columns, cells, width)
+        .iter()
+        .try_for_each(|row| out.entry(format_args!("{}\n", row)))
+        .and_then(|_| out.finish());
+    if let Err(e) = written {
+        exit_on_write_error(e);
+    }
+}
+
+/// The rows of the listing from the cells of every column, padded so the columns line up. With
+/// a `width` paths are shortened to fit the rows into that many characters.
+fn layout_rows(
+    columns: &[Column],
+    mut cells: Vec<Vec<String>>,
+    width: Option<usize>,
+) -> Vec<String> {
+    let column_width = |column: &Column, cells: &[String]| {
+        let widest = cells.iter().map(|cell| cell.len()).max().unwrap_or(0);
+        // Keep sizes in the column width the listing always had
+        if matches!(column, Column::Size | Column::SelfSize) {
+            max(widest, 8)
+        } else {
+            widest
+        }
+    };
+
+    if let Some(width) = width {
+        let others: usize = columns
+            .iter()
+            .zip(cells.iter())
+            .filter(|(column, _)| **column != Column::Path)
+            .map(|(column, cells)| column_width(column, cells) + 1)
+            .sum();
+        let available = width.saturating_sub(others);
+        for (column, cells) in columns.iter().zip(cells.iter_mut()) {
+            if *column == Column::Path {
+                for cell in cells.iter_mut() {
+                    *cell = terminal::truncate_path(cell, available);
+                }
+            }
+        }
+    }
+
+    let widths: Vec<usize> = columns
+        .iter()
+        .zip(cells.iter())
+        .map(|(column, cells)| column_width(column, cells))
+        .collect();
+
+    let rows = cells.first().map_or(0, Vec::len);
+    (0..rows)
+        .map(|i| {
+            let mut row = String::new();
+            for (c, column_cells) in cells.iter().enumerate() {
+                if c + 1 == cells.len() {
+                    row.push_str(&column_cells[i]);
+                } else {
+                    row.push_str(&format!("{0: <width$} ", column_cells[i], width = widths[c]));
+                }
+            }
+            row
+        })
+        .collect()
+}
+
+fn print_total(total_size: u64, report_options: &ReportOptions) {
+    let matching = if report_options.count_only_matches {
+        " (matching files only)"
+    } else {
+        ""
+    };
+    match report_options.block_size {
+        Some(block_size) => println!(
+            "Total size: {}{} (allocated in {} byte blocks)",
+            bytes_to_nice(total_size),
+            matching,
+            block_size
+        ),
+        None => println!("Total size: {}{}", bytes_to_nice(total_size), matching),
+    }
+}
+
+/// Tell the user about entries that could not be read, on stderr so results stay parseable
+fn report_skipped(skipped: &[SkippedEntry], as_json: bool) {
+    SKIPPED.fetch_add(skipped.len() as u64, Ordering::Relaxed);
+    if !as_json {
+        for entry in skipped {
+            log::warn!("skipped {}: {}", entry.path, entry.error);
+        }
+        return;
+    }
+    let mut err = io::stderr().lock();
+    for entry in skipped {
+        let _ = entry.to_json().write(&mut err).and_then(|_| writeln!(err));
+    }
+}
+
+/// Version of the JSON report layout, bumped on breaking changes
+const SCHEMA_VERSION: u64 = 1;
+
+/// The JSON report, see the README for the documented shape
+struct ScanReport<'a> {
+    schema_version: u64,
+    root: &'a str,
+    total_bytes: u64,
+    entries: Vec<ListEntry<'a>>,
+}
+
+impl ScanReport<'_> {
+    fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("schema_version", self.schema_version.into()),
+            ("root", self.root.into()),
+            ("total_bytes", self.total_bytes.into()),
+            (
+                "entries",
+                Json::Array(self.entries.iter().map(|entry| entry.to_json()).collect()),
+            ),
+        ])
+    }
+}
+
+/// How the cutoff is derived from the scan
+enum Percent {
+    /// Percentage of the largest file
+    Fixed(f64),
+    /// Picked from the file size distribution, either a percentile or the N largest files
+    Auto { percentile: f64, count: Option<usize> },
+}
+
+/// Cutoff for `--percent auto`. `sizes` must be sorted ascending.
+/// With a count the cutoff is the size of the Nth largest file, otherwise the percentile.
+fn auto_cutoff(sizes: &[u64], percentile: f64, count: Option<usize>) -> u64 {
+    if sizes.is_empty() {
+        return 0;
+    }
+    match count {
+        Some(count) => sizes[sizes.len() - count.min(sizes.len())],
+        None => nearest_rank_percentile(sizes, percentile),
+    }
+}
+
+/// Nearest-rank percentile of values sorted ascending, `sorted` must not be empty
+fn nearest_rank_percentile(sorted: &[u64], percentile: f64) -> u64 {
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// The sizes that pass the --percent cutoff and --min-size, combined per --threshold-mode
+struct Cutoff {
+    relative: u64,
+    absolute: Option<u64>,
+    /// Passing one of the two is enough
+    either: bool,
+}
+
+impl Cutoff {
+    /// Both cutoffs are inclusive
+    fn admits(&self, size: u64) -> bool {
+        match self.absolute {
+            None => size >= self.relative,
+            Some(absolute) if self.either => size >= self.relative || size >= absolute,
+            Some(absolute) => size >= self.relative && size >= absolute,
+        }
+    }
+
+    /// The smallest size that passes
+    fn lowest(&self) -> u64 {
+        match self.absolute {
+            None => self.relative,
+            Some(absolute) if self.either => self.relative.min(absolute),
+            Some(absolute) => self.relative.max(absolute),
+        }
+    }
+}
+
+/// Order of the entries inside each directory of the listing
+#[derive(Clone, Copy, PartialEq)]
+enum ChildOrder {
+    /// By path, files and subdirectories interleaved
+    Path,
+    /// Subdirectories, then files, each largest first, for --dirs-first
+    DirsFirst,
+    /// Files, then subdirectories, each largest first, for --files-first
+    FilesFirst,
+}
+
+/// Decides which entries make it into the listing
+struct ListFilter {
+    /// Sizes shown
+    cutoff: Cutoff,
+    /// Directories with fewer files than this (recursively) are not shown
+    min_files: u64,
+    /// Files with these extensions are shown only when they reach their threshold
+    ext_thresholds: HashMap<String, u64>,
+    case_sensitive_ext: bool,
+    /// Directories with a smaller share of their parent are hidden with all they hold
+    min_parent_percent: Option<f64>,
+    /// Show empty files, whatever the cutoff
+    include_zero: bool,
+    /// List runs of similarly named sibling files as one entry
+    collapse: bool,
+    /// Files modified after this are not shown, though counted, for --time-filter-mode display
+    hide_newer: Option<u64>,
+    /// List files that do not count towards the totals, marked as such, rather than leave them out
+    mark_uncounted: bool,
+    /// Order of the entries inside each directory
+    order: ChildOrder,
+    /// Order directories by file count within their group rather than by size
+    order_by_count: bool,
+    /// Directories smaller than this can not hold anything that is shown
+    descend: u64,
+}
+
+impl ListFilter {
+    fn new(
+        cutoff: Cutoff,
+        min_files: u64,
+        ext_thresholds: HashMap<String, u64>,
+        case_sensitive_ext: bool,
+        min_parent_percent: Option<f64>,
+        include_zero: bool,
+        collapse: bool,
+    ) -> ListFilter {
+        let descend = ext_thresholds
+            .values()
+            .copied()
+            .fold(cutoff.lowest(), u64::min);
+        // Empty files can be anywhere, also in directories without anything else
+        let descend = if include_zero { 0 } else { descend };
+        ListFilter {
+            cutoff,
+            min_files,
+            ext_thresholds,
+            case_sensitive_ext,
+            min_parent_percent,
+            include_zero,
+            collapse,
+            hide_newer: None,
+            mark_uncounted: false,
+            order: ChildOrder::Path,
+            order_by_count: false,
+            descend,
+        }
+    }
+
+    /// Whether a directory of `size` with a parent of `parent_size` is shown at all
+    fn shows_child_dir(&self, size: u64, parent_size: u64) -> bool {
+        match self.min_parent_percent {
+            Some(percent) => size as f64 * 100.0 >= percent * parent_size as f64,
+            None => true,
+        }
+    }
+
+    /// The --ext-threshold rule for the extension of `path`, if there is one
+    fn ext_threshold(&self, path: &str) -> Option<u64> {
+        if self.ext_thresholds.is_empty() {
+            return None;
+        }
+        self.ext_thresholds
+            .get(&extension(path, self.case_sensitive_ext))
+            .copied()
+    }
+
+    /// Whether a file modified at `mtime` is hidden by --time-filter-mode display
+    fn hides_recent(&self, mtime: u64) -> bool {
+        self.hide_newer.is_some_and(|since| mtime > since)
+    }
+
+    fn shows_file(&self, path: &str, size: u64) -> bool {
+        if size == 0 {
+            return self.include_zero;
+        }
+        match self.ext_threshold(path) {
+            Some(threshold) => size >= threshold,
+            None => self.cutoff.admits(size),
+        }
+    }
+}
+
+/// Parse --ext-threshold rules like `mp4=2G,log=100M`, a leading dot is allowed
+fn parse_ext_thresholds(s: &str, case_sensitive: bool) -> Result<HashMap<String, u64>, String> {
+    s.split(',')
+        .map(|rule| {
+            let (ext, size) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("invalid extension threshold, expected EXT=SIZE: {}", rule))?;
+            let ext = ext.trim().trim_start_matches('.');
+            if ext.is_empty() {
+                return Err(format!("missing extension in threshold: {}", rule));
+            }
+            let ext = if case_sensitive { ext.to_owned() } else { ext.to_lowercase() };
+            Ok((ext, parse_size(size)?))
+        })
+        .collect()
+}
+
+/// One line of the listing, an entry that passed the cutoff
+struct ListEntry<'a> {
+    /// Borrowed from the tree, owned for a --collapse-pattern group
+    path: Cow<'a, str>,
+    size: u64,
+    is_dir: bool,
+    /// Files at or below the entry, 1 for a file
+    files: u64,
+    /// Bytes of the files directly inside a directory, the size for a file
+    self_size: u64,
+}
+
+impl ListEntry<'_> {
+    fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("path", self.path.as_ref().into()),
+            ("type", if self.is_dir { "dir" } else { "file" }.into()),
+            ("size", self.size.into()),
+        ])
+    }
+}
+
+/// Follow the largest child of every directory from the root down to a leaf
+fn print_heavy_path(root: &Dir) {
+    let mut chain = vec![ListEntry {
+        path: Cow::Borrowed(&root.path),
+        size: root.size(),
+        is_dir: true,
+        files: root.file_count(),
+        self_size: root.self_size,
+    }];
+    let mut current = root;
+    while let Some(item) = current.largest_item() {
+        match item {
+            FsItem::Dir(dir) => {
+                chain.push(ListEntry {
+                    path: Cow::Borrowed(&dir.path),
+                    size: dir.size(),
+                    is_dir: true,
+                    files: dir.file_count(),
+                    self_size: dir.self_size,
+                });
+                current = dir;
+            }
+            FsItem::File(file) => {
+                chain.push(ListEntry {
+                    path: Cow::Borrowed(&file.path),
+                    size: file.size,
+                    is_dir: false,
+                    files: 1,
+                    self_size: file.size,
+                });
+                break;
+            }
+        }
+    }
+
+    let total = root.size();
+    let width = size_column_width(chain.iter().map(|entry| entry.size));
+    for entry in chain {
+        let percent = if total == 0 {
+            0.0
+        } else {
+            entry.size as f64 * 100.0 / total as f64
+        };
+        let kind = if entry.is_dir { "d" } else { "f" };
+        println!(
+            "{0: <width$} {1: >6.1}% {2} {3}",
+            bytes_to_nice(entry.size),
+            percent,
+            kind,
+            shown_path(&entry.path)
+        );
+    }
+}
+
+/// Print every file the filter shows, largest first, each followed by its parent directories
+/// up to `root` with the share of them the file takes up
+fn print_reverse_tree(root: &Dir, filter: &ListFilter) {
+    fn collect<'a>(
+        dir: &'a Dir,
+        filter: &ListFilter,
+        parents: &mut Vec<&'a Dir>,
+        leaves: &mut Vec<(&'a File, Vec<&'a Dir>)>,
+    ) {
+        parents.push(dir);
+        for child in dir.children.iter() {
+            match child {
+                FsItem::Dir(child) if child.size() >= filter.descend => {
+                    collect(child, filter, parents, leaves)
+                }
+                FsItem::File(file)
+                    if filter.shows_file(&file.path, file.size)
+                        && !filter.hides_recent(file.mtime) =>
+                {
+                    leaves.push((file, parents.iter().rev().copied().collect()))
+                }
+                _ => {}
+            }
+        }
+        parents.pop();
+    }
+    let mut leaves = Vec::new();
+    collect(root, filter, &mut Vec::new(), &mut leaves);
+    leaves.sort_by_key(|(file, _)| Reverse(file.size));
+
+    let width = size_column_width(leaves.iter().map(|(file, _)| file.size).chain(Some(root.size())));
+    let mut out = io::stdout().lock();
+    let written = leaves.iter().enumerate().try_for_each(|(i, (file, parents))| {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        let path = shown_path(&file.path);
+        writeln!(out, "{0: <width$}         f {1}", bytes_to_nice(file.size), path)?;
+        for dir in parents {
+            let percent = file.size as f64 * 100.0 / max(dir.size(), 1) as f64;
+            let size = bytes_to_nice(dir.size());
+            writeln!(out, "{0: <width$} {1: >6.1}% d {2}", size, percent, shown_path(&dir.path))?;
+        }
+        Ok(())
+    });
+    if let Err(e) = written {
+        exit_on_write_error(e);
+    }
+}
+
+/// Sort directories largest first, or with the most files first for `--sort count`
+fn rank_dirs<T>(dirs: &mut [T], by_count: bool, dir: impl Fn(&T) -> &Dir) {
+    if by_count {
+        dirs.sort_by_key(|d| (Reverse(dir(d).file_count()), Reverse(dir(d).size())));
+    } else {
+        dirs.sort_by_key(|d| (Reverse(dir(d).size()), Reverse(dir(d).file_count())));
+    }
+}
+
+/// Print every directory as one row with its size and file counts, largest first
+fn print_dir_rollup(root: &Dir, by_count: bool) {
+    let mut dirs = root.all_dirs();
+    rank_dirs(&mut dirs, by_count, |dir| dir);
+    let width = size_column_width(dirs.iter().map(|dir| dir.size()));
+    println!("{0: <width$} {1: >8} {2: >8}  PATH", "SIZE", "FILES", "DIRECT");
+    for dir in dirs {
+        println!(
+            "{0: <width$} {1: >8} {2: >8}  {3}",
+            bytes_to_nice(dir.size()),
+            dir.file_count(),
+            dir.direct_file_count(),
+            shown_path(&dir.path)
+        );
+    }
+}
+
+/// Print every group of files with the same contents, those whose extra copies take the most
+/// space first. Only files that share their size with another are hashed, so most files are
+/// never read, and empty files are never listed.
+fn print_duplicates(root: &Dir, checksum_cache: Option<&Mutex<ChecksumCache>>) {
+    let mut by_size: HashMap<u64, Vec<&File>> = HashMap::new();
+    for file in root.all_files() {
+        if file.size > 0 {
+            by_size.entry(file.size).or_default().push(file);
+        }
+    }
+    let candidates = by_size.into_values().filter(|files| files.len() > 1).flatten();
+
+    let mut cache = checksum_cache.map(|cache| cache.lock().unwrap());
+    let mut hashes = Vec::new();
+    let mut pending = Vec::new();
+    for file in candidates {
+        match cache.as_mut().and_then(|cache| cache.get(&file.path, file.size, file.mtime)) {
+            Some(hash) => hashes.push((file, hash)),
+            None => pending.push(file),
+        }
+    }
+    fn hash(file: &File) -> Option<(&File, u128)> {
+        match dedupe::hash_file(Path::new(&file.path)) {
+            Ok(hash) => Some((file, hash)),
+            Err(e) => {
+                log::warn!("cannot read {}: {}", file.path, e);
+                None
+            }
+        }
+    }
+    let hashed: Vec<(&File, u128)> = if PARALLEL.load(Ordering::Relaxed) {
+        pending.par_iter().copied().filter_map(hash).collect()
+    } else {
+        pending.iter().copied().filter_map(hash).collect()
+    };
+    if let Some(cache) = cache.as_mut() {
+        for (file, hash) in hashed.iter() {
+            let checksum = Checksum {
+                size: file.size,
+                mtime: file.mtime,
+                hash: *hash,
+            };
+            cache.insert(&file.path, checksum);
+        }
+    }
+    hashes.extend(hashed);
+
+    let mut same: HashMap<(u64, u128), Vec<&File>> = HashMap::new();
+    for (file, hash) in hashes {
+        same.entry((file.size, hash)).or_default().push(file);
+    }
+    let mut groups: Vec<Vec<&File>> = same.into_values().filter(|files| files.len() > 1).collect();
+    for files in groups.iter_mut() {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    let wasted = |files: &[&File]| files[0].size * (files.len() as u64 - 1);
+    groups.sort_by(|a, b| wasted(b).cmp(&wasted(a)).then_with(|| a[0].path.cmp(&b[0].path)));
+
+    for files in groups.iter() {
+        println!(
+            "{} x {}, {} reclaimable",
+            bytes_to_nice(files[0].size),
+            files.len(),
+            bytes_to_nice(wasted(files))
+        );
+        for file in files {
+            println!("  {}", shown_path(&file.path));
+        }
+        println!();
+    }
+    let reclaimable: u64 = groups.iter().map(|files| wasted(files)).sum();
+    let plural = if groups.len() == 1 { "" } else { "s" };
+    println!("Reclaimable: {} in {} group{}", bytes_to_nice(reclaimable), groups.len(), plural);
+}
+
+/// List the disk images below `root`, largest first, with their format
+fn print_disk_images(root: &Dir) {
+    let mut images: Vec<(&File, &str)> = root
+        .all_files()
+        .into_iter()
+        .filter_map(|file| filetype::disk_image(Path::new(&file.path)).map(|format| (file, format)))
+        .collect();
+    images.sort_by(|a, b| b.0.size.cmp(&a.0.size).then_with(|| a.0.path.cmp(&b.0.path)));
+    let width = size_column_width(images.iter().map(|(file, _)| file.size));
+    println!("{0: <width$} {1: <6}  PATH", "SIZE", "FORMAT");
+    for (file, format) in images.iter() {
+        let path = shown_path(&file.path);
+        println!("{0: <width$} {1: <6}  {2}", bytes_to_nice(file.size), format, path);
+    }
+    let total: u64 = images.iter().map(|(file, _)| file.size).sum();
+    let plural = if images.len() == 1 { "" } else { "s" };
+    println!("Images: {} in {} file{}", bytes_to_nice(total), images.len(), plural);
+}
+
+/// List the directories below `root` by file count with the share of the used inodes of the
+/// filesystem their files take, then the inode usage of the filesystem
+fn print_inode_usage(root: &Dir) {
+    let inodes = match fsusage::inodes(Path::new(&root.path)) {
+        Ok(inodes) => Some(inodes),
+        Err(e) => {
+            log::warn!("cannot query the inodes of {}: {}", root.path, e);
+            None
+        }
+    };
+    let mut dirs = root.all_dirs();
+    rank_dirs(&mut dirs, true, |dir| dir);
+    let width = size_column_width(dirs.iter().map(|dir| dir.size()));
+    println!("{0: >8} {1: >8} {2: <width$}  PATH", "FILES", "INODES", "SIZE");
+    for dir in dirs {
+        let share = match &inodes {
+            Some(inodes) if inodes.used > 0 => {
+                format!("{:.1}%", dir.file_count() as f64 * 100.0 / inodes.used as f64)
+            }
+            _ => "-".to_owned(),
+        };
+        let size = bytes_to_nice(dir.size());
+        let path = shown_path(&dir.path);
+        println!("{0: >8} {1: >8} {2: <width$}  {3}", dir.file_count(), share, size, path);
+    }
+    if let Some(inodes) = inodes {
+        println!(
+            "Inodes: {} total, {} used ({:.1}%), {} free",
+            inodes.total,
+            inodes.used,
+            inodes.used as f64 * 100.0 / max(inodes.total, 1) as f64,
+            inodes.free
+        );
+    }
+}
+
+/// List the files below `root` that are new, gone or changed in size since the scan that wrote
+/// `previous`, largest change first
+fn print_changes(
+    root: &Dir,
+    previous: &HashMap<String, cache::CachedFile>,
+    min_change: ChangeThreshold,
+) {
+    let files = root.all_files();
+    let current: HashSet<&str> = files.iter().map(|file| file.path.as_str()).collect();
+    let mut changes: Vec<(Option<u64>, Option<u64>, &str)> = files
+        .iter()
+        .map(|file| {
+            let before = previous.get(&file.path).map(|old| old.size);
+            (before, Some(file.size), file.path.as_str())
+        })
+        .filter(|(before, after, _)| before != after)
+        .collect();
+    let changed = changes.len();
+    changes.retain(|change| match change {
+        (Some(before), Some(after), _) => min_change.admits(*before, *after),
+        _ => true,
+    });
+    let below_threshold = changed - changes.len();
+    // The root itself and, for merged roots, every root below it, so other roots are not gone
+    let roots: Vec<&Path> = std::iter::once(root)
+        .chain(root.children.iter().filter_map(|child| match child {
+            FsItem::Dir(dir) => Some(dir),
+            FsItem::File(_) => None,
+        }))
+        .map(|dir| Path::new(&dir.path))
+        .collect();
+    for (path, old) in previous.iter() {
+        let below_roots = roots.iter().any(|root| Path::new(path).starts_with(root));
+        if below_roots && !current.contains(path.as_str()) {
+            changes.push((Some(old.size), None, path));
+        }
+    }
+    let delta = |before: Option<u64>, after: Option<u64>| {
+        after.unwrap_or(0) as i128 - before.unwrap_or(0) as i128
+    };
+    changes.sort_by(|a, b| {
+        let (a_delta, b_delta) = (delta(a.0, a.1), delta(b.0, b.1));
+        b_delta.abs().cmp(&a_delta.abs()).then_with(|| a.2.cmp(b.2))
+    });
+
+    let nice = |size: Option<u64>| size.map_or_else(|| "-".to_owned(), bytes_to_nice);
+    let sizes = changes.iter().flat_map(|(before, after, _)| before.iter().chain(after).copied());
+    let width = size_column_width(sizes);
+    println!("{0: <width$} {1: <width$}  PATH", "BEFORE", "AFTER");
+    let (mut grown, mut shrunk) = (0, 0);
+    for (before, after, path) in changes.iter() {
+        match delta(*before, *after) {
+            d if d > 0 => grown += d as u64,
+            d => shrunk += (-d) as u64,
+        }
+        println!("{0: <width$} {1: <width$}  {2}", nice(*before), nice(*after), shown_path(path));
+    }
+    let plural = if changes.len() == 1 { "" } else { "s" };
+    println!(
+        "Changed: {} file{}, {} grown, {} shrunk",
+        changes.len(),
+        plural,
+        bytes_to_nice(grown),
+        bytes_to_nice(shrunk)
+    );
+    if below_threshold > 0 {
+        let plural = if below_threshold == 1 { "" } else { "s" };
+        println!("Not listed: {} file{} with smaller changes", below_threshold, plural);
+    }
+}
+
+/// Smallest change in size lff compare lists, for --min-change and --min-percent-change
+#[derive(Clone, Copy)]
+struct ChangeThreshold {
+    bytes: u64,
+    /// Percent of the earlier size
+    percent: f64,
+}
+
+impl ChangeThreshold {
+    /// Whether a file that went from `before` to `after` bytes changed enough to be listed. Any
+    /// change of an empty file is.
+    fn admits(&self, before: u64, after: u64) -> bool {
+        let delta = before.abs_diff(after);
+        delta >= self.bytes && (before == 0 || delta as f64 * 100.0 >= self.percent * before as f64)
+    }
+}
+
+/// Print the total size and count of the files at every depth below `root`, the files directly
+/// inside it being at depth 1
+fn print_depth_rollup(root: &Dir) {
+    fn collect(dir: &Dir, depth: usize, totals: &mut Vec<(u64, u64)>) {
+        for child in dir.children.iter() {
+            match child {
+                FsItem::File(file) => {
+                    if totals.len() <= depth {
+                        totals.resize(depth + 1, (0, 0));
+                    }
+                    totals[depth].0 += file.size;
+                    totals[depth].1 += 1;
+                }
+                FsItem::Dir(child) => collect(child, depth + 1, totals),
+            }
+        }
+    }
+    let mut totals = Vec::new();
+    collect(root, 1, &mut totals);
+    let width = size_column_width(totals.iter().map(|(size, _)| *size));
+    println!("{0: >5}  {1: <width$} {2: >8}", "DEPTH", "SIZE", "FILES");
+    for (depth, (size, files)) in totals.into_iter().enumerate().skip(1) {
+        println!("{0: >5}  {1: <width$} {2: >8}", depth, bytes_to_nice(size), files);
+    }
+}
+
+/// Print the `n` largest directories with their depth below `root`, largest first. With
+/// `by_count` the `n` with the most files, with their file counts.
+fn print_largest_dirs(root: &Dir, n: usize, by_count: bool) {
+    // Depth from the tree rather than the path, the root of merged roots is not a real path
+    fn collect<'a>(dir: &'a Dir, depth: usize, dirs: &mut Vec<(&'a Dir, usize)>) {
+        dirs.push((dir, depth));
+        for child in dir.children.iter() {
+            if let FsItem::Dir(child) = child {
+                collect(child, depth + 1, dirs);
+            }
+        }
+    }
+    let mut dirs = Vec::new();
+    collect(root, 0, &mut dirs);
+    rank_dirs(&mut dirs, by_count, |(dir, _)| dir);
+    dirs.truncate(n);
+    let width = size_column_width(dirs.iter().map(|(dir, _)| dir.size()));
+    if by_count {
+        println!("{0: <width$} {1: >8} {2: >5}  PATH", "SIZE", "FILES", "DEPTH");
+        for (dir, depth) in dirs {
+            let size = bytes_to_nice(dir.size());
+            let path = shown_path(&dir.path);
+            println!("{0: <width$} {1: >8} {2: >5}  {3}", size, dir.file_count(), depth, path);
+        }
+        return;
+    }
+    println!("{0: <width$} {1: >5}  PATH", "SIZE", "DEPTH");
+    for (dir, depth) in dirs {
+        let path = shown_path(&dir.path);
+        println!("{0: <width$} {1: >5}  {2}", bytes_to_nice(dir.size()), depth, path);
+    }
+}
+
+/// List every directory below `root` of at least `min_size` that holds a file, each followed by
+/// its largest file
+fn print_largest_per_dir(root: &Dir, min_size: u64) {
+    let rows: Vec<(&Dir, &File)> = root
+        .all_dirs()
+        .into_iter()
+        .filter(|dir| dir.size() >= min_size)
+        .filter_map(|dir| Some((dir, dir.largest_file()?)))
+        .collect();
+    let width = size_column_width(rows.iter().flat_map(|(dir, file)| [dir.size(), file.size()]));
+    let mut out = io::stdout().lock();
+    let written = rows.iter().enumerate().try_for_each(|(i, (dir, file))| {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        let size = bytes_to_nice(dir.size());
+        writeln!(out, "{0: <width$}         d {1}", size, shown_path(&dir.path))?;
+        let percent = file.size() as f64 * 100.0 / max(dir.size(), 1) as f64;
+        let size = bytes_to_nice(file.size());
+        writeln!(out, "{0: <width$} {1: >6.1}% f {2}", size, percent, shown_path(&file.path))
+    });
+    if let Err(e) = written {
+        exit_on_write_error(e);
+    }
+}
+
+/// List the files and directories below `root` with a name longer than `max` bytes, longest
+/// first
+fn print_long_names(root: &Dir, max: usize) {
+    let dirs = root.all_dirs().into_iter().skip(1).map(|dir| (&dir.path, dir.size()));
+    let files = root.all_files().into_iter().map(|file| (&file.path, file.size));
+    let mut long: Vec<(usize, &String, u64)> = dirs
+        .chain(files)
+        .map(|(path, size)| (file_name(path).len(), path, size))
+        .filter(|(length, _, _)| *length > max)
+        .collect();
+    long.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    let width = size_column_width(long.iter().map(|(_, _, size)| *size));
+    println!("{0: <width$} {1: >6}  PATH", "SIZE", "LENGTH");
+    for (length, path, size) in long {
+        println!("{0: <width$} {1: >6}  {2}", bytes_to_nice(size), length, shown_path(path));
+    }
+}
+
+/// List the files with at least `min` hard links, most links first
+fn print_linked_files(root: &Dir, min: u64) {
+    let mut files: Vec<&File> = root.all_files().into_iter().filter(|f| f.links >= min).collect();
+    files.sort_by_key(|file| (Reverse(file.links), Reverse(file.size)));
+    let width = size_column_width(files.iter().map(|file| file.size));
+    println!("{0: <width$} {1: >5}  PATH", "SIZE", "LINKS");
+    for file in files {
+        let path = shown_path(&file.path);
+        println!("{0: <width$} {1: >5}  {2}", bytes_to_nice(file.size), file.links, path);
+    }
+}
+
+/// The extension of `path` without the dot, lowercased unless `case_sensitive`.
+/// Empty for files without one, including dot files like `.bashrc`.
+fn extension(path: &str, case_sensitive: bool) -> String {
+    let ext = Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .unwrap_or_default();
+    if case_sensitive {
+        ext.into_owned()
+    } else {
+        ext.to_lowercase()
+    }
+}
+
+/// Print one row per file extension with its total size and file count, largest first
+fn print_ext_rollup(root: &Dir, case_sensitive: bool) {
+    let mut totals = ExtTotals::new(case_sensitive);
+    for file in root.all_files() {
+        totals.add(&file.path, file.size);
+    }
+    print_ext_totals(totals.totals);
+}
+
+/// Print the total size and file count of every extension, largest first
+fn print_ext_totals(by_ext: HashMap<String, (u64, u64)>) {
+    let mut rows: Vec<(String, (u64, u64))> = by_ext.into_iter().collect();
+    rows.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then_with(|| a.0.cmp(&b.0)));
+    let width = size_column_width(rows.iter().map(|(_, (size, _))| *size));
+    println!("{0: <width$} {1: >8}  EXT", "SIZE", "FILES");
+    for (ext, (size, files)) in rows {
+        let ext = if ext.is_empty() { "(none)".to_owned() } else { ext };
+        println!("{0: <width$} {1: >8}  {2}", bytes_to_nice(size), files, ext);
+    }
+}
+
+/// Print the `n` largest files of every extension, extensions with the largest total first
+fn print_top_per_ext(root: &Dir, n: usize, case_sensitive: bool) {
+    let mut by_ext: HashMap<String, Vec<&File>> = HashMap::new();
+    for file in root.all_files() {
+        by_ext.entry(extension(&file.path, case_sensitive)).or_default().push(file);
+    }
+    let mut groups: Vec<(String, Vec<&File>, u64)> = by_ext
+        .into_iter()
+        .map(|(ext, files)| {
+            let total = files.iter().map(|file| file.size).sum();
+            (ext, files, total)
+        })
+        .collect();
+    groups.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    for (i, (ext, mut files, total)) in groups.into_iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let ext = if ext.is_empty() { "(none)" } else { &ext };
+        println!("{}: {} files, {}", shown_path(ext), files.len(), bytes_to_nice(total));
+        files.sort_by_key(|file| Reverse(file.size));
+        files.truncate(n);
+        let width = size_column_width(files.iter().map(|file| file.size));
+        for file in files {
+            println!("{0: <width$} {1}", bytes_to_nice(file.size), shown_path(&file.path));
+        }
+    }
+}
+
+/// A --tiered section holding files larger than `min_size`
+struct Tier {
+    label: String,
+    min_size: u64,
+}
+
+fn default_tiers() -> Vec<Tier> {
+    [
+        ("Huge (>1 GiB)", 1024 * 1024 * 1024),
+        ("Large (>100 MiB)", 100 * 1024 * 1024),
+        ("Medium (>10 MiB)", 10 * 1024 * 1024),
+        ("Small (>1 MiB)", 1024 * 1024),
+        ("Tiny", 0),
+    ]
+    .into_iter()
+    .map(|(label, min_size)| Tier {
+        label: label.to_owned(),
+        min_size,
+    })
+    .collect()
+}
+
+/// Parse a comma separated list of `SIZE` or `NAME=SIZE` boundaries.
+/// A catch-all tier is added for whatever is smaller than the last boundary.
+fn parse_tiers(spec: &str) -> Result<Vec<Tier>, String> {
+    let mut tiers = Vec::new();
+    for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (name, size_str) = match item.split_once('=') {
+            Some((name, size)) => (Some(name.trim()), size.trim()),
+            None => (None, item),
+        };
+        let min_size = parse_size(size_str)?;
+        let label = match name {
+            Some(name) => format!("{} (>{})", name, size_str),
+            None => format!(">{}", size_str),
+        };
+        tiers.push(Tier { label, min_size });
+    }
+    if tiers.is_empty() {
+        return Err("no tiers given".to_owned());
+    }
+    tiers.sort_by_key(|tier| Reverse(tier.min_size));
+    if tiers[tiers.len() - 1].min_size > 0 {
+        tiers.push(Tier {
+            label: "Rest".to_owned(),
+            min_size: 0,
+        });
+    }
+    Ok(tiers)
+}
+
+/// Print the listed files grouped by tier, largest first within each tier
+fn print_tiered(entries: &[ListEntry], tiers: &[Tier]) {
+    let mut files: Vec<&ListEntry> = entries.iter().filter(|entry| !entry.is_dir).collect();
+    files.sort_by_key(|entry| Reverse(entry.size));
+    let width = size_column_width(files.iter().map(|entry| entry.size));
+
+    let mut remaining = files.as_slice();
+    for (i, tier) in tiers.iter().enumerate() {
+        // The last tier takes everything, including empty files
+        let end = if i == tiers.len() - 1 {
+            remaining.len()
+        } else {
+            remaining
+                .iter()
+                .position(|entry| entry.size <= tier.min_size)
+                .unwrap_or(remaining.len())
+        };
+        let (in_tier, rest) = remaining.split_at(end);
+        remaining = rest;
+        if in_tier.is_empty() {
+            continue;
+        }
+        println!("{}", tier.label);
+        for entry in in_tier {
+            println!("{0: <width$} f {1}", bytes_to_nice(entry.size), shown_path(&entry.path));
+        }
+        let subtotal: u64 = in_tier.iter().map(|entry| entry.size).sum();
+        println!("Subtotal: {}", bytes_to_nice(subtotal));
+        println!();
+    }
+}
+
+/// Width of the size column, wide enough for every formatted size and at least 8
+fn size_column_width(sizes: impl Iterator<Item = u64>) -> usize {
+    sizes
+        .map(|size| bytes_to_nice(size).len())
+        .fold(8, max)
+}
+
+/// Make sure the root exists, is a directory and can be read before walking it,
+/// since the walker would otherwise just yield an error entry that gets skipped.
+fn check_root(path: &str) -> Result<(), String> {
+    // The server is only contacted by the scan itself
+    if let Some(url) = sftp::Url::parse(path) {
+        return url.map(|_| ());
+    }
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(format!("no such directory: {}", path));
+        }
+        Err(e) => return Err(format!("cannot access {}: {}", path, e)),
+    };
+    if !metadata.is_dir() {
+        return Err(format!("not a directory: {}", path));
+    }
+    if let Err(e) = fs::read_dir(path) {
+        return Err(format!("cannot access {}: {}", path, e));
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct ScanOptions {
+    ignore: bool,
+    resolve_dir_symlinks: bool,
+    no_symlinks: bool,
+    /// Count the size of directories themselves, not only their contents
+    count_dir_overhead: bool,
+    /// Add the size of NTFS alternate data streams to every file
+    include_ads: bool,
+    /// Count block devices with their capacity
+    device_sizes: bool,
+    /// Skip the directories in VCS_DIRS wherever they are
+    exclude_vcs: bool,
+    follow_links: bool,
+    max_symlink_depth: usize,
+    block_size: Option<u64>,
+    dereference_root: bool,
+    /// Overrides the hidden file filter that --ignore toggles
+    exclude_hidden: Option<bool>,
+    /// Only keep the N largest files instead of building the tree
+    top: Option<usize>,
+    /// Only sum up the files per extension instead of building the tree, for --by-ext. Whether
+    /// extensions are case sensitive.
+    ext_totals: Option<bool>,
+    /// Files from an earlier scan whose size is reused while their mtime is unchanged
+    previous: Option<HashMap<String, cache::CachedFile>>,
+    /// Entries that are not walked at all
+    exclude: Option<Arc<Excludes>>,
+    /// Absolute paths of the directories left out with --skip-dir
+    skip_dirs: Arc<Vec<PathBuf>>,
+    /// Only files matching these count towards the totals, for --count-only-matches
+    count_only: Option<Excludes>,
+    /// Files modified within --skip-newer-than
+    skip_newer: Option<RecentWindow>,
+    /// Walk with `fs::read_dir` instead of the ignore crate, for --walker std
+    std_walker: bool,
+    /// Print how long every root took to stderr
+    timing: bool,
+    /// Where finished subtrees are recorded, the children of a root are then walked one by one
+    checkpoint: Option<Mutex<checkpoint::Writer>>,
+    /// Subtrees finished by an earlier, interrupted scan, keyed by the path of the child
+    resumed: HashMap<String, Subtree>,
+}
+
+impl ScanOptions {
+    /// The options that change what a scan counts, for --scan-id
+    fn id_key(&self) -> String {
+        format!(
+            "ignore={} hidden={:?} follow={} resolve={} no-symlinks={} dir-overhead={} block={:?} deref={} top={:?} exclude={:?}{}{}{}{}{}{}",
+            self.ignore,
+            self.exclude_hidden,
+            self.follow_links,
+            self.resolve_dir_symlinks,
+            self.no_symlinks,
+            self.count_dir_overhead,
+            self.block_size,
+            self.dereference_root,
+            self.top,
+            self.exclude.as_ref().map(|exclude| exclude.patterns()),
+            // Only named when on, keeping the IDs of earlier scans
+            if self.include_ads { " ads=true" } else { "" },
+            if self.device_sizes { " device-sizes=true" } else { "" },
+            if self.exclude_vcs { " exclude-vcs=true" } else { "" },
+            if self.skip_dirs.is_empty() {
+                String::new()
+            } else {
+                format!(" skip-dirs={:?}", self.skip_dirs)
+            },
+            match &self.count_only {
+                Some(count_only) => format!(" count-only={:?}", count_only.patterns()),
+                None => String::new(),
+            },
+            match self.skip_newer {
+                Some(recent) => format!(
+                    " skip-newer-than={}s mode={}",
+                    recent.window.as_secs(),
+                    if recent.aggregate { "aggregate" } else { "display" }
+                ),
+                None => String::new(),
+            }
+        )
+    }
+}
+
+/// The window of --skip-newer-than, files modified within it are left out
+#[derive(Clone, Copy)]
+struct RecentWindow {
+    window: Duration,
+    /// Nanoseconds since the epoch, like `File::mtime`
+    since: u64,
+    /// Left out of the totals too, not only of the listing
+    aggregate: bool,
+}
+
+impl RecentWindow {
+    /// A window of `window` up to now
+    fn ending_now(window: Duration, aggregate: bool) -> RecentWindow {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        RecentWindow {
+            window,
+            since: now.saturating_sub(window).as_nanos() as u64,
+            aggregate,
+        }
+    }
+
+    /// Whether a file with `mtime` was modified within the window, never when it is not known
+    fn contains(&self, mtime: u64) -> bool {
+        mtime > self.since
+    }
+}
+
+/// Directory names left out by --exclude-vcs
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn"];
+
+fn is_vcs_dir(file_name: &OsStr, file_type: Option<fs::FileType>) -> bool {
+    let is_dir = file_type.is_some_and(|file_type| file_type.is_dir());
+    is_dir && VCS_DIRS.iter().any(|name| file_name == *name)
+}
+
+/// Whether `dir` is one of the --skip-dir directories, compared as absolute paths without
+/// resolving symlinks
+fn is_skipped_dir(dir: &Path, skip_dirs: &[PathBuf]) -> bool {
+    !skip_dirs.is_empty() && path::absolute(dir).is_ok_and(|dir| skip_dirs.contains(&dir))
+}
+
+/// A builder for walking `path`, or only its immediate child `subtree` when given
+fn walk_builder(path: &Path, options: &ScanOptions, subtree: Option<&OsStr>) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(path);
+    builder
+        .standard_filters(options.ignore)
+        .follow_links(options.follow_links);
+    if let Some(exclude_hidden) = options.exclude_hidden {
+        builder.hidden(exclude_hidden);
+    }
+    let filtered = options.exclude.is_some()
+        || !options.skip_dirs.is_empty()
+        || options.exclude_vcs
+        || options.no_symlinks
+        || options.follow_links;
+    if !filtered && subtree.is_none() {
+        return builder;
+    }
+
+    let root = path.to_path_buf();
+    let subtree = subtree.map(OsStr::to_os_string);
+    let exclude = options.exclude.clone();
+    let exclude_vcs = options.exclude_vcs;
+    let skip_dirs = options.skip_dirs.clone();
+    let no_symlinks = options.no_symlinks;
+    let follow_links = options.follow_links;
+    // Directories reached through a symlink, so each target is only descended once
+    let visited = Arc::new(Mutex::new(HashSet::new()));
+    let max_depth = options.max_symlink_depth;
+    builder.filter_entry(move |entry| {
+        let other_child = |subtree: &OsString| entry.depth() == 1 && entry.file_name() != subtree;
+        if subtree.as_ref().is_some_and(other_child) {
+            return false;
+        }
+        if let Some(exclude) = &exclude {
+            let relative = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+            if exclude.is_match(relative) {
+                log::debug!("excluded {}", entry.path().display());
+                return false;
+            }
+        }
+        if exclude_vcs && entry.depth() > 0 && is_vcs_dir(entry.file_name(), entry.file_type()) {
+            log::debug!("excluded {}", entry.path().display());
+            return false;
+        }
+        let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+        if entry.depth() > 0 && is_dir && is_skipped_dir(entry.path(), &skip_dirs) {
+            log::debug!("skipped {}", entry.path().display());
+            return false;
+        }
+        // The symlink status of an entry, never that of its target
+        if !entry.path_is_symlink() {
+            return true;
+        }
+        if no_symlinks {
+            return false;
+        }
+        if !follow_links {
+            return true;
+        }
+        if symlink_chain_len(entry.path(), max_depth) > max_depth {
+            log::warn!(
+                "not following symlink chain longer than {}: {}",
+                max_depth,
+                entry.path().display()
+            );
+            return false;
+        }
+        let target = match fs::canonicalize(entry.path()) {
+            Ok(target) => target,
+            Err(_) => return true,
+        };
+        if target.is_dir() && !visited.lock().unwrap().insert(target.clone()) {
+            log::warn!(
+                "not following symlink to already visited directory: {} -> {}",
+                entry.path().display(),
+                target.display()
+            );
+            return false;
+        }
+        true
+    });
+    builder
+}
+
+/// Number of symlinks that have to be resolved to get from `path` to a real file,
+/// counting at most `max + 1` hops
+fn symlink_chain_len(path: &Path, max: usize) -> usize {
+    let mut current = path.to_path_buf();
+    let mut hops = 0;
+    while hops <= max {
+        match fs::read_link(&current) {
+            Ok(target) => {
+                current = match current.parent() {
+                    Some(parent) => parent.join(target),
+                    None => target,
+                };
+                hops += 1;
+            }
+            Err(_) => break,
+        }
+    }
+    hops
+}
+
+/// Report walk errors caused by symlinks, anything else becomes a skipped entry
+fn walk_error(err: &ignore::Error, max_symlink_depth: usize) -> Option<SkippedEntry> {
+    match err {
+        ignore::Error::WithDepth { err, .. } => walk_error(err, max_symlink_depth),
+        ignore::Error::WithPath { path, err } => {
+            // The OS refuses to stat very long chains, so they surface as IO errors
+            if symlink_chain_len(path, max_symlink_depth) > max_symlink_depth {
+                log::warn!(
+                    "not following symlink chain longer than {}: {}",
+                    max_symlink_depth,
+                    path.display()
+                );
+                return None;
+            }
+            walk_error(err, max_symlink_depth).map(|mut skipped| {
+                skipped.path = path.to_string_lossy().into_owned();
+                skipped
+            })
+        }
+        ignore::Error::Loop { ancestor, child } => {
+            log::warn!(
+                "not following symlink cycle: {} points to an ancestor {}",
+                child.display(),
+                ancestor.display()
+            );
+            None
+        }
+        err => Some(SkippedEntry::new("", err, err.io_error())),
+    }
+}
+
+/// An entry that could not be read and is missing from the totals
+struct SkippedEntry {
+    path: String,
+    error: String,
+    kind: &'static str,
+}
+
+impl SkippedEntry {
+    fn new(path: &str, error: &dyn fmt::Display, io_error: Option<&io::Error>) -> SkippedEntry {
+        let kind = match io_error.map(|e| e.kind()) {
+            Some(io::ErrorKind::PermissionDenied) => "permission",
+            Some(io::ErrorKind::NotFound) => "not-found",
+            _ => "io",
+        };
+        SkippedEntry {
+            path: path.to_owned(),
+            error: error.to_string(),
+            kind,
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("path", self.path.as_str().into()),
+            ("error", self.error.as_str().into()),
+            ("kind", self.kind.into()),
+        ])
+    }
+}
+
+/// Running totals of the walk, cheap to read while the walk is still going
+#[derive(Default)]
+struct ScanStats {
+    files: AtomicU64,
+    bytes: AtomicU64,
+    dirs: AtomicU64,
+    /// Files measured again, or reused from the cache, by --size-only-cache
+    refreshed: AtomicU64,
+    reused: AtomicU64,
+}
+
+impl ScanStats {
+    /// Count a walked file, in these totals and in the progress of the whole run
+    fn add_file(&self, size: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+        PROGRESS.files.fetch_add(1, Ordering::Relaxed);
+        PROGRESS.bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Count a walked directory taking `own_size` bytes itself
+    fn add_dir(&self, own_size: u64) {
+        self.dirs.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(own_size, Ordering::Relaxed);
+        PROGRESS.dirs.fetch_add(1, Ordering::Relaxed);
+        PROGRESS.bytes.fetch_add(own_size, Ordering::Relaxed);
+    }
+}
+
+/// Totals of all roots walked so far, for --progress-to
+static PROGRESS: ScanStats = ScanStats {
+    files: AtomicU64::new(0),
+    bytes: AtomicU64::new(0),
+    dirs: AtomicU64::new(0),
+    refreshed: AtomicU64::new(0),
+    reused: AtomicU64::new(0),
+};
+
+fn find_all_files_and_directories(
+    dir: &mut Dir,
+    options: &ScanOptions,
+    stats: &ScanStats,
+) -> Result<ScanOutput, Box<dyn Error>> {
+    // Stats may already hold earlier roots when they are merged
+    let files_before = stats.files.load(Ordering::Relaxed);
+    let bytes_before = stats.bytes.load(Ordering::Relaxed);
+
+    let mut collected = match sftp::Url::parse(&dir.path) {
+        Some(url) => sftp_walk(&url?, dir, options, stats)?,
+        None => local_walk(dir, options, stats)?,
+    };
+
+    if let Some(top) = collected.top {
+        return Ok(ScanOutput {
+            skipped: collected.errors,
+            top: Some(top.into_sorted_vec()),
+            ext_totals: None,
+            devices: collected.devices,
+        });
+    }
+    if collected.ext_totals.is_some() {
+        return Ok(ScanOutput {
+            skipped: collected.errors,
+            top: None,
+            ext_totals: collected.ext_totals,
+            devices: collected.devices,
+        });
+    }
+
+    debug_assert_eq!(
+        stats.files.load(Ordering::Relaxed) - files_before,
+        collected.files.len() as u64
+    );
+    debug_assert_eq!(
+        stats.bytes.load(Ordering::Relaxed) - bytes_before,
+        collected.files.iter().map(|file| file.size).sum::<u64>()
+            + collected.dirs.iter().map(|(_, own_size)| own_size).sum::<u64>()
+            + dir.own_size
+    );
+
+    log::debug!(
+        "walk found {} files and {} directories, building tree",
+        collected.files.len(),
+        collected.dirs.len()
+    );
+    if let Some(count_only) = &options.count_only {
+        for file in collected.files.iter_mut() {
+            let path = Path::new(&file.path);
+            file.counted = count_only.is_match(path.strip_prefix(&dir.path).unwrap_or(path));
+        }
+    }
+    dir.build_tree(collected.dirs, collected.files);
+
+    Ok(ScanOutput {
+        skipped: collected.errors,
+        top: None,
+        ext_totals: None,
+        devices: collected.devices,
+    })
+}
+
+/// Walk the local directory `dir`
+fn local_walk(
+    dir: &mut Dir,
+    options: &ScanOptions,
+    stats: &ScanStats,
+) -> Result<Collected, Box<dyn Error>> {
+    // Canonical directories currently being expanded, used to detect symlink cycles
+    let chain = vec![fs::canonicalize(&dir.path)?];
+    let root = Path::new(&dir.path);
+    let walk_root = if options.dereference_root {
+        chain[0].as_path()
+    } else {
+        root
+    };
+
+    // The walk skips the root itself, so its own size is taken here
+    if options.count_dir_overhead {
+        dir.own_size = disk_size(fs::metadata(walk_root)?.len(), options);
+        stats.bytes.fetch_add(dir.own_size, Ordering::Relaxed);
+    }
+
+    Ok(if options.checkpoint.is_some() {
+        walk_subtrees(walk_root, root, options, stats, &chain)?
+    } else {
+        walk(walk_root, root, None, options, stats, &chain)
+    })
+}
+
+/// Walk the directory at `url` over SFTP, one directory listing at a time. The listings carry
+/// the sizes, so unlike a walk over a mount no file needs a round trip of its own. Ignore
+/// files, --skip-dir and following symlinks do not apply to remote trees.
+fn sftp_walk(
+    url: &sftp::Url,
+    dir: &mut Dir,
+    options: &ScanOptions,
+    stats: &ScanStats,
+) -> io::Result<Collected> {
+    let mut connection = sftp::Connection::open(url)?;
+    walk_session(&mut connection.session, url, dir, options, stats)
+}
+
+/// Walk the directory at `url` with an SFTP session that is already set up
+fn walk_session(
+    session: &mut sftp::Session<impl Read, impl Write>,
+    url: &sftp::Url,
+    dir: &mut Dir,
+    options: &ScanOptions,
+    stats: &ScanStats,
+) -> io::Result<Collected> {
+    let root = session.lstat(&url.path)?;
+    if !root.is_dir() {
+        return Err(io::Error::other(format!("not a directory: {}", dir.path)));
+    }
+    if options.count_dir_overhead {
+        dir.own_size = disk_size(root.size.unwrap_or(0), options);
+        stats.bytes.fetch_add(dir.own_size, Ordering::Relaxed);
+    }
+
+    let mut collected = Collected::new(options.top, options.ext_totals);
+    // Paths relative to the root
+    let mut pending = vec![String::new()];
+    while let Some(relative_dir) = pending.pop() {
+        if TIMED_OUT.load(Ordering::Relaxed) {
+            break;
+        }
+        let remote_dir = join_remote(&url.path, &relative_dir);
+        let entries = match session.read_dir(&remote_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                let shown = join_remote(&dir.path, &relative_dir);
+                collected.errors.push(SkippedEntry::new(&shown, &e, Some(&e)));
+                continue;
+            }
+        };
+        for (name, attrs) in entries {
+            let relative = join_remote(&relative_dir, &name);
+            if options.exclude.as_ref().is_some_and(|exclude| exclude.is_match(Path::new(&relative)))
+                || options.exclude_vcs && attrs.is_dir() && VCS_DIRS.contains(&name.as_str())
+                || options.exclude_hidden == Some(true) && name.starts_with('.')
+            {
+                log::debug!("excluded {}", relative);
+                continue;
+            }
+            if options.no_symlinks && attrs.is_symlink() {
+                continue;
+            }
+            let entry_path = join_remote(&dir.path, &relative);
+            let stat = attrs.to_stat();
+            collect_stat(&stat, Path::new(&entry_path), &entry_path, options, stats, &mut collected);
+            if stat.is_dir {
+                pending.push(relative);
+            }
+        }
+    }
+    Ok(collected)
+}
+
+/// `name` below the remote directory `dir`, which may be empty for the root
+fn join_remote(dir: &str, name: &str) -> String {
+    match (dir, name) {
+        (dir, "") => dir.to_owned(),
+        ("", name) => name.to_owned(),
+        (dir, name) => format!("{}/{}", dir.trim_end_matches('/'), name),
+    }
+}
+
+/// Walk `walk_root`, or only its child `subtree`, in parallel unless that is turned off
+fn walk(
+    walk_root: &Path,
+    root: &Path,
+    subtree: Option<&OsStr>,
+    options: &ScanOptions,
+    stats: &ScanStats,
+    chain: &[PathBuf],
+) -> Collected {
+    if options.std_walker {
+        return std_walk(walk_root, root, subtree, options, stats, chain);
+    }
+    let visit = |collected: &mut Collected, entry_result: Result<DirEntry, ignore::Error>| {
+        let entry = match entry_result {
+            Err(err) => {
+                collected.errors.extend(walk_error(&err, options.max_symlink_depth));
+                return;
+            }
+            Ok(de) => de,
+        };
+        // The root itself is the Dir the walk is collected into
+        if entry.depth() == 0 {
+            return;
+        }
+
+        // Report what is found below a dereferenced root under the path that was given
+        let display_path = match entry.path().strip_prefix(walk_root) {
+            Ok(relative) if walk_root != root => root.join(relative),
+            _ => entry.path().to_path_buf(),
+        };
+        visit_entry(&entry, &display_path, options, stats, chain, collected);
+    };
+
+    let mut builder = walk_builder(walk_root, options, subtree);
+    if !PARALLEL.load(Ordering::Relaxed) {
+        let mut collected = Collected::new(options.top, options.ext_totals);
+        for entry_result in builder.build().take_while(|_| !TIMED_OUT.load(Ordering::Relaxed)) {
+            visit(&mut collected, entry_result);
+        }
+        return collected;
+    }
+
+    // Every walker thread collects on its own, the buffers are only merged once it is done
+    let done = Mutex::new(Vec::new());
+    builder
+        .threads(rayon::current_num_threads())
+        .build_parallel()
+        .run(|| {
+            let mut local = ThreadCollected {
+                collected: Collected::new(options.top, options.ext_totals),
+                done: &done,
+            };
+            Box::new(move |entry_result| {
+                if TIMED_OUT.load(Ordering::Relaxed) {
+                    return WalkState::Quit;
+                }
+                visit(&mut local.collected, entry_result);
+                WalkState::Continue
+            })
+        });
+    let mut collected = Collected::new(options.top, options.ext_totals);
+    for other in done.into_inner().unwrap() {
+        collected.merge(other);
+    }
+    collected
+}
+
+/// The buffer of one thread of the parallel walk, handed over to `done` when the thread ends
+struct ThreadCollected<'a> {
+    collected: Collected,
+    done: &'a Mutex<Vec<Collected>>,
+}
+
+impl Drop for ThreadCollected<'_> {
+    fn drop(&mut self) {
+        let collected = std::mem::replace(&mut self.collected, Collected::new(None, None));
+        self.done.lock().unwrap().push(collected);
+    }
+}
+
+/// Walk `walk_root`, or only its child `subtree`, with plain `fs::read_dir` for --walker std.
+/// It knows nothing of ignore files, hidden files or following symlinks, so the options that
+/// need those are refused up front, and it always runs on one thread.
+fn std_walk(
+    walk_root: &Path,
+    root: &Path,
+    subtree: Option<&OsStr>,
+    options: &ScanOptions,
+    stats: &ScanStats,
+    chain: &[PathBuf],
+) -> Collected {
+    let mut collected = Collected::new(options.top, options.ext_totals);
+    let mut pending = vec![(walk_root.to_path_buf(), 0)];
+    while let Some((dir, depth)) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                collected.errors.push(SkippedEntry::new(&dir.to_string_lossy(), &e, Some(&e)));
+                continue;
+            }
+        };
+        for entry in entries {
+            if TIMED_OUT.load(Ordering::Relaxed) {
+                return collected;
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    collected.errors.push(SkippedEntry::new(&dir.to_string_lossy(), &e, Some(&e)));
+                    continue;
+                }
+            };
+            let file_name = entry.file_name();
+            if depth == 0 && subtree.is_some_and(|subtree| file_name != subtree) {
+                continue;
+            }
+            let entry = StdEntry {
+                path: entry.path(),
+                file_type: entry.file_type().ok(),
+            };
+            if let Some(exclude) = &options.exclude {
+                let relative = entry.path.strip_prefix(walk_root).unwrap_or(&entry.path);
+                if exclude.is_match(relative) {
+                    log::debug!("excluded {}", entry.path.display());
+                    continue;
+                }
+            }
+            if options.exclude_vcs && is_vcs_dir(&file_name, entry.file_type) {
+                log::debug!("excluded {}", entry.path.display());
+                continue;
+            }
+            let is_dir = entry.file_type.is_some_and(|file_type| file_type.is_dir());
+            if is_dir && is_skipped_dir(&entry.path, &options.skip_dirs) {
+                log::debug!("skipped {}", entry.path.display());
+                continue;
+            }
+            if options.no_symlinks && entry.path_is_symlink() {
+                continue;
+            }
+
+            let display_path = match entry.path.strip_prefix(walk_root) {
+                Ok(relative) if walk_root != root => root.join(relative),
+                _ => entry.path.clone(),
+            };
+            visit_entry(&entry, &display_path, options, stats, chain, &mut collected);
+            if entry.file_type.is_some_and(|file_type| file_type.is_dir()) {
+                pending.push((entry.path, depth + 1));
+            }
+        }
+    }
+    collected
+}
+
+/// Walk the children of `walk_root` one at a time, reusing the subtrees of an interrupted scan
+/// and recording every finished one in the checkpoint
+fn walk_subtrees(
+    walk_root: &Path,
+    root: &Path,
+    options: &ScanOptions,
+    stats: &ScanStats,
+    chain: &[PathBuf],
+) -> io::Result<Collected> {
+    let mut children: Vec<OsString> = fs::read_dir(walk_root)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+        .collect();
+    children.sort();
+
+    let mut collected = Collected::new(None, None);
+    for name in children {
+        if TIMED_OUT.load(Ordering::Relaxed) {
+            break;
+        }
+        let child = root.join(&name).to_string_lossy().into_owned();
+        if let Some(done) = options.resumed.get(&child) {
+            log::debug!("resuming with finished subtree {}", child);
+            for (own_size, dir) in &done.dirs {
+                stats.add_dir(*own_size);
+                collected.dirs.push((dir.clone(), *own_size));
+            }
+            for (size, mtime, file) in &done.files {
+                stats.add_file(*size);
+                collected.files.push(File::new(*size, *mtime, file));
+            }
+            continue;
+        }
+
+        let mut part = walk(walk_root, root, Some(&name), options, stats, chain);
+        // A walk cut short by --timeout is incomplete and must be walked again on resume
+        if !TIMED_OUT.load(Ordering::Relaxed) {
+            let subtree = Subtree {
+                files: part.files.iter().map(|f| (f.size, f.mtime, f.path.clone())).collect(),
+                dirs: part.dirs.iter().map(|(dir, own_size)| (*own_size, dir.clone())).collect(),
+            };
+            if let Some(writer) = &options.checkpoint {
+                if let Err(e) = writer.lock().unwrap().add(&child, &subtree) {
+                    log::warn!("cannot write checkpoint: {}", e);
+                }
+            }
+        }
+        collected.files.append(&mut part.files);
+        collected.dirs.append(&mut part.dirs);
+        collected.errors.append(&mut part.errors);
+        add_device_bytes(&mut collected.devices, part.devices.drain());
+    }
+    Ok(collected)
+}
+
+struct ScanOutput {
+    skipped: Vec<SkippedEntry>,
+    /// The largest files, largest first, when only the top N were kept
+    top: Option<Vec<File>>,
+    /// Totals per extension, when only those were kept
+    ext_totals: Option<ExtTotals>,
+    /// Bytes found on every device id, to tell when a scan crossed into other filesystems
+    devices: HashMap<u64, u64>,
+}
+
+/// Everything found by a walk, merged across the walker threads
+struct Collected {
+    files: Vec<File>,
+    /// Paths with the directory's own size, 0 unless directory overhead is counted
+    dirs: Vec<(String, u64)>,
+    errors: Vec<SkippedEntry>,
+    /// Replaces `files` and `dirs` when only the N largest files are wanted
+    top: Option<TopFiles>,
+    /// Replaces `files` and `dirs` when only the totals per extension are wanted
+    ext_totals: Option<ExtTotals>,
+    /// Bytes found on every device id
+    devices: HashMap<u64, u64>,
+}
+
+impl Collected {
+    fn new(top: Option<usize>, ext_totals: Option<bool>) -> Collected {
+        Collected {
+            files: Vec::new(),
+            dirs: Vec::new(),
+            errors: Vec::new(),
+            top: top.map(TopFiles::new),
+            ext_totals: ext_totals.map(ExtTotals::new),
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Whether files and directories are collected to build the tree
+    fn builds_tree(&self) -> bool {
+        self.top.is_none() && self.ext_totals.is_none()
+    }
+
+    /// Add what another thread of the same walk found
+    fn merge(&mut self, mut other: Collected) {
+        self.files.append(&mut other.files);
+        self.dirs.append(&mut other.dirs);
+        self.errors.append(&mut other.errors);
+        if let (Some(top), Some(other)) = (self.top.as_mut(), other.top.take()) {
+            top.merge(other);
+        }
+        add_device_bytes(&mut self.devices, other.devices.drain());
+        if let (Some(totals), Some(other)) = (self.ext_totals.as_mut(), other.ext_totals.take()) {
+            totals.merge(other);
+        }
+    }
+}
+
+/// Total size and number of files per extension, memory grows with the number of extensions
+/// rather than files
+struct ExtTotals {
+    case_sensitive: bool,
+    totals: HashMap<String, (u64, u64)>,
+}
+
+impl ExtTotals {
+    fn new(case_sensitive: bool) -> ExtTotals {
+        ExtTotals {
+            case_sensitive,
+            totals: HashMap::new(),
+        }
+    }
 
-    let path_str = match matches.value_of("directory") {
-        None => {
-            println!("Error: must provide a directory");
-            return;
+    fn add(&mut self, path: &str, size: u64) {
+        let totals = self.totals.entry(extension(path, self.case_sensitive)).or_default();
+        totals.0 += size;
+        totals.1 += 1;
+    }
+
+    fn merge(&mut self, other: ExtTotals) {
+        for (ext, (size, files)) in other.totals {
+            let totals = self.totals.entry(ext).or_default();
+            totals.0 += size;
+            totals.1 += files;
         }
-        Some(path_str) => path_str,
-    };
+    }
+}
 
-    let percent = match matches.value_of("percent") {
-        Some(percent_str) => match f64::from_str(percent_str) {
-            Ok(pct) => pct,
-            Err(_) => panic!(),
-        },
-        None => panic!(),
-    };
+/// The N largest files seen so far, in a min-heap so the smallest is evicted first
+struct TopFiles {
+    capacity: usize,
+    heap: BinaryHeap<Reverse<(u64, String)>>,
+}
 
-    let ignore = match matches.value_of("ignore") {
-        Some(ignore) => bool::from_str(ignore).unwrap_or_else(|_| false),
-        None => false,
-    };
+impl TopFiles {
+    fn new(capacity: usize) -> TopFiles {
+        TopFiles {
+            capacity,
+            heap: BinaryHeap::with_capacity(capacity + 1),
+        }
+    }
 
-    let expanded_path_str = match shellexpand::full(path_str) {
-        Ok(eps) => eps,
-        Err(e) => {
-            panic!("Error: {}", e);
+    fn push(&mut self, size: u64, path: &str) {
+        if self.heap.len() == self.capacity {
+            match self.heap.peek() {
+                Some(Reverse((smallest, _))) if *smallest < size => {
+                    self.heap.pop();
+                }
+                _ => return,
+            }
         }
-    };
-    let mut base_dir = Dir::new(&expanded_path_str);
+        self.heap.push(Reverse((size, path.to_owned())));
+    }
 
-    match find_all_files_and_directories(&mut base_dir, ignore) {
-        Ok(_) => (),
-        Err(e) => {
-            panic!("Error: {}", e);
+    fn merge(&mut self, other: TopFiles) {
+        for Reverse((size, path)) in other.heap {
+            self.push(size, &path);
         }
     }
 
-    base_dir.calc_size();
-    let total_size = base_dir.size();
-    let largest_child = base_dir.largest_child();
-    base_dir.print((largest_child as f64 * (percent / 100.0)) as u64);
+    /// Largest first
+    fn into_sorted_vec(self) -> Vec<File> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((size, path))| File::new(size, 0, &path))
+            .collect()
+    }
+}
 
-    println!("Total size: {}", bytes_to_nice(total_size));
-    println!("Largest child: {}", bytes_to_nice(largest_child));
+/// What visit_entry needs of an entry, found by either walker of --walker
+trait WalkEntry {
+    fn path(&self) -> &Path;
+
+    /// Whether the entry itself is a symlink, never its target
+    fn path_is_symlink(&self) -> bool;
+
+    /// The metadata of the entry, a failure is reported under `entry_path`
+    fn metadata(&self, entry_path: &str) -> Result<fs::Metadata, SkippedEntry>;
 }
 
-fn find_all_files_and_directories(dir: &mut Dir, ignore: bool) -> Result<(), Box<dyn Error>> {
-    // let path = Path::new(&dir.path);
-    // let read_dir = read_dir(path)?;
+impl WalkEntry for DirEntry {
+    fn path(&self) -> &Path {
+        DirEntry::path(self)
+    }
 
-    println!("Ignore {}", ignore);
+    fn path_is_symlink(&self) -> bool {
+        DirEntry::path_is_symlink(self)
+    }
 
-    dir.children =
-        // read_dir
-        WalkBuilder::new(&dir.path).standard_filters(ignore).build()
-        .map(|f| Arc::new(f))
-        .par_bridge()
-        .fold(
-            || Arc::new(Mutex::new(Vec::<FsItem>::new())),
-            |children, entry_result| {
-                let entry = match entry_result.as_ref() {
-                    Err(_) => return children,
-                    Ok(de) => de,
-                };
+    fn metadata(&self, entry_path: &str) -> Result<fs::Metadata, SkippedEntry> {
+        DirEntry::metadata(self).map_err(|e| {
+            let mut skipped = SkippedEntry::new(entry_path, &e, e.io_error());
+            if let ignore::Error::WithPath { err, .. } = &e {
+                skipped.error = err.to_string();
+            }
+            skipped
+        })
+    }
+}
 
-                let path = entry.path();
-                let entry_path: &str = match path.to_str() {
-                    None => panic!("oops"),
-                    Some(t) => t,
-                };
+/// An entry found by std_walk
+struct StdEntry {
+    path: PathBuf,
+    file_type: Option<fs::FileType>,
+}
 
-                let metadata = match entry.metadata() {
-                    Ok(metadata) => metadata,
-                    Err(e) => {
-                        println!("{e}");
-                        return children;
-                    }
+impl WalkEntry for StdEntry {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn path_is_symlink(&self) -> bool {
+        self.file_type.is_some_and(|file_type| file_type.is_symlink())
+    }
+
+    fn metadata(&self, entry_path: &str) -> Result<fs::Metadata, SkippedEntry> {
+        fs::symlink_metadata(&self.path).map_err(|e| SkippedEntry::new(entry_path, &e, Some(&e)))
+    }
+}
+
+/// Turn a walked entry into files and directories. `display_path` is where the entry is
+/// reported, which differs from the real path inside a resolved directory symlink.
+fn visit_entry(
+    entry: &impl WalkEntry,
+    display_path: &Path,
+    options: &ScanOptions,
+    stats: &ScanStats,
+    chain: &[PathBuf],
+    collected: &mut Collected,
+) {
+    // A name that is not UTF-8 is reported with replacement characters, its size still counts
+    let entry_path = display_path.to_string_lossy();
+    let entry_path = &*entry_path;
+
+    if options.resolve_dir_symlinks && entry.path_is_symlink() {
+        if let Ok(target) = fs::metadata(entry.path()) {
+            if target.is_dir() {
+                resolve_dir_symlink(entry.path(), display_path, options, stats, chain, collected);
+                return;
+            }
+        }
+    }
+
+    match entry.metadata(entry_path).map(|metadata| Stat::from_metadata(&metadata)) {
+        Ok(stat) => collect_stat(&stat, entry.path(), entry_path, options, stats, collected),
+        Err(skipped) => collected.errors.push(skipped),
+    }
+}
+
+/// Count an entry of `stat` at `path` and collect it as it is reported, under `entry_path`
+fn collect_stat(
+    stat: &Stat,
+    path: &Path,
+    entry_path: &str,
+    options: &ScanOptions,
+    stats: &ScanStats,
+    collected: &mut Collected,
+) {
+    if stat.is_dir {
+        let own_size = if options.count_dir_overhead {
+            disk_size(stat.len, options)
+        } else {
+            0
+        };
+        stats.add_dir(own_size);
+        *collected.devices.entry(stat.device).or_default() += own_size;
+        if collected.builds_tree() {
+            collected.dirs.push((entry_path.to_owned(), own_size));
+        }
+    } else {
+        let mtime = stat.mtime;
+        let recent = options.skip_newer.filter(|window| window.contains(mtime));
+        if recent.is_some_and(|window| window.aggregate) {
+            log::debug!("skipped recently modified {}", entry_path);
+            return;
+        }
+        let cached = options.previous.as_ref().and_then(|previous| previous.get(entry_path));
+        let size = match cached {
+            Some(cached) if cached.mtime == mtime => {
+                stats.reused.fetch_add(1, Ordering::Relaxed);
+                cached.size
+            }
+            _ => {
+                if options.previous.is_some() {
+                    stats.refreshed.fetch_add(1, Ordering::Relaxed);
+                }
+                let len = if options.include_ads {
+                    stat.len + stream_bytes(path, entry_path)
+                } else if options.device_sizes && stat.is_block_device {
+                    device_bytes(path, entry_path)
+                } else {
+                    stat.len
                 };
-                if !metadata.is_dir() {
-                    let new_file = File::new(metadata.len(), entry_path);
-                    children.lock().unwrap().push(FsItem::File(new_file));
+                disk_size(len, options)
+            }
+        };
+        stats.add_file(size);
+        *collected.devices.entry(stat.device).or_default() += size;
+        log::trace!("file {} {}", size, entry_path);
+        match (collected.top.as_mut(), collected.ext_totals.as_mut()) {
+            // Counted, but never listed
+            (Some(_), _) if recent.is_some() => {}
+            (Some(top), _) => top.push(size, entry_path),
+            (None, Some(totals)) => totals.add(entry_path, size),
+            (None, None) => {
+                let mut file = File::new(size, mtime, entry_path);
+                file.links = stat.links;
+                if file.links > 1 {
+                    file.inode = stat.inode;
                 }
-                children
-            },
-        )
-        .reduce(
-            || Arc::new(Mutex::new(Vec::<FsItem>::new())),
-            |acc, val| {
-                acc.lock().unwrap().append(val.lock().unwrap().borrow_mut());
-                acc
-            },
-        );
+                collected.files.push(file);
+            }
+        }
+    }
+}
 
-    Ok(())
+/// Capacity of a block device, for --device-sizes. Zero when it can not be opened or asked.
+fn device_bytes(path: &Path, entry_path: &str) -> u64 {
+    match fsusage::block_device_size(path) {
+        Ok(size) => size,
+        Err(e) => {
+            log::warn!("cannot read the capacity of {}: {}", entry_path, e);
+            0
+        }
+    }
+}
+
+/// Total size of the alternate data streams of a file, for --include-ads. Zero when they can
+/// not be read, the file then counts with its plain size.
+fn stream_bytes(path: &Path, entry_path: &str) -> u64 {
+    match ads::streams(path) {
+        Ok(streams) => streams
+            .iter()
+            .map(|stream| {
+                log::info!("stream {}:{} {}", entry_path, stream.name, stream.size);
+                stream.size
+            })
+            .sum(),
+        Err(e) => {
+            log::debug!("cannot read the streams of {}: {}", entry_path, e);
+            0
+        }
+    }
+}
+
+fn add_device_bytes(devices: &mut HashMap<u64, u64>, more: impl IntoIterator<Item = (u64, u64)>) {
+    for (device, bytes) in more {
+        *devices.entry(device).or_default() += bytes;
+    }
+}
+
+/// The size counted for `len` bytes, rounded up to whole blocks when --block-size is given
+fn disk_size(len: u64, options: &ScanOptions) -> u64 {
+    match options.block_size {
+        Some(block_size) => round_up_to_block(len, block_size),
+        None => len,
+    }
+}
+
+/// Scan the target of a directory symlink, reporting everything below `display_path`.
+fn resolve_dir_symlink(
+    link: &Path,
+    display_path: &Path,
+    options: &ScanOptions,
+    stats: &ScanStats,
+    chain: &[PathBuf],
+    collected: &mut Collected,
+) {
+    let target = match fs::canonicalize(link) {
+        Ok(target) => target,
+        Err(e) => {
+            collected
+                .errors
+                .push(SkippedEntry::new(&link.to_string_lossy(), &e, Some(&e)));
+            return;
+        }
+    };
+    let link_dir = link
+        .parent()
+        .and_then(|parent| fs::canonicalize(parent).ok());
+    let is_cycle = chain.iter().chain(link_dir.iter()).any(|p| p.starts_with(&target));
+    if is_cycle {
+        log::warn!("not resolving symlink cycle: {} -> {}", link.display(), target.display());
+        return;
+    }
+
+    let mut chain = chain.to_vec();
+    chain.push(target.clone());
+
+    for entry in walk_builder(&target, options, None).build() {
+        if TIMED_OUT.load(Ordering::Relaxed) {
+            break;
+        }
+        let entry = match entry {
+            Err(err) => {
+                collected.errors.extend(walk_error(&err, options.max_symlink_depth));
+                continue;
+            }
+            Ok(entry) => entry,
+        };
+        let relative = match entry.path().strip_prefix(&target) {
+            Ok(relative) => relative,
+            Err(_) => continue,
+        };
+        let path = if relative.as_os_str().is_empty() {
+            display_path.to_path_buf()
+        } else {
+            display_path.join(relative)
+        };
+        visit_entry(&entry, &path, options, stats, &chain, collected);
+    }
 }
 
 enum FsItem {
     File(File),
+    Dir(Dir),
 }
 
 impl FsItem {
-    fn as_file_data(&mut self) -> &mut dyn FileData {
+    fn as_file_data(&self) -> &dyn FileData {
+        match self {
+            FsItem::File(file) => file,
+            FsItem::Dir(dir) => dir,
+        }
+    }
+
+    fn as_file_data_mut(&mut self) -> &mut dyn FileData {
         match self {
             FsItem::File(file) => file,
+            FsItem::Dir(dir) => dir,
         }
     }
 }
@@ -150,82 +5050,415 @@ trait FileData {
     fn calc_size(&mut self);
     fn size(&self) -> u64;
     fn largest_child(&self) -> u64;
-    fn path(&self) -> Cow<str>;
-    fn is_file(&self) -> bool;
-    fn print(&self, cutoff: u64);
+    fn file_count(&self) -> u64;
+    fn list<'a>(&'a self, filter: &ListFilter, entries: &mut Vec<ListEntry<'a>>);
+    /// Nested `{name, size, children}` object, files have no children
+    fn to_treemap(&self, name: &str) -> Json;
+}
+
+/// Files that need to share a name pattern to be collapsed by --collapse-pattern
+const COLLAPSE_MIN_FILES: u64 = 3;
+
+/// `name` with every run of digits, and every hash like run of 8 or more hex digits, replaced
+/// by as many `#`, so `chunk-0042.dat` and `chunk-0043.dat` both become `chunk-####.dat`
+fn name_pattern(name: &str) -> String {
+    let mut pattern = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(c) = rest.chars().next() {
+        let hex_len = rest.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(rest.len());
+        let hex = &rest[..hex_len];
+        if hex_len >= 8 && hex.contains(|c: char| c.is_ascii_digit()) {
+            pattern.push_str(&"#".repeat(hex_len));
+            rest = &rest[hex_len..];
+        } else if c.is_ascii_digit() {
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            pattern.push_str(&"#".repeat(digits));
+            rest = &rest[digits..];
+        } else {
+            pattern.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    pattern
+}
+
+/// `path` as the text output shows it, with control characters like newlines and terminal escape
+/// sequences written as C-style escapes, e.g. `\n` or `\x1b`, unless --raw-names is given
+fn shown_path(path: &str) -> Cow<'_, str> {
+    if RAW_NAMES.load(Ordering::Relaxed) || !path.chars().any(char::is_control) {
+        return path.into();
+    }
+    let mut shown = String::with_capacity(path.len() + 8);
+    for c in path.chars() {
+        match c {
+            '\n' => shown.push_str("\\n"),
+            '\t' => shown.push_str("\\t"),
+            '\r' => shown.push_str("\\r"),
+            // Control characters are all below U+0100, C0, DEL and C1 alike
+            c if c.is_control() => shown.push_str(&format!("\\x{:02x}", c as u32)),
+            c => shown.push(c),
+        }
+    }
+    shown.into()
+}
+
+/// Order of `a` and `b` as paths, every directory right before what is in it. Comparing the
+/// bytes with the separators as the lowest byte is the same as comparing the components.
+fn path_order(a: &str, b: &str) -> std::cmp::Ordering {
+    let key = |byte: u8| if path::is_separator(byte as char) { 0 } else { byte };
+    a.bytes().map(key).cmp(b.bytes().map(key))
+}
+
+/// `path` with forward slashes, for --normalize-paths. Only the platform's own separator is
+/// replaced, a backslash in a Unix file name is part of the name and kept.
+fn normalized_path(path: &str) -> Cow<'_, str> {
+    with_forward_slashes(path, path::MAIN_SEPARATOR)
+}
+
+fn with_forward_slashes(path: &str, separator: char) -> Cow<'_, str> {
+    if separator != '/' && path.contains(separator) {
+        path.replace(separator, "/").into()
+    } else {
+        path.into()
+    }
+}
+
+/// Last path component, used as the treemap node name
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
 }
 
 struct Dir {
     path: String,
-    children: Arc<Mutex<Vec<FsItem>>>,
+    children: Vec<FsItem>,
     size: Option<u64>,
+    files: u64,
+    /// Bytes of the files directly inside, set by `calc_size`
+    self_size: u64,
+    /// Size of the directory itself, only counted with --count-dir-overhead
+    own_size: u64,
 }
 
 impl Dir {
     fn new(path: &str) -> Dir {
         Dir {
             path: path.to_owned(),
-            children: Arc::new(Mutex::new(Vec::new())),
+            children: Vec::new(),
             size: None,
+            files: 0,
+            self_size: 0,
+            own_size: 0,
+        }
+    }
+
+    /// Build the nested tree below this directory from the flat walk results.
+    /// Sorting by path puts every directory right before its contents, so the
+    /// tree can be assembled with a stack of the directories currently open.
+    fn build_tree(&mut self, dirs: Vec<(String, u64)>, files: Vec<File>) {
+        enum Entry {
+            Dir(String, u64),
+            File(File),
+        }
+        impl Entry {
+            fn path(&self) -> &str {
+                match self {
+                    Entry::Dir(path, _) => path,
+                    Entry::File(file) => &file.path,
+                }
+            }
+        }
+
+        let mut entries: Vec<Entry> = dirs
+            .into_iter()
+            .map(|(dir, own_size)| Entry::Dir(dir, own_size))
+            .chain(files.into_iter().map(Entry::File))
+            .collect();
+        // The parallel walk finds entries in no particular order, comparing the bytes is much
+        // quicker than comparing them as paths component by component
+        entries.sort_unstable_by(|a, b| path_order(a.path(), b.path()));
+
+        let mut stack = vec![Dir::new(&self.path)];
+        for entry in entries {
+            let path = Path::new(entry.path());
+            while stack.len() > 1 && !path.starts_with(&stack[stack.len() - 1].path) {
+                Dir::close(&mut stack);
+            }
+            let parent = path.parent().unwrap_or(Path::new(""));
+            let top = Path::new(&stack[stack.len() - 1].path);
+            if parent != top {
+                // Directories that were not walked themselves, e.g. below a resolved symlink
+                let mut current = top.to_path_buf();
+                for component in parent.strip_prefix(top).unwrap_or(Path::new("")).iter() {
+                    current = current.join(component);
+                    stack.push(Dir::new(&current.to_string_lossy()));
+                }
+            }
+            match entry {
+                Entry::File(file) => {
+                    let last = stack.len() - 1;
+                    stack[last].children.push(FsItem::File(file));
+                }
+                Entry::Dir(path, own_size) => {
+                    let mut dir = Dir::new(&path);
+                    dir.own_size = own_size;
+                    stack.push(dir);
+                }
+            }
+        }
+        while stack.len() > 1 {
+            Dir::close(&mut stack);
+        }
+        self.children = stack.pop().unwrap().children;
+    }
+
+    /// The children in the order `filter` lists them in. They are kept in path order, the
+    /// sort is stable so ties stay that way.
+    fn ordered_children(&self, filter: &ListFilter) -> Vec<&FsItem> {
+        let mut children: Vec<&FsItem> = self.children.iter().collect();
+        if filter.order != ChildOrder::Path {
+            let dirs_first = filter.order == ChildOrder::DirsFirst;
+            children.sort_by_key(|child| {
+                let child_data = child.as_file_data();
+                let (first, second) = if filter.order_by_count {
+                    (child_data.file_count(), child_data.size())
+                } else {
+                    (child_data.size(), child_data.file_count())
+                };
+                (matches!(child, FsItem::Dir(_)) != dirs_first, Reverse(first), Reverse(second))
+            });
+        }
+        children
+    }
+
+    /// Move the innermost open directory into its parent
+    fn close(stack: &mut Vec<Dir>) {
+        let dir = stack.pop().unwrap();
+        let last = stack.len() - 1;
+        stack[last].children.push(FsItem::Dir(dir));
+    }
+
+    /// Number and total size of the direct files per name pattern, for the patterns shared by
+    /// enough files to be collapsed by --collapse-pattern
+    fn pattern_groups(&self) -> HashMap<String, (u64, u64)> {
+        let mut groups: HashMap<String, (u64, u64)> = HashMap::new();
+        for child in self.children.iter() {
+            if let FsItem::File(file) = child {
+                let pattern = name_pattern(file_name(&file.path));
+                if pattern.contains('#') {
+                    let group = groups.entry(pattern).or_default();
+                    group.0 += 1;
+                    group.1 += file.size;
+                }
+            }
+        }
+        groups.retain(|_, (count, _)| *count >= COLLAPSE_MIN_FILES);
+        groups
+    }
+
+    /// Files directly inside this directory, not counting subdirectories
+    fn direct_file_count(&self) -> u64 {
+        self.children
+            .iter()
+            .filter(|child| matches!(child, FsItem::File(_)))
+            .count() as u64
+    }
+
+    /// The direct child taking up the most space
+    fn largest_item(&self) -> Option<&FsItem> {
+        self.children
+            .iter()
+            .max_by_key(|child| child.as_file_data().size())
+    }
+
+    /// The largest file at any depth below this directory, what `largest_child` is the size of.
+    /// The first in path order wins a tie.
+    fn largest_file(&self) -> Option<&File> {
+        self.children
+            .iter()
+            .filter_map(|child| match child {
+                FsItem::File(file) => Some(file),
+                FsItem::Dir(dir) => dir.largest_file(),
+            })
+            .fold(None, |largest: Option<&File>, file| match largest {
+                Some(largest) if largest.size() >= file.size() => Some(largest),
+                _ => Some(file),
+            })
+    }
+
+    /// Every file below this directory
+    fn all_files(&self) -> Vec<&File> {
+        let mut files = Vec::new();
+        for child in self.children.iter() {
+            match child {
+                FsItem::File(file) => files.push(file),
+                FsItem::Dir(dir) => files.append(&mut dir.all_files()),
+            }
+        }
+        files
+    }
+
+    /// A row for this directory and everything below it, for --format sqlite
+    fn sqlite_rows(&self, root: &Rc<str>, rows: &mut Vec<sqlite::Row>) {
+        rows.push(sqlite::Row {
+            root: Rc::clone(root),
+            path: self.path.clone(),
+            size: self.size(),
+            is_file: false,
+            mtime: None,
+            links: None,
+        });
+        for child in self.children.iter() {
+            match child {
+                FsItem::File(file) => rows.push(sqlite::Row {
+                    root: Rc::clone(root),
+                    path: file.path.clone(),
+                    size: file.size,
+                    is_file: true,
+                    mtime: (file.mtime > 0).then_some(file.mtime / 1_000_000_000),
+                    links: Some(file.links),
+                }),
+                FsItem::Dir(dir) => dir.sqlite_rows(root, rows),
+            }
         }
     }
+
+    /// This directory and every directory below it
+    fn all_dirs(&self) -> Vec<&Dir> {
+        let mut dirs = vec![self];
+        for child in self.children.iter() {
+            if let FsItem::Dir(dir) = child {
+                dirs.append(&mut dir.all_dirs());
+            }
+        }
+        dirs
+    }
 }
 
 impl FileData for Dir {
     fn calc_size(&mut self) {
-        let mut total_size: u64 = 0;
-        for child in self.children.lock().unwrap().iter_mut() {
-            let fd = child.as_file_data();
+        let mut total_size: u64 = self.own_size;
+        let mut total_files: u64 = 0;
+        let mut self_size: u64 = 0;
+        for child in self.children.iter_mut() {
+            if let FsItem::File(file) = child {
+                self_size += file.size();
+            }
+            let fd = child.as_file_data_mut();
             fd.calc_size();
             total_size += fd.size();
+            total_files += fd.file_count();
         }
         self.size = Some(total_size);
+        self.files = total_files;
+        self.self_size = self_size;
     }
 
     fn size(&self) -> u64 {
-        self.size.unwrap_or_else(|| 0)
+        self.size.unwrap_or(0)
     }
 
     fn largest_child(&self) -> u64 {
-        self.children.lock().unwrap().iter_mut().fold(0, |v, f| {
+        self.children.iter().fold(0, |v, f| {
             let fd = f.as_file_data();
-            return max(v, fd.largest_child());
+            max(v, fd.largest_child())
         })
     }
 
-    fn path(&self) -> Cow<str> {
-        return Cow::Borrowed(&self.path);
-    }
-
-    fn is_file(&self) -> bool {
-        return false;
+    fn file_count(&self) -> u64 {
+        self.files
     }
 
-    fn print(&self, cutoff: u64) {
+    fn list<'a>(&'a self, filter: &ListFilter, entries: &mut Vec<ListEntry<'a>>) {
         let sz = self.size();
-        if sz >= cutoff {
-            println!("{0: <8} d {1}", bytes_to_nice(sz), self.path);
-            self.children
-                .lock()
-                .unwrap()
-                .iter_mut()
-                .map(|fsi| fsi.as_file_data())
-                .for_each(|f| f.print(cutoff));
+        if sz >= filter.descend {
+            if filter.cutoff.admits(sz) && self.files >= filter.min_files {
+                entries.push(ListEntry {
+                    path: Cow::Borrowed(&self.path),
+                    size: sz,
+                    is_dir: true,
+                    files: self.files,
+                    self_size: self.self_size,
+                });
+            }
+            let groups = if filter.collapse {
+                self.pattern_groups()
+            } else {
+                HashMap::new()
+            };
+            let mut listed_groups = HashSet::new();
+            for child in self.ordered_children(filter) {
+                match child {
+                    FsItem::Dir(dir) if !filter.shows_child_dir(dir.size(), sz) => {}
+                    FsItem::File(file) if !groups.is_empty() => {
+                        let pattern = name_pattern(file_name(&file.path));
+                        match groups.get(&pattern) {
+                            Some(&(count, total)) => {
+                                if listed_groups.insert(pattern.clone())
+                                    && filter.shows_file(&pattern, total)
+                                {
+                                    let path = Path::new(&self.path).join(&pattern);
+                                    entries.push(ListEntry {
+                                        path: format!("{} ({} files)", path.display(), count).into(),
+                                        size: total,
+                                        is_dir: false,
+                                        files: count,
+                                        self_size: total,
+                                    });
+                                }
+                            }
+                            None => file.list(filter, entries),
+                        }
+                    }
+                    child => child.as_file_data().list(filter, entries),
+                }
+            }
         }
     }
+
+    fn to_treemap(&self, name: &str) -> Json {
+        let children = self
+            .children
+            .iter()
+            .map(|child| match child {
+                FsItem::Dir(dir) => dir.to_treemap(file_name(&dir.path)),
+                FsItem::File(file) => file.to_treemap(file_name(&file.path)),
+            })
+            .collect();
+        Json::Object(vec![
+            ("name", name.into()),
+            ("size", self.size().into()),
+            ("children", Json::Array(children)),
+        ])
+    }
 }
 
 #[derive(Clone)]
 struct File {
     size: u64,
+    /// Nanoseconds since the epoch, 0 when not known
+    mtime: u64,
     path: String,
+    /// Number of hard links, 1 where it is not known
+    links: u64,
+    /// Device and inode of a file with more than one hard link, for --unique-inodes
+    inode: Option<(u64, u64)>,
+    /// Whether the size adds to the totals, false for the files --count-only-matches does not
+    /// match
+    counted: bool,
 }
 
 impl File {
-    fn new(size: u64, path: &str) -> File {
+    fn new(size: u64, mtime: u64, path: &str) -> File {
         File {
             size,
+            mtime,
             path: path.to_owned(),
+            links: 1,
+            inode: None,
+            counted: true,
         }
     }
 }
@@ -234,35 +5467,499 @@ impl FileData for File {
     fn calc_size(&mut self) {}
 
     fn size(&self) -> u64 {
-        self.size
+        if self.counted {
+            self.size
+        } else {
+            0
+        }
     }
 
     fn largest_child(&self) -> u64 {
-        self.size
+        self.size()
     }
 
-    fn path(&self) -> Cow<str> {
-        return Cow::Borrowed(&self.path);
+    fn file_count(&self) -> u64 {
+        1
     }
 
-    fn is_file(&self) -> bool {
-        return true;
+    fn list<'a>(&'a self, filter: &ListFilter, entries: &mut Vec<ListEntry<'a>>) {
+        if filter.shows_file(&self.path, self.size) && !filter.hides_recent(self.mtime) {
+            let path = match (self.counted, filter.mark_uncounted) {
+                (true, _) => Cow::Borrowed(self.path.as_str()),
+                (false, true) => format!("{} (not counted)", self.path).into(),
+                (false, false) => return,
+            };
+            entries.push(ListEntry {
+                path,
+                size: self.size,
+                is_dir: false,
+                files: 1,
+                self_size: self.size,
+            });
+        }
     }
 
-    fn print(&self, cutoff: u64) {
-        if self.size > cutoff {
-            println!("{0: <8} f {1}", bytes_to_nice(self.size), self.path);
-        }
+    fn to_treemap(&self, name: &str) -> Json {
+        Json::Object(vec![("name", name.into()), ("size", self.size.into())])
     }
 }
 
+/// Round `size` up to a whole number of blocks, empty files take no blocks
+fn round_up_to_block(size: u64, block_size: u64) -> u64 {
+    size.div_ceil(block_size) * block_size
+}
+
+/// Parse a human readable size like `512`, `10K`, `100M` or `1.5GiB`.
+/// Units are binary, `K`, `KB` and `KiB` all mean 1024 bytes.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number = f64::from_str(number).map_err(|_| format!("invalid size: {}", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        "t" | "tb" | "tib" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size unit: {}", s)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parse a duration like `90m`, `12h`, `7d` or `2w`, for --skip-newer-than
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number = f64::from_str(number).map_err(|_| format!("invalid duration: {}", s))?;
+    let seconds: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit, expected s, m, h, d or w: {}", s)),
+    };
+    Duration::try_from_secs_f64(number * seconds as f64)
+        .map_err(|e| format!("invalid duration {}: {}", s, e))
+}
+
+/// A size in the --unit, or in the largest unit up to GiB it exceeds
 fn bytes_to_nice(bytes: u64) -> String {
-    if bytes > 1024 * 1024 * 1024 {
-        return format!("{} GiB", bytes / (1024 * 1024 * 1024));
-    } else if bytes > 1024 * 1024 {
-        return format!("{} MiB", bytes / (1024 * 1024));
-    } else if bytes > 1024 {
-        return format!("{} KiB", bytes / 1024);
-    }
-    return format!("{} B", bytes);
+    let format = SIZE_FORMAT.get().unwrap_or(&DEFAULT_SIZE_FORMAT);
+    match format.style {
+        SizeStyle::Units => {}
+        SizeStyle::Bytes => return group_thousands(bytes),
+        SizeStyle::Blocks => return bytes.div_ceil(1024).to_string(),
+    }
+    let units = format.units;
+    let unit = match format.unit {
+        None => (1..4).rev().find(|i| bytes > units[*i].1).unwrap_or(0),
+        Some(unit) => unit,
+    };
+    let (name, factor) = units[unit];
+    match format.precision {
+        _ if factor == 1 => format!("{} {}", bytes, name),
+        None => format!("{} {}", bytes / factor, name),
+        Some(precision) => format!("{:.*} {}", precision, bytes as f64 / factor as f64, name),
+    }
+}
+
+/// `1234567` as `1,234,567`
+fn group_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tree below /t of files with the given paths and sizes, with its sizes calculated
+    fn tree(files: &[(&str, u64)]) -> Dir {
+        let mut root = Dir::new("/t");
+        let files = files.iter().map(|(path, size)| File::new(*size, 0, path)).collect();
+        root.build_tree(Vec::new(), files);
+        root.calc_size();
+        root
+    }
+
+    /// A fresh directory below the system temp dir, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            static NEXT: AtomicU64 = AtomicU64::new(0);
+            let id = NEXT.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!("lff-unit-{}-{}", process::id(), id));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn cutoff(relative: u64) -> Cutoff {
+        Cutoff {
+            relative,
+            absolute: None,
+            either: false,
+        }
+    }
+
+    fn filter(cutoff: Cutoff) -> ListFilter {
+        ListFilter::new(cutoff, 0, HashMap::new(), false, None, false, false)
+    }
+
+    /// Paths of the listed entries, sorted
+    fn listed(root: &Dir, filter: &ListFilter) -> Vec<String> {
+        let mut entries = Vec::new();
+        root.list(filter, &mut entries);
+        let mut paths: Vec<String> = entries.iter().map(|entry| entry.path.to_string()).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn files_and_dirs_at_the_cutoff_are_shown() {
+        let root = tree(&[
+            ("/t/below", 99),
+            ("/t/at", 100),
+            ("/t/above", 101),
+            ("/t/d99/f", 99),
+            ("/t/d100/f", 100),
+            ("/t/d101/f", 101),
+        ]);
+        assert_eq!(
+            listed(&root, &filter(cutoff(100))),
+            ["/t", "/t/above", "/t/at", "/t/d100", "/t/d100/f", "/t/d101", "/t/d101/f"]
+        );
+    }
+
+    #[test]
+    fn threshold_modes_combine_percent_and_min_size() {
+        let root = tree(&[("/t/a", 50), ("/t/b", 150), ("/t/c", 250)]);
+        let both = |either| Cutoff {
+            relative: 100,
+            absolute: Some(200),
+            either,
+        };
+        assert_eq!(both(false).lowest(), 200);
+        assert_eq!(listed(&root, &filter(both(false))), ["/t", "/t/c"]);
+        assert_eq!(both(true).lowest(), 100);
+        assert_eq!(listed(&root, &filter(both(true))), ["/t", "/t/b", "/t/c"]);
+        for either in [false, true] {
+            assert!(both(either).admits(250));
+            assert!(!both(either).admits(99));
+            assert_eq!(both(either).admits(100), either);
+            assert_eq!(both(either).admits(199), either);
+            assert!(both(either).admits(200));
+        }
+    }
+
+    #[test]
+    fn empty_files_are_shown_only_with_include_zero() {
+        let root = tree(&[("/t/empty", 0), ("/t/d/empty", 0), ("/t/d/small", 10), ("/t/big", 100)]);
+        let include_zero =
+            |cutoff| ListFilter::new(cutoff, 0, HashMap::new(), false, None, true, false);
+        for relative in [0, 10, 100] {
+            let shown = listed(&root, &filter(cutoff(relative)));
+            assert!(!shown.iter().any(|path| path.ends_with("empty")), "{:?}", shown);
+            let shown = listed(&root, &include_zero(cutoff(relative)));
+            assert!(shown.contains(&"/t/empty".to_owned()), "{:?}", shown);
+            assert!(shown.contains(&"/t/d/empty".to_owned()), "{:?}", shown);
+        }
+        assert_eq!(listed(&root, &filter(cutoff(0))), ["/t", "/t/big", "/t/d", "/t/d/small"]);
+        // --min-size 0 on its own does not list empty files either
+        let min_size_zero = || Cutoff {
+            absolute: Some(0),
+            ..cutoff(100)
+        };
+        assert_eq!(listed(&root, &filter(min_size_zero())), ["/t", "/t/big"]);
+        assert_eq!(
+            listed(&root, &include_zero(min_size_zero())),
+            ["/t", "/t/big", "/t/d/empty", "/t/empty"]
+        );
+    }
+
+    #[test]
+    fn files_at_their_ext_threshold_are_shown() {
+        let thresholds = HashMap::from([("log".to_owned(), 100)]);
+        let filter = ListFilter::new(cutoff(1000), 0, thresholds, false, None, false, false);
+        assert!(!filter.shows_file("/t/a.log", 99));
+        assert!(filter.shows_file("/t/a.LOG", 100));
+        assert!(filter.shows_file("/t/a.log", 101));
+        assert!(!filter.shows_file("/t/a.txt", 101));
+
+        // A directory holding just a file at the threshold is descended into
+        let root = tree(&[("/t/big", 5000), ("/t/logs/a.log", 100)]);
+        assert_eq!(listed(&root, &filter), ["/t", "/t/big", "/t/logs/a.log"]);
+    }
+
+    #[test]
+    fn min_parent_percent_keeps_the_dominant_child() {
+        let root = tree(&[
+            ("/t/big/a", 900),
+            ("/t/big/inner/b", 80),
+            ("/t/big/tiny/c", 9),
+            ("/t/small/d", 50),
+            ("/t/small/e", 50),
+        ]);
+        let filter = ListFilter::new(cutoff(0), 0, HashMap::new(), false, Some(10.0), false, false);
+        // small is 100 of 1089 bytes, inner 80 and tiny 9 of big's 989
+        assert_eq!(listed(&root, &filter), ["/t", "/t/big", "/t/big/a"]);
+        assert_eq!(root.size(), 1089);
+
+        let filter = ListFilter::new(cutoff(0), 0, HashMap::new(), false, Some(9.0), false, false);
+        assert_eq!(
+            listed(&root, &filter),
+            ["/t", "/t/big", "/t/big/a", "/t/small", "/t/small/d", "/t/small/e"]
+        );
+    }
+
+    #[test]
+    fn columns_line_up_with_mixed_units() {
+        let sizes = [512, 3 << 30, 1023 << 20, 7];
+        let cells = vec![
+            sizes.iter().map(|size| bytes_to_nice(*size)).collect(),
+            vec!["f".to_owned(); 4],
+            vec!["/t/a".to_owned(), "/t/b".to_owned(), "/t/c".to_owned(), "/t/d".to_owned()],
+        ];
+        let rows = layout_rows(&[Column::Size, Column::Kind, Column::Path], cells, None);
+        assert_eq!(
+            rows,
+            ["512 B    f /t/a", "3 GiB    f /t/b", "1023 MiB f /t/c", "7 B      f /t/d"]
+        );
+
+        // A size wider than the usual 8 characters widens the whole column
+        let cells = vec![
+            vec!["1 B".to_owned(), "1023.99 GiB".to_owned()],
+            vec!["/t/a".to_owned(), "/t/b".to_owned()],
+        ];
+        let rows = layout_rows(&[Column::Size, Column::Path], cells, None);
+        assert_eq!(rows, ["1 B         /t/a", "1023.99 GiB /t/b"]);
+    }
+
+    #[test]
+    fn sizes_round_up_to_whole_blocks() {
+        assert_eq!(round_up_to_block(0, 4096), 0);
+        assert_eq!(round_up_to_block(1, 4096), 4096);
+        assert_eq!(round_up_to_block(4095, 4096), 4096);
+        assert_eq!(round_up_to_block(4096, 4096), 4096);
+        assert_eq!(round_up_to_block(4097, 4096), 8192);
+        assert_eq!(round_up_to_block(8192, 4096), 8192);
+        assert_eq!(round_up_to_block(8193, 4096), 12288);
+    }
+
+    #[test]
+    fn walk_counters_match_the_collected_files() {
+        let dir = TempDir::new();
+        fixture::generate(Path::new(dir.path()), 3000, 10_000).unwrap();
+        let root = Path::new(dir.path());
+        let chain = vec![fs::canonicalize(root).unwrap()];
+        let options = ScanOptions::default();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(16).build().unwrap();
+        for _ in 0..10 {
+            let stats = ScanStats::default();
+            let collected = pool.install(|| walk(root, root, None, &options, &stats, &chain));
+            assert_eq!(stats.files.load(Ordering::Relaxed), collected.files.len() as u64);
+            assert_eq!(
+                stats.bytes.load(Ordering::Relaxed),
+                collected.files.iter().map(|file| file.size).sum::<u64>()
+            );
+            assert_eq!(collected.files.len(), 3000);
+        }
+    }
+
+    #[test]
+    fn top_files_match_sort_and_truncate() {
+        let files: Vec<(u64, String)> = [5, 3, 5, 1, 3, 3, 0, 5, 8, 1]
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| (size, format!("/t/{}", i)))
+            .collect();
+        for n in 0..=files.len() + 2 {
+            let mut expected: Vec<u64> = files.iter().map(|(size, _)| *size).collect();
+            expected.sort_unstable_by(|a, b| b.cmp(a));
+            expected.truncate(n);
+
+            let mut top = TopFiles::new(n);
+            let (first, second) = files.split_at(files.len() / 2);
+            let mut other = TopFiles::new(n);
+            first.iter().for_each(|(size, path)| top.push(*size, path));
+            second.iter().for_each(|(size, path)| other.push(*size, path));
+            top.merge(other);
+
+            let kept = top.into_sorted_vec();
+            assert_eq!(kept.iter().map(|file| file.size).collect::<Vec<_>>(), expected, "n={}", n);
+            for file in &kept {
+                assert!(files.contains(&(file.size, file.path.clone())), "{}", file.path);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sftp_walk_matches_a_local_walk() {
+        let dir = TempDir::new();
+        fixture::generate(Path::new(dir.path()), 50, 100_000).unwrap();
+        fs::write(Path::new(dir.path()).join("dir0/.hidden"), [0; 10]).unwrap();
+        let options = ScanOptions::default();
+        let filter = filter(cutoff(0));
+
+        let mut local = Dir::new(dir.path());
+        find_all_files_and_directories(&mut local, &options, &ScanStats::default()).unwrap();
+        local.calc_size();
+
+        let (client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        let server = thread::spawn(move || sftp::tests::serve(server));
+        let mut session = sftp::Session::start(client.try_clone().unwrap(), client).unwrap();
+        let root = format!("sftp://host{}", dir.path());
+        let url = sftp::Url::parse(&root).unwrap().unwrap();
+        let mut remote = Dir::new(&root);
+        let stats = ScanStats::default();
+        let collected = walk_session(&mut session, &url, &mut remote, &options, &stats).unwrap();
+        assert_eq!(stats.files.load(Ordering::Relaxed), 51);
+        remote.build_tree(collected.dirs, collected.files);
+        remote.calc_size();
+        drop(session);
+        server.join().unwrap();
+
+        assert_eq!(remote.size(), local.size());
+        let local_paths: Vec<String> = listed(&local, &filter)
+            .into_iter()
+            .map(|path| format!("sftp://host{}", path))
+            .collect();
+        assert_eq!(listed(&remote, &filter), local_paths);
+    }
+
+    #[test]
+    fn nested_and_repeated_roots_are_dropped() {
+        let dir = TempDir::new();
+        for sub in ["a/b", "ab", "c"] {
+            fs::create_dir_all(Path::new(dir.path()).join(sub)).unwrap();
+        }
+        let root = |relative: &str| format!("{}/{}", dir.path(), relative);
+        let roots = vec![
+            root("a/b"),
+            root("a"),
+            root("ab"),
+            root("c/../a"),
+            root("c"),
+            root("missing"),
+            root("c/"),
+        ];
+        assert_eq!(
+            drop_nested_roots(roots),
+            [root("a"), root("ab"), root("c"), root("missing")]
+        );
+    }
+
+    #[test]
+    fn path_order_is_component_order() {
+        let mut paths = vec!["/t/a-b", "/t/a/b", "/t/a", "/t/a.b/c", "/t/a/b/c", "/t/ab", "/t"];
+        let mut by_components = paths.clone();
+        by_components.sort_by(|a, b| Path::new(a).cmp(Path::new(b)));
+        paths.sort_by(|a, b| path_order(a, b));
+        assert_eq!(paths, by_components);
+        assert_eq!(paths, ["/t", "/t/a", "/t/a/b", "/t/a/b/c", "/t/a-b", "/t/a.b/c", "/t/ab"]);
+    }
+
+    #[test]
+    fn windows_paths_are_normalized_to_forward_slashes() {
+        let windows = |path| with_forward_slashes(path, '\\');
+        assert_eq!(windows(r"C:\Users\bes\video.mp4"), "C:/Users/bes/video.mp4");
+        assert_eq!(windows(r"\\server\share\dir\"), "//server/share/dir/");
+        assert_eq!(windows("C:/already/forward"), "C:/already/forward");
+        assert!(matches!(windows("C:/already/forward"), Cow::Borrowed(_)));
+        // On Unix a backslash is part of the name
+        assert_eq!(with_forward_slashes(r"/srv/a\b", '/'), r"/srv/a\b");
+        #[cfg(windows)]
+        assert_eq!(normalized_path(r"C:\Users\bes"), "C:/Users/bes");
+        #[cfg(unix)]
+        assert_eq!(normalized_path(r"/srv/a\b"), r"/srv/a\b");
+    }
+
+    #[test]
+    fn control_characters_in_names_are_escaped() {
+        assert_eq!(shown_path("/srv/a\nb"), "/srv/a\\nb");
+        assert_eq!(shown_path("/srv/\x1b[31mred"), "/srv/\\x1b[31mred");
+        assert_eq!(shown_path("/srv/tab\there\r"), "/srv/tab\\there\\r");
+        assert!(matches!(shown_path("/srv/plain é"), Cow::Borrowed("/srv/plain é")));
+    }
+
+    #[test]
+    fn long_paths_are_truncated_in_the_middle() {
+        use terminal::truncate_path;
+
+        // Nothing in the middle to leave out
+        assert_eq!(truncate_path("averyveryverylongname.bin", 10), "averyveryverylongname.bin");
+        assert_eq!(truncate_path("dir/averylongname.bin", 10), "dir/averylongname.bin");
+        assert_eq!(truncate_path("/averylongname.bin", 10), "/averylongname.bin");
+        // The name is kept whole even when it alone is too long
+        assert_eq!(truncate_path("/srv/data/averylongname.bin", 10), ".../averylongname.bin");
+
+        let path = "/srv/data/projects/2024/report/final.pdf";
+        assert_eq!(truncate_path(path, 40), path);
+        // Directories next to the name are kept before those at the start
+        assert_eq!(truncate_path(path, 21), "/.../report/final.pdf");
+        assert_eq!(truncate_path(path, 25), "/srv/.../report/final.pdf");
+        assert_eq!(truncate_path(path, 30), "/srv/.../2024/report/final.pdf");
+        for max in 21..40 {
+            assert!(truncate_path(path, max).chars().count() <= max, "{}", max);
+        }
+    }
+
+    #[test]
+    fn durations_too_long_are_an_error() {
+        assert_eq!(parse_duration("90m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("1.5 h"), Ok(Duration::from_secs(90 * 60)));
+        assert!(parse_duration("99999999999999999999w").is_err());
+        assert!(parse_duration("10y").is_err());
+    }
+
+    #[test]
+    fn json_report_snapshot() {
+        let root = tree(&[("/t/a.bin", 300), ("/t/sub/b \"q\".log", 100)]);
+        let mut entries = Vec::new();
+        root.list(&filter(cutoff(0)), &mut entries);
+        let report = ScanReport {
+            schema_version: SCHEMA_VERSION,
+            root: &root.path,
+            total_bytes: root.size(),
+            entries,
+        };
+        let mut out = Vec::new();
+        report.to_json().write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                r#"{"schema_version":1,"root":"/t","total_bytes":400,"entries":["#,
+                r#"{"path":"/t","type":"dir","size":400},"#,
+                r#"{"path":"/t/a.bin","type":"file","size":300},"#,
+                r#"{"path":"/t/sub","type":"dir","size":100},"#,
+                r#"{"path":"/t/sub/b \"q\".log","type":"file","size":100}]}"#
+            )
+        );
+    }
 }
@@ -0,0 +1,77 @@
+use std::io::{self, BufWriter, StdoutLock, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// When buffered listing output is flushed, from --flush-interval
+#[derive(Clone, Copy)]
+pub enum FlushInterval {
+    /// Only once the listing is complete
+    End,
+    /// After every N entries
+    Entries(usize),
+    /// Once this much time passed since the last flush
+    Time(Duration),
+}
+
+impl FromStr for FlushInterval {
+    type Err = String;
+
+    /// A count of entries like `100`, or a time like `500ms` or `2s`
+    fn from_str(s: &str) -> Result<FlushInterval, String> {
+        let invalid = || format!("invalid flush interval: {} (expected e.g. 100, 500ms or 2s)", s);
+        let s = s.trim();
+        let interval = if let Some(ms) = s.strip_suffix("ms") {
+            FlushInterval::Time(Duration::from_millis(ms.parse().map_err(|_| invalid())?))
+        } else if let Some(secs) = s.strip_suffix('s') {
+            FlushInterval::Time(Duration::from_secs_f64(secs.parse().map_err(|_| invalid())?))
+        } else {
+            FlushInterval::Entries(s.parse().map_err(|_| invalid())?)
+        };
+        match interval {
+            FlushInterval::Entries(0) => Err(invalid()),
+            FlushInterval::Time(time) if time.is_zero() => Err(invalid()),
+            interval => Ok(interval),
+        }
+    }
+}
+
+/// Buffered stdout for printing one entry at a time, flushed as often as the interval asks
+pub struct Output {
+    out: BufWriter<StdoutLock<'static>>,
+    interval: FlushInterval,
+    pending: usize,
+    last_flush: Instant,
+}
+
+impl Output {
+    pub fn new(interval: FlushInterval) -> Output {
+        Output {
+            out: BufWriter::new(io::stdout().lock()),
+            interval,
+            pending: 0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Write one entry, terminator included
+    pub fn entry(&mut self, args: std::fmt::Arguments) -> io::Result<()> {
+        self.out.write_fmt(args)?;
+        self.pending += 1;
+        let due = match self.interval {
+            FlushInterval::End => false,
+            FlushInterval::Entries(n) => self.pending >= n,
+            FlushInterval::Time(time) => self.last_flush.elapsed() >= time,
+        };
+        if due {
+            self.out.flush()?;
+            self.pending = 0;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Must be called before anything else is printed, so the listing stays in order
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
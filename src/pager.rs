@@ -0,0 +1,58 @@
+use std::env;
+use std::io::{self, Write};
+use std::process::{Child, Command, Stdio};
+
+/// A pager process that stdout is redirected into, from --pager
+pub struct Pager {
+    child: Child,
+}
+
+impl Pager {
+    /// Start `$PAGER`, or `less`, and point stdout at it. Like git, `LESS` defaults to `FRX`
+    /// so output that fits on one screen is printed as is. None when there is no pager to
+    /// run, output then goes to the terminal directly.
+    #[cfg(unix)]
+    pub fn start() -> Option<Pager> {
+        use std::os::unix::io::AsRawFd;
+
+        let command = env::var("PAGER").unwrap_or_else(|_| "less".to_owned());
+        if command.trim().is_empty() || command.trim() == "cat" {
+            return None;
+        }
+        let mut pager = Command::new("sh");
+        pager.arg("-c").arg(&command).stdin(Stdio::piped());
+        if env::var_os("LESS").is_none() {
+            pager.env("LESS", "FRX");
+        }
+        let mut child = match pager.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                log::warn!("cannot start pager {}: {}", command, e);
+                return None;
+            }
+        };
+        let stdin = child.stdin.take()?;
+        // SAFETY: both descriptors are open, stdout is replaced by the pipe to the pager
+        if unsafe { libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO) } == -1 {
+            log::warn!("cannot redirect output to pager: {}", io::Error::last_os_error());
+            return None;
+        }
+        Some(Pager { child })
+    }
+
+    #[cfg(not(unix))]
+    pub fn start() -> Option<Pager> {
+        None
+    }
+
+    /// Close the pipe so the pager sees the end of the output, and wait until it is quit
+    pub fn finish(mut self) {
+        let _ = io::stdout().flush();
+        #[cfg(unix)]
+        // SAFETY: nothing is written to stdout after this
+        unsafe {
+            libc::close(libc::STDOUT_FILENO);
+        }
+        let _ = self.child.wait();
+    }
+}
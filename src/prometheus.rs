@@ -0,0 +1,62 @@
+use std::io::{self, Write};
+
+/// The totals of one scanned root, as exported with --format prometheus
+pub struct RootMetrics {
+    pub root: String,
+    pub total_bytes: u64,
+    pub files: u64,
+    pub dirs: u64,
+    pub largest_file_bytes: u64,
+}
+
+/// One gauge per root
+struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: fn(&RootMetrics) -> u64,
+}
+
+const GAUGES: &[Gauge] = &[
+    Gauge {
+        name: "largefinder_total_bytes",
+        help: "Total size of the files below the root in bytes.",
+        value: |m| m.total_bytes,
+    },
+    Gauge {
+        name: "largefinder_file_count",
+        help: "Number of files below the root.",
+        value: |m| m.files,
+    },
+    Gauge {
+        name: "largefinder_dir_count",
+        help: "Number of directories below the root, itself included.",
+        value: |m| m.dirs,
+    },
+    Gauge {
+        name: "largefinder_largest_file_bytes",
+        help: "Size of the largest file below the root in bytes.",
+        value: |m| m.largest_file_bytes,
+    },
+];
+
+/// Write all roots in the text exposition format, each metric family once with a sample per
+/// root, so the output can be dropped into a node_exporter textfile collector directory.
+pub fn write(out: &mut dyn Write, roots: &[RootMetrics]) -> io::Result<()> {
+    for gauge in GAUGES {
+        writeln!(out, "# HELP {} {}", gauge.name, gauge.help)?;
+        writeln!(out, "# TYPE {} gauge", gauge.name)?;
+        for root in roots {
+            let root_label = escape_label(&root.root);
+            writeln!(out, "{}{{root=\"{}\"}} {}", gauge.name, root_label, (gauge.value)(root))?;
+        }
+    }
+    Ok(())
+}
+
+/// Label values escape backslash, double quote and newline
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,66 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Stdout pointed at a report file for --output-dir, until `restore` points it back
+pub struct Redirect {
+    #[cfg(unix)]
+    saved: libc::c_int,
+}
+
+impl Redirect {
+    /// Create `path` and send everything printed to stdout into it
+    #[cfg(unix)]
+    pub fn to_file(path: &Path) -> io::Result<Redirect> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = fs::File::create(path)?;
+        io::stdout().flush()?;
+        // SAFETY: stdout is open, the duplicate keeps it around to be restored later
+        let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        if saved == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: both descriptors are open, the file stays open as stdout once `file` is closed
+        if unsafe { libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO) } == -1 {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(saved) };
+            return Err(e);
+        }
+        Ok(Redirect { saved })
+    }
+
+    #[cfg(not(unix))]
+    pub fn to_file(_path: &Path) -> io::Result<Redirect> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "only supported on Unix"))
+    }
+
+    /// Finish the report file and print to the original stdout again
+    pub fn restore(self) -> io::Result<()> {
+        io::stdout().flush()?;
+        #[cfg(unix)]
+        // SAFETY: `saved` is the original stdout, duplicated in `to_file`
+        unsafe {
+            if libc::dup2(self.saved, libc::STDOUT_FILENO) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::close(self.saved);
+        }
+        Ok(())
+    }
+}
+
+/// A file name for the report of `root`, its path components joined by `_` so no separator
+/// is left, e.g. `srv_data` for `/srv/data`. `root` when nothing is left, as for `.` or `/`.
+pub fn file_stem(root: &str) -> String {
+    let components: Vec<&str> = root
+        .split(['/', '\\', ':'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .map(|component| if component == ".." { "up" } else { component })
+        .collect();
+    if components.is_empty() {
+        "root".to_owned()
+    } else {
+        components.join("_")
+    }
+}
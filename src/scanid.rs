@@ -0,0 +1,28 @@
+/// A short ID for the results of a scan, from --scan-id. It hashes with 64 bit FNV-1a since
+/// the std hashers are not guaranteed to give the same result across Rust releases.
+pub struct ScanId(u64);
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0100_0000_01b3;
+
+impl ScanId {
+    /// Start from the root and a description of the options that change the results
+    pub fn start(root: &str, options: &str) -> ScanId {
+        let mut id = ScanId(OFFSET_BASIS);
+        id.add(root.as_bytes());
+        id.add(options.as_bytes());
+        id
+    }
+
+    /// Add one field, terminated so that `ab` + `c` and `a` + `bc` differ
+    pub fn add(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter().chain(Some(&0)) {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
@@ -0,0 +1,542 @@
+//! Just enough of SFTP version 3 to walk a remote tree, spoken over `ssh -s HOST sftp` so
+//! authentication is whatever ssh does, the agent and keys by default
+
+use crate::stat::Stat;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_CLOSE: u8 = 4;
+const FXP_LSTAT: u8 = 7;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_PERMISSION_DENIED: u32 = 3;
+
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_UIDGID: u32 = 0x2;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+const S_IFMT: u32 = 0o170_000;
+const S_IFDIR: u32 = 0o040_000;
+const S_IFLNK: u32 = 0o120_000;
+const S_IFBLK: u32 = 0o060_000;
+
+/// The largest packet OpenSSH sends or accepts
+const MAX_PACKET: u32 = 256 * 1024;
+
+/// A parsed `sftp://[user@]host[:port][/path]` root
+#[derive(Debug, PartialEq)]
+pub struct Url {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    /// Absolute, or relative to the home directory for `/~/dir` and no path at all
+    pub path: String,
+}
+
+impl Url {
+    /// None when `s` is no sftp URL, an error when it is one that can not be used
+    pub fn parse(s: &str) -> Option<Result<Url, String>> {
+        let rest = s.strip_prefix("sftp://")?;
+        Some(Url::parse_rest(rest).ok_or_else(|| format!("invalid sftp URL: {}", s)))
+    }
+
+    fn parse_rest(rest: &str) -> Option<Url> {
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user.to_owned()), host_port),
+            None => (None, authority),
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            // An IPv6 address in brackets has colons of its own
+            Some((host, port)) if !port.contains(']') => (host, Some(port.parse().ok()?)),
+            _ => (host_port, None),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() || user.as_deref() == Some("") {
+            return None;
+        }
+        let path = match path.trim_end_matches('/') {
+            "" | "/~" => ".".to_owned(),
+            path => path.strip_prefix("/~/").unwrap_or(path).to_owned(),
+        };
+        Some(Url {
+            user,
+            host: host.to_owned(),
+            port,
+            path,
+        })
+    }
+}
+
+/// What a listing tells about an entry, only the fields that are sent are set
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Attrs {
+    pub size: Option<u64>,
+    pub permissions: Option<u32>,
+    /// Seconds since the epoch
+    pub mtime: Option<u32>,
+}
+
+impl Attrs {
+    fn file_type(&self) -> Option<u32> {
+        self.permissions.map(|permissions| permissions & S_IFMT)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == Some(S_IFDIR)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == Some(S_IFLNK)
+    }
+
+    /// As much of a `Stat` as SFTP knows, there are no devices, links or inodes
+    pub fn to_stat(self) -> Stat {
+        Stat {
+            is_dir: self.is_dir(),
+            is_block_device: self.file_type() == Some(S_IFBLK),
+            len: self.size.unwrap_or(0),
+            mtime: self.mtime.map_or(0, |mtime| u64::from(mtime) * 1_000_000_000),
+            device: 0,
+            links: 1,
+            inode: None,
+        }
+    }
+}
+
+/// The requests of one SFTP session, answered one at a time
+pub struct Session<R, W> {
+    reader: R,
+    writer: W,
+    next_id: u32,
+}
+
+impl<R: Read, W: Write> Session<R, W> {
+    /// Agree on version 3 with the server at the other end of `reader` and `writer`
+    pub fn start(reader: R, writer: W) -> io::Result<Session<R, W>> {
+        let mut session = Session {
+            reader,
+            writer,
+            next_id: 0,
+        };
+        session.send(FXP_INIT, &3u32.to_be_bytes())?;
+        let mut reply = session.receive()?;
+        if reply.u8()? != FXP_VERSION {
+            return Err(protocol_error("no version from the server"));
+        }
+        let version = reply.u32()?;
+        if version < 3 {
+            return Err(protocol_error(&format!("server speaks SFTP version {}", version)));
+        }
+        Ok(session)
+    }
+
+    /// The attributes of `path` itself, also when it is a symlink
+    pub fn lstat(&mut self, path: &str) -> io::Result<Attrs> {
+        let (kind, mut reply) = self.request(FXP_LSTAT, path.as_bytes())?;
+        match kind {
+            FXP_ATTRS => reply.attrs(),
+            kind => Err(reply.unexpected(kind)),
+        }
+    }
+
+    /// The names and attributes of the entries of directory `path`, without `.` and `..`
+    pub fn read_dir(&mut self, path: &str) -> io::Result<Vec<(String, Attrs)>> {
+        let (kind, mut reply) = self.request(FXP_OPENDIR, path.as_bytes())?;
+        let handle = match kind {
+            FXP_HANDLE => reply.string()?.to_vec(),
+            kind => return Err(reply.unexpected(kind)),
+        };
+        let entries = self.read_entries(&handle);
+        // A handle left open only costs the server a little until the session ends
+        let closed = self.request(FXP_CLOSE, &handle).and_then(|(kind, mut reply)| match kind {
+            FXP_STATUS => reply.status(),
+            kind => Err(reply.unexpected(kind)),
+        });
+        let entries = entries?;
+        closed?;
+        Ok(entries)
+    }
+
+    fn read_entries(&mut self, handle: &[u8]) -> io::Result<Vec<(String, Attrs)>> {
+        let mut entries = Vec::new();
+        loop {
+            let (kind, mut reply) = self.request(FXP_READDIR, handle)?;
+            match kind {
+                FXP_NAME => {
+                    for _ in 0..reply.u32()? {
+                        let name = String::from_utf8_lossy(reply.string()?).into_owned();
+                        reply.string()?;
+                        let attrs = reply.attrs()?;
+                        if name != "." && name != ".." {
+                            entries.push((name, attrs));
+                        }
+                    }
+                }
+                FXP_STATUS => match reply.status() {
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(entries),
+                    Err(e) => return Err(e),
+                    Ok(()) => return Err(protocol_error("directory listing without an end")),
+                },
+                kind => return Err(reply.unexpected(kind)),
+            }
+        }
+    }
+
+    /// Send a request of `kind` with one string argument, returning the type of the reply and
+    /// the rest of it after the request id
+    fn request(&mut self, kind: u8, argument: &[u8]) -> io::Result<(u8, Reply)> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut payload = Vec::with_capacity(8 + argument.len());
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend_from_slice(&(argument.len() as u32).to_be_bytes());
+        payload.extend_from_slice(argument);
+        self.send(kind, &payload)?;
+        let mut reply = self.receive()?;
+        let kind = reply.u8()?;
+        if reply.u32()? != id {
+            return Err(protocol_error("reply to another request"));
+        }
+        Ok((kind, reply))
+    }
+
+    fn send(&mut self, kind: u8, payload: &[u8]) -> io::Result<()> {
+        let length = (payload.len() + 1) as u32;
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(&[kind])?;
+        self.writer.write_all(payload)?;
+        self.writer.flush()
+    }
+
+    fn receive(&mut self) -> io::Result<Reply> {
+        let mut length = [0; 4];
+        self.reader.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length);
+        if length == 0 || length > MAX_PACKET {
+            return Err(protocol_error(&format!("packet of {} bytes", length)));
+        }
+        let mut data = vec![0; length as usize];
+        self.reader.read_exact(&mut data)?;
+        Ok(Reply { data, position: 0 })
+    }
+}
+
+/// A packet being taken apart
+struct Reply {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl Reply {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.data.len() - self.position < n {
+            return Err(protocol_error("packet too short"));
+        }
+        self.position += n;
+        Ok(&self.data[self.position - n..self.position])
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<&[u8]> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    fn attrs(&mut self) -> io::Result<Attrs> {
+        let flags = self.u32()?;
+        let mut attrs = Attrs::default();
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(self.u64()?);
+        }
+        if flags & ATTR_UIDGID != 0 {
+            self.take(8)?;
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(self.u32()?);
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            self.u32()?;
+            attrs.mtime = Some(self.u32()?);
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.string()?;
+                self.string()?;
+            }
+        }
+        Ok(attrs)
+    }
+
+    /// The outcome of a STATUS reply, end of file as `UnexpectedEof`
+    fn status(&mut self) -> io::Result<()> {
+        let code = self.u32()?;
+        let message = String::from_utf8_lossy(self.string().unwrap_or_default()).into_owned();
+        let kind = match code {
+            FX_OK => return Ok(()),
+            FX_EOF => io::ErrorKind::UnexpectedEof,
+            FX_NO_SUCH_FILE => io::ErrorKind::NotFound,
+            FX_PERMISSION_DENIED => io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::Other,
+        };
+        Err(io::Error::new(kind, message))
+    }
+
+    /// The error for a reply of `kind` where another was expected, the server's own one for a
+    /// STATUS reply
+    fn unexpected(&mut self, kind: u8) -> io::Error {
+        match kind {
+            FXP_STATUS => self.status().err().unwrap_or_else(|| protocol_error("no reply")),
+            _ => protocol_error(&format!("unexpected reply {}", kind)),
+        }
+    }
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("SFTP: {}", message))
+}
+
+/// An SFTP session with a server reached through the ssh command
+pub struct Connection {
+    child: Child,
+    pub session: Session<ChildStdout, ChildStdin>,
+}
+
+impl Connection {
+    pub fn open(url: &Url) -> io::Result<Connection> {
+        let mut command = Command::new("ssh");
+        command.arg("-s");
+        if let Some(port) = url.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        if let Some(user) = &url.user {
+            command.arg("-l").arg(user);
+        }
+        // A password prompt, if any, goes to the terminal, ssh's errors to stderr
+        command.arg("--").arg(&url.host).arg("sftp");
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let reader = child.stdout.take().unwrap();
+        let writer = child.stdin.take().unwrap();
+        match Session::start(reader, writer) {
+            Ok(session) => Ok(Connection { child, session }),
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                // ssh has said why on stderr
+                let reason = match e.kind() {
+                    io::ErrorKind::UnexpectedEof => "ssh closed the connection".to_owned(),
+                    _ => e.to_string(),
+                };
+                let message = format!("cannot start SFTP with {}: {}", url.host, reason);
+                Err(io::Error::new(e.kind(), message))
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(all(test, unix))]
+pub mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::net::UnixStream;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+
+    #[test]
+    fn urls() {
+        let url = |s| Url::parse(s).unwrap();
+        assert_eq!(
+            url("sftp://bes@backup:2222/srv/archive/"),
+            Ok(Url {
+                user: Some("bes".to_owned()),
+                host: "backup".to_owned(),
+                port: Some(2222),
+                path: "/srv/archive".to_owned(),
+            })
+        );
+        assert_eq!(url("sftp://backup").unwrap().path, ".");
+        assert_eq!(url("sftp://backup/~/photos").unwrap().path, "photos");
+        assert_eq!(url("sftp://[::1]:22/srv").unwrap().host, "::1");
+        assert!(url("sftp://backup:ssh/srv").is_err());
+        assert!(url("sftp:///srv").is_err());
+        assert_eq!(Url::parse("/srv/archive"), None);
+    }
+
+    /// Answer the requests of a session from the local filesystem, the way sftp-server does
+    pub fn serve(mut stream: UnixStream) {
+        let mut out = stream.try_clone().unwrap();
+        let mut handles: Vec<Option<Vec<PathBuf>>> = Vec::new();
+        loop {
+            let mut length = [0; 4];
+            if stream.read_exact(&mut length).is_err() {
+                return;
+            }
+            let mut request = Reply {
+                data: vec![0; u32::from_be_bytes(length) as usize],
+                position: 0,
+            };
+            stream.read_exact(&mut request.data).unwrap();
+            let kind = request.u8().unwrap();
+            let mut reply = Vec::new();
+            if kind == FXP_INIT {
+                reply.push(FXP_VERSION);
+                reply.extend_from_slice(&3u32.to_be_bytes());
+                send(&mut out, &reply);
+                continue;
+            }
+            let id = request.u32().unwrap();
+            let argument = request.string().unwrap().to_vec();
+            let status = |reply: &mut Vec<u8>, code: u32| {
+                reply.push(FXP_STATUS);
+                reply.extend_from_slice(&id.to_be_bytes());
+                reply.extend_from_slice(&code.to_be_bytes());
+                put_string(reply, b"status");
+                put_string(reply, b"");
+            };
+            let handle = || u32::from_be_bytes(argument[..4].try_into().unwrap()) as usize;
+            match kind {
+                FXP_LSTAT => match fs::symlink_metadata(String::from_utf8(argument).unwrap()) {
+                    Ok(metadata) => {
+                        reply.push(FXP_ATTRS);
+                        reply.extend_from_slice(&id.to_be_bytes());
+                        put_attrs(&mut reply, &metadata);
+                    }
+                    Err(_) => status(&mut reply, FX_NO_SUCH_FILE),
+                },
+                FXP_OPENDIR => match fs::read_dir(String::from_utf8(argument).unwrap()) {
+                    Ok(entries) => {
+                        let mut paths: Vec<PathBuf> =
+                            entries.map(|entry| entry.unwrap().path()).collect();
+                        paths.push(".".into());
+                        reply.push(FXP_HANDLE);
+                        reply.extend_from_slice(&id.to_be_bytes());
+                        put_string(&mut reply, &(handles.len() as u32).to_be_bytes());
+                        handles.push(Some(paths));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                        status(&mut reply, FX_PERMISSION_DENIED)
+                    }
+                    Err(_) => status(&mut reply, FX_NO_SUCH_FILE),
+                },
+                FXP_READDIR => {
+                    let paths = handles[handle()].as_mut().unwrap();
+                    if paths.is_empty() {
+                        status(&mut reply, FX_EOF);
+                    } else {
+                        // Two at a time, so listings take more than one reply
+                        let batch: Vec<PathBuf> = paths.drain(..paths.len().min(2)).collect();
+                        reply.push(FXP_NAME);
+                        reply.extend_from_slice(&id.to_be_bytes());
+                        reply.extend_from_slice(&(batch.len() as u32).to_be_bytes());
+                        for path in batch {
+                            let name = path.file_name().map_or(".".into(), |name| name.to_owned());
+                            put_string(&mut reply, name.to_str().unwrap().as_bytes());
+                            put_string(&mut reply, b"-rw-r--r-- long name");
+                            let real = if name == "." { Path::new(".") } else { &path };
+                            put_attrs(&mut reply, &fs::symlink_metadata(real).unwrap());
+                        }
+                    }
+                }
+                FXP_CLOSE => {
+                    handles[handle()] = None;
+                    status(&mut reply, FX_OK);
+                }
+                _ => status(&mut reply, 8),
+            }
+            send(&mut out, &reply);
+        }
+    }
+
+    fn send(out: &mut UnixStream, reply: &[u8]) {
+        out.write_all(&(reply.len() as u32).to_be_bytes()).unwrap();
+        out.write_all(reply).unwrap();
+    }
+
+    fn put_string(reply: &mut Vec<u8>, s: &[u8]) {
+        reply.extend_from_slice(&(s.len() as u32).to_be_bytes());
+        reply.extend_from_slice(s);
+    }
+
+    fn put_attrs(reply: &mut Vec<u8>, metadata: &fs::Metadata) {
+        let flags = ATTR_SIZE | ATTR_UIDGID | ATTR_PERMISSIONS | ATTR_ACMODTIME | ATTR_EXTENDED;
+        reply.extend_from_slice(&flags.to_be_bytes());
+        reply.extend_from_slice(&metadata.len().to_be_bytes());
+        reply.extend_from_slice(&metadata.uid().to_be_bytes());
+        reply.extend_from_slice(&metadata.gid().to_be_bytes());
+        reply.extend_from_slice(&metadata.mode().to_be_bytes());
+        reply.extend_from_slice(&(metadata.atime() as u32).to_be_bytes());
+        reply.extend_from_slice(&(metadata.mtime() as u32).to_be_bytes());
+        reply.extend_from_slice(&1u32.to_be_bytes());
+        put_string(reply, b"name@example.com");
+        put_string(reply, b"value");
+    }
+
+    #[test]
+    fn lists_directories_of_a_server() {
+        let dir = std::env::temp_dir().join(format!("lff-sftp-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.bin"), vec![0; 3000]).unwrap();
+        fs::write(dir.join("b.bin"), vec![0; 10]).unwrap();
+        fs::write(dir.join("sub/c.bin"), vec![0; 7]).unwrap();
+        std::os::unix::fs::symlink("a.bin", dir.join("link")).unwrap();
+
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || serve(server));
+        let mut session = Session::start(client.try_clone().unwrap(), client).unwrap();
+
+        let root = dir.to_str().unwrap();
+        assert!(session.lstat(root).unwrap().is_dir());
+        let mut entries = session.read_dir(root).unwrap();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a.bin", "b.bin", "link", "sub"]);
+        assert_eq!(entries[0].1.to_stat().len, 3000);
+        assert!(entries[0].1.mtime.is_some());
+        assert!(entries[2].1.is_symlink());
+        assert!(entries[3].1.is_dir());
+        let sub = session.read_dir(&format!("{}/sub", root)).unwrap();
+        assert_eq!(sub.len(), 1);
+        assert_eq!(sub[0].1.size, Some(7));
+
+        let missing = session.read_dir(&format!("{}/missing", root)).unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+
+        drop(session);
+        server.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,649 @@
+//! Just enough of the SQLite file format to write a database holding one table and its
+//! indexes, so --format sqlite needs neither the library nor the sqlite3 shell
+
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// One file or directory of the `files` table
+pub struct Row {
+    /// The root as given on the command line
+    pub root: Rc<str>,
+    pub path: String,
+    pub size: u64,
+    pub is_file: bool,
+    /// Seconds since the epoch, only known for files
+    pub mtime: Option<u64>,
+    /// Hard links of a file
+    pub links: Option<u64>,
+}
+
+const PAGE_SIZE: usize = 4096;
+
+const INTERIOR_INDEX: u8 = 2;
+const INTERIOR_TABLE: u8 = 5;
+const LEAF_INDEX: u8 = 10;
+const LEAF_TABLE: u8 = 13;
+
+/// The largest payload a table leaf cell keeps on its page, the rest goes to overflow pages
+const TABLE_MAX_LOCAL: usize = PAGE_SIZE - 35;
+/// The same for index cells, which have to leave room for at least four of them per page
+const INDEX_MAX_LOCAL: usize = (PAGE_SIZE - 12) * 64 / 255 - 23;
+/// What a payload that spills over keeps on its page at least
+const MIN_LOCAL: usize = (PAGE_SIZE - 12) * 32 / 255 - 23;
+
+const TABLE_SQL: &str = "\
+CREATE TABLE files (
+  root TEXT NOT NULL,
+  path TEXT NOT NULL,
+  name TEXT NOT NULL,
+  extension TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  is_file INTEGER NOT NULL,
+  mtime INTEGER,
+  links INTEGER
+)";
+
+const SIZE_INDEX_SQL: &str = "CREATE INDEX files_size ON files (size)";
+const PATH_INDEX_SQL: &str = "CREATE INDEX files_path ON files (path)";
+
+/// Write `rows` as an SQLite database with a `files` table, indexed by size and by path
+pub fn write<W: Write + Seek>(out: W, rows: &[Row]) -> io::Result<()> {
+    let mut pages = Pages { out, count: 1 };
+    // Page 1 holds the schema with the root pages of the b-trees, so it is written last
+    pages.out.write_all(&[0; PAGE_SIZE])?;
+
+    let mut files = BTree::new(false);
+    for (i, row) in rows.iter().enumerate() {
+        let path = Path::new(&row.path);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let extension = if row.is_file {
+            path.extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let payload = record(&[
+            Value::Text(&row.root),
+            Value::Text(&row.path),
+            Value::Text(&name),
+            Value::Text(&extension),
+            Value::Int(row.size as i64),
+            Value::Int(row.is_file as i64),
+            row.mtime
+                .map_or(Value::Null, |mtime| Value::Int(mtime as i64)),
+            row.links
+                .map_or(Value::Null, |links| Value::Int(links as i64)),
+        ]);
+        let rowid = i as u64 + 1;
+        let cell = pages.cell(Some(rowid), payload, TABLE_MAX_LOCAL)?;
+        files.add(&mut pages, cell, rowid)?;
+    }
+    let files_root = files.finish(&mut pages)?;
+
+    // Rows are numbered from 1 in order, so sorting stably keeps equal keys in rowid order
+    let mut order: Vec<usize> = (0..rows.len()).collect();
+    order.sort_by_key(|&i| rows[i].size as i64);
+    let size_root = write_index(&mut pages, &order, |i| Value::Int(rows[i].size as i64))?;
+    order.sort_by(|&a, &b| {
+        rows[a]
+            .path
+            .as_bytes()
+            .cmp(rows[b].path.as_bytes())
+            .then(a.cmp(&b))
+    });
+    let path_root = write_index(&mut pages, &order, |i| Value::Text(&rows[i].path))?;
+
+    let mut schema = Page::new(LEAF_TABLE);
+    let objects = [
+        ("table", "files", files_root, TABLE_SQL),
+        ("index", "files_size", size_root, SIZE_INDEX_SQL),
+        ("index", "files_path", path_root, PATH_INDEX_SQL),
+    ];
+    for (rowid, (kind, name, root, sql)) in objects.into_iter().enumerate() {
+        let payload = record(&[
+            Value::Text(kind),
+            Value::Text(name),
+            Value::Text("files"),
+            Value::Int(i64::from(root)),
+            Value::Text(sql),
+        ]);
+        schema.push(pages.cell(Some(rowid as u64 + 1), payload, TABLE_MAX_LOCAL)?);
+    }
+    let mut first = schema.encode(DATABASE_HEADER, None);
+    first[..DATABASE_HEADER].copy_from_slice(&database_header(pages.count));
+    pages.out.seek(SeekFrom::Start(0))?;
+    pages.out.write_all(&first)?;
+    pages.out.flush()
+}
+
+/// Write the index of the rows in `order`, each keyed by `key` and its rowid
+fn write_index<'a, W: Write>(
+    pages: &mut Pages<W>,
+    order: &[usize],
+    key: impl Fn(usize) -> Value<'a>,
+) -> io::Result<u32> {
+    let mut index = BTree::new(true);
+    for &i in order {
+        let rowid = i as u64 + 1;
+        let payload = record(&[key(i), Value::Int(rowid as i64)]);
+        let cell = pages.cell(None, payload, INDEX_MAX_LOCAL)?;
+        index.add(pages, cell, rowid)?;
+    }
+    index.finish(pages)
+}
+
+const DATABASE_HEADER: usize = 100;
+
+/// The header at the start of page 1, for a database of `page_count` pages
+fn database_header(page_count: u32) -> [u8; DATABASE_HEADER] {
+    let mut header = [0; DATABASE_HEADER];
+    header[..16].copy_from_slice(b"SQLite format 3\0");
+    header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    // Rollback journal for both writing and reading
+    header[18] = 1;
+    header[19] = 1;
+    // Largest and smallest embedded payload fractions, always these
+    header[21] = 64;
+    header[22] = 32;
+    header[23] = 32;
+    // The change counter, and the version valid for it that makes the page count trusted
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&page_count.to_be_bytes());
+    // Schema cookie and format 4, which has the 0 and 1 serial types
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    // UTF-8
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());
+    // The version of SQLite whose format this follows
+    header[96..100].copy_from_slice(&3_040_000u32.to_be_bytes());
+    header
+}
+
+enum Value<'a> {
+    Null,
+    Int(i64),
+    Text(&'a str),
+}
+
+/// The values as a record: a header with the size of itself and the serial type of every
+/// value, followed by the values
+fn record(values: &[Value]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        match *value {
+            Value::Null => types.push(0),
+            Value::Int(0) => types.push(8),
+            Value::Int(1) => types.push(9),
+            Value::Int(n) => {
+                let (serial_type, len) = match n {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                types.push(serial_type);
+                body.extend_from_slice(&n.to_be_bytes()[8 - len..]);
+            }
+            Value::Text(s) => {
+                types.push(13 + 2 * s.len() as u64);
+                body.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+    let mut header = Vec::new();
+    for serial_type in types {
+        put_varint(&mut header, serial_type);
+    }
+    // The header size counts its own varint
+    let mut header_size = header.len() + 1;
+    while header.len() + varint_len(header_size as u64) != header_size {
+        header_size = header.len() + varint_len(header_size as u64);
+    }
+    let mut record = Vec::with_capacity(header_size + body.len());
+    put_varint(&mut record, header_size as u64);
+    record.extend(header);
+    record.extend(body);
+    record
+}
+
+/// Append `value` as a big-endian varint of 7 bits per byte, where a ninth byte holds 8
+fn put_varint(out: &mut Vec<u8>, value: u64) {
+    if value >> 56 != 0 {
+        for i in (0..8).rev() {
+            out.push((value >> (8 + 7 * i)) as u8 | 0x80);
+        }
+        out.push(value as u8);
+        return;
+    }
+    let len = varint_len(value);
+    for i in (0..len).rev() {
+        let more = if i > 0 { 0x80 } else { 0 };
+        out.push((value >> (7 * i)) as u8 & 0x7f | more);
+    }
+}
+
+fn varint_len(value: u64) -> usize {
+    if value >> 56 != 0 {
+        9
+    } else {
+        (64 - value.leading_zeros() as usize).max(1).div_ceil(7)
+    }
+}
+
+/// The pages of the database, written out in order
+struct Pages<W> {
+    out: W,
+    count: u32,
+}
+
+impl<W: Write> Pages<W> {
+    /// Write the next page, returning its number
+    fn push(&mut self, page: &[u8]) -> io::Result<u32> {
+        self.out.write_all(page)?;
+        self.count += 1;
+        Ok(self.count)
+    }
+
+    /// A leaf cell of `payload`, with the rowid for a table. What does not fit on the page is
+    /// written to overflow pages right away, each pointing at the next.
+    fn cell(
+        &mut self,
+        rowid: Option<u64>,
+        payload: Vec<u8>,
+        max_local: usize,
+    ) -> io::Result<Vec<u8>> {
+        let mut cell = Vec::new();
+        put_varint(&mut cell, payload.len() as u64);
+        if let Some(rowid) = rowid {
+            put_varint(&mut cell, rowid);
+        }
+        let local = local_size(payload.len(), max_local);
+        cell.extend_from_slice(&payload[..local]);
+        if local < payload.len() {
+            cell.extend_from_slice(&(self.count + 1).to_be_bytes());
+            let chunks: Vec<&[u8]> = payload[local..].chunks(PAGE_SIZE - 4).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let mut page = vec![0; PAGE_SIZE];
+                if i + 1 < chunks.len() {
+                    page[..4].copy_from_slice(&(self.count + 2).to_be_bytes());
+                }
+                page[4..4 + chunk.len()].copy_from_slice(chunk);
+                self.push(&page)?;
+            }
+        }
+        Ok(cell)
+    }
+}
+
+/// How much of a payload of `len` bytes stays on the page of its cell
+fn local_size(len: usize, max_local: usize) -> usize {
+    if len <= max_local {
+        return len;
+    }
+    let local = MIN_LOCAL + (len - MIN_LOCAL) % (PAGE_SIZE - 4);
+    if local <= max_local {
+        local
+    } else {
+        MIN_LOCAL
+    }
+}
+
+/// A b-tree page being filled with cells
+struct Page {
+    kind: u8,
+    cells: Vec<Vec<u8>>,
+    used: usize,
+}
+
+impl Page {
+    fn new(kind: u8) -> Page {
+        Page {
+            kind,
+            cells: Vec::new(),
+            used: 0,
+        }
+    }
+
+    fn header_len(&self) -> usize {
+        if self.kind == LEAF_INDEX || self.kind == LEAF_TABLE {
+            8
+        } else {
+            12
+        }
+    }
+
+    /// Whether `cell` and its pointer still fit
+    fn fits(&self, cell: &[u8]) -> bool {
+        self.header_len() + 2 * (self.cells.len() + 1) + self.used + cell.len() <= PAGE_SIZE
+    }
+
+    fn push(&mut self, cell: Vec<u8>) {
+        self.used += cell.len();
+        self.cells.push(cell);
+    }
+
+    fn pop(&mut self) -> Vec<u8> {
+        let cell = self.cells.pop().expect("a full page has cells");
+        self.used -= cell.len();
+        cell
+    }
+
+    /// The page as stored, its header `offset` bytes in, which is after the database header
+    /// on page 1. Cells are packed at the end, in reverse, and pointed at in order.
+    fn encode(&self, offset: usize, right_child: Option<u32>) -> Vec<u8> {
+        let mut page = vec![0; PAGE_SIZE];
+        let mut content = PAGE_SIZE;
+        let mut pointer = offset + self.header_len();
+        for cell in self.cells.iter() {
+            content -= cell.len();
+            page[content..content + cell.len()].copy_from_slice(cell);
+            page[pointer..pointer + 2].copy_from_slice(&(content as u16).to_be_bytes());
+            pointer += 2;
+        }
+        page[offset] = self.kind;
+        page[offset + 3..offset + 5].copy_from_slice(&(self.cells.len() as u16).to_be_bytes());
+        page[offset + 5..offset + 7].copy_from_slice(&(content as u16).to_be_bytes());
+        if let Some(child) = right_child {
+            page[offset + 8..offset + 12].copy_from_slice(&child.to_be_bytes());
+        }
+        page
+    }
+}
+
+/// A b-tree built bottom up from cells in key order, with one page being filled per level.
+/// Full pages are written as they are left behind, so a child is always written before the
+/// parent that points at it.
+struct BTree {
+    index: bool,
+    levels: Vec<Page>,
+    last_rowid: u64,
+}
+
+impl BTree {
+    fn new(index: bool) -> BTree {
+        BTree {
+            index,
+            levels: vec![Page::new(if index { LEAF_INDEX } else { LEAF_TABLE })],
+            last_rowid: 0,
+        }
+    }
+
+    /// Add the leaf cell of the next key
+    fn add<W: Write>(&mut self, pages: &mut Pages<W>, cell: Vec<u8>, rowid: u64) -> io::Result<()> {
+        let leaf = &mut self.levels[0];
+        if !leaf.fits(&cell) {
+            // A table divides its leaves by their last rowid. An index has every key once, so
+            // the last key of the leaf moves up to divide it from the next one.
+            let divider = if self.index {
+                leaf.pop()
+            } else {
+                let mut key = Vec::new();
+                put_varint(&mut key, self.last_rowid);
+                key
+            };
+            let child = pages.push(&leaf.encode(0, None))?;
+            *leaf = Page::new(leaf.kind);
+            self.add_divider(pages, child, divider)?;
+        }
+        self.levels[0].push(cell);
+        self.last_rowid = rowid;
+        Ok(())
+    }
+
+    /// Add `child` and the key after everything in it to the interior pages
+    fn add_divider<W: Write>(
+        &mut self,
+        pages: &mut Pages<W>,
+        mut child: u32,
+        mut key: Vec<u8>,
+    ) -> io::Result<()> {
+        let kind = if self.index {
+            INTERIOR_INDEX
+        } else {
+            INTERIOR_TABLE
+        };
+        for level in 1.. {
+            if self.levels.len() == level {
+                self.levels.push(Page::new(kind));
+            }
+            let mut cell = child.to_be_bytes().to_vec();
+            cell.extend(key);
+            let page = &mut self.levels[level];
+            if page.fits(&cell) {
+                page.push(cell);
+                break;
+            }
+            // The last child of the full page becomes its right-most one, and the key after
+            // it divides the page from the next one a level up
+            let last = page.pop();
+            let right_child = u32::from_be_bytes(last[..4].try_into().unwrap());
+            let number = pages.push(&page.encode(0, Some(right_child)))?;
+            *page = Page::new(kind);
+            page.push(cell);
+            child = number;
+            key = last[4..].to_vec();
+        }
+        Ok(())
+    }
+
+    /// Write the pages that are left, returning the root page
+    fn finish<W: Write>(self, pages: &mut Pages<W>) -> io::Result<u32> {
+        let mut child = None;
+        for page in self.levels {
+            child = Some(pages.push(&page.encode(0, child))?);
+        }
+        Ok(child.expect("there is a leaf"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn page(db: &[u8], number: u32) -> &[u8] {
+        &db[(number as usize - 1) * PAGE_SIZE..][..PAGE_SIZE]
+    }
+
+    fn u32_at(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes(bytes[..4].try_into().unwrap())
+    }
+
+    fn read_varint(bytes: &[u8]) -> (u64, usize) {
+        let mut value = 0;
+        for (i, &byte) in bytes.iter().enumerate().take(8) {
+            value = value << 7 | u64::from(byte & 0x7f);
+            if byte & 0x80 == 0 {
+                return (value, i + 1);
+            }
+        }
+        (value << 8 | u64::from(bytes[8]), 9)
+    }
+
+    /// The payloads of the b-tree at `root` in key order, read back the way SQLite does
+    fn payloads(db: &[u8], root: u32, out: &mut Vec<Vec<u8>>) {
+        let tree_page = page(db, root);
+        let offset = if root == 1 { DATABASE_HEADER } else { 0 };
+        let kind = tree_page[offset];
+        let leaf = kind == LEAF_INDEX || kind == LEAF_TABLE;
+        let cells = u16::from_be_bytes([tree_page[offset + 3], tree_page[offset + 4]]) as usize;
+        let pointers = offset + if leaf { 8 } else { 12 };
+        for i in 0..cells {
+            let at =
+                u16::from_be_bytes([tree_page[pointers + 2 * i], tree_page[pointers + 2 * i + 1]]);
+            let mut cell = &tree_page[at as usize..];
+            if !leaf {
+                payloads(db, u32_at(cell), out);
+                cell = &cell[4..];
+            }
+            if kind == INTERIOR_TABLE {
+                continue;
+            }
+            let (len, n) = read_varint(cell);
+            cell = &cell[n..];
+            if kind == LEAF_TABLE {
+                cell = &cell[read_varint(cell).1..];
+            }
+            let len = len as usize;
+            let max_local = if kind == LEAF_TABLE {
+                TABLE_MAX_LOCAL
+            } else {
+                INDEX_MAX_LOCAL
+            };
+            let local = local_size(len, max_local);
+            let mut payload = cell[..local].to_vec();
+            let mut next = if local < len {
+                u32_at(&cell[local..])
+            } else {
+                0
+            };
+            while next != 0 {
+                let overflow = page(db, next);
+                let take = (len - payload.len()).min(PAGE_SIZE - 4);
+                payload.extend_from_slice(&overflow[4..4 + take]);
+                next = u32_at(overflow);
+            }
+            assert_eq!(payload.len(), len);
+            out.push(payload);
+        }
+        if !leaf {
+            payloads(db, u32_at(&tree_page[offset + 8..]), out);
+        }
+    }
+
+    /// The values of a record, NULL for null
+    fn fields(record: &[u8]) -> Vec<String> {
+        let (header_size, mut at) = read_varint(record);
+        let mut body = header_size as usize;
+        let mut fields = Vec::new();
+        while at < header_size as usize {
+            let (serial_type, n) = read_varint(&record[at..]);
+            at += n;
+            let len = match serial_type {
+                0 | 8 | 9 => 0,
+                1..=4 => serial_type as usize,
+                5 => 6,
+                6 => 8,
+                _ => (serial_type as usize - 13) / 2,
+            };
+            let value = &record[body..body + len];
+            fields.push(match serial_type {
+                0 => "NULL".to_owned(),
+                8 => "0".to_owned(),
+                9 => "1".to_owned(),
+                1..=6 => {
+                    let mut bytes = [if value[0] & 0x80 != 0 { 0xff } else { 0 }; 8];
+                    bytes[8 - len..].copy_from_slice(value);
+                    i64::from_be_bytes(bytes).to_string()
+                }
+                _ => String::from_utf8(value.to_vec()).unwrap(),
+            });
+            body += len;
+        }
+        fields
+    }
+
+    fn records(db: &[u8], root: u32) -> Vec<Vec<String>> {
+        let mut out = Vec::new();
+        payloads(db, root, &mut out);
+        out.iter().map(|payload| fields(payload)).collect()
+    }
+
+    #[test]
+    fn varints() {
+        for value in [
+            0,
+            127,
+            128,
+            16_383,
+            16_384,
+            1 << 55,
+            (1 << 56) - 1,
+            1 << 56,
+            u64::MAX,
+        ] {
+            let mut bytes = Vec::new();
+            put_varint(&mut bytes, value);
+            assert_eq!(bytes.len(), varint_len(value));
+            assert_eq!(read_varint(&bytes), (value, bytes.len()));
+        }
+    }
+
+    #[test]
+    fn rows_and_indexes_read_back() {
+        let root: Rc<str> = Rc::from("/srv");
+        // Paths from a few bytes to ones that spill over several overflow pages, in a tree
+        // deep enough for interior pages on every b-tree
+        let rows: Vec<Row> = (0..3000u64)
+            .map(|i| Row {
+                root: Rc::clone(&root),
+                path: format!("/srv/{}/file{}.Dat", "d".repeat(i as usize % 7 * 1500), i),
+                size: if i == 7 { 1 << 40 } else { i * 7919 % 1000 },
+                is_file: i % 10 != 0,
+                mtime: (i % 10 != 0).then_some(1_700_000_000 + i),
+                links: (i % 10 != 0).then_some(1),
+            })
+            .collect();
+        let mut db = Cursor::new(Vec::new());
+        write(&mut db, &rows).unwrap();
+        let db = db.into_inner();
+        assert_eq!(&db[..16], b"SQLite format 3\0");
+        assert_eq!(db.len(), u32_at(&db[28..]) as usize * PAGE_SIZE);
+
+        let schema = records(&db, 1);
+        let names: Vec<&str> = schema.iter().map(|object| object[1].as_str()).collect();
+        assert_eq!(names, ["files", "files_size", "files_path"]);
+        let root_page = |i: usize| schema[i][3].parse().unwrap();
+
+        let files = records(&db, root_page(0));
+        assert_eq!(files.len(), rows.len());
+        for (row, fields) in rows.iter().zip(files.iter()) {
+            let name = row.path.rsplit('/').next().unwrap();
+            let extension = if row.is_file { "dat" } else { "" };
+            let optional =
+                |value: Option<u64>| value.map_or("NULL".to_owned(), |value| value.to_string());
+            let expected = [
+                "/srv".to_owned(),
+                row.path.clone(),
+                name.to_owned(),
+                extension.to_owned(),
+                row.size.to_string(),
+                (row.is_file as u8).to_string(),
+                optional(row.mtime),
+                optional(row.links),
+            ];
+            assert_eq!(fields[..], expected[..]);
+        }
+
+        let mut by_size: Vec<(u64, u64)> = (1..)
+            .zip(rows.iter())
+            .map(|(rowid, row)| (row.size, rowid))
+            .collect();
+        by_size.sort_unstable();
+        let size_index: Vec<(u64, u64)> = records(&db, root_page(1))
+            .iter()
+            .map(|key| (key[0].parse().unwrap(), key[1].parse().unwrap()))
+            .collect();
+        assert_eq!(size_index, by_size);
+
+        let mut by_path: Vec<(&str, u64)> = (1..)
+            .zip(rows.iter())
+            .map(|(rowid, row)| (row.path.as_str(), rowid))
+            .collect();
+        by_path.sort_unstable();
+        let path_index = records(&db, root_page(2));
+        assert_eq!(path_index.len(), by_path.len());
+        for (key, (path, rowid)) in path_index.iter().zip(by_path) {
+            assert_eq!((key[0].as_str(), key[1].parse().unwrap()), (path, rowid));
+        }
+    }
+}
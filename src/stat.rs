@@ -0,0 +1,47 @@
+use std::fs::Metadata;
+
+/// The part of a file's metadata a scan looks at
+pub struct Stat {
+    pub is_dir: bool,
+    /// A block device, whose len is 0 rather than its capacity
+    pub is_block_device: bool,
+    pub len: u64,
+    /// Modification time in nanoseconds since the epoch, see `cache::mtime`
+    pub mtime: u64,
+    /// The device it is on, the same for everything where that is not known
+    pub device: u64,
+    /// Hard links, 1 where the platform has none
+    pub links: u64,
+    /// Device and inode number, None where they are not known
+    pub inode: Option<(u64, u64)>,
+}
+
+impl Stat {
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Stat {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        Stat {
+            is_dir: metadata.is_dir(),
+            is_block_device: metadata.file_type().is_block_device(),
+            len: metadata.len(),
+            mtime: crate::cache::mtime(metadata),
+            device: metadata.dev(),
+            links: metadata.nlink(),
+            inode: Some((metadata.dev(), metadata.ino())),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(metadata: &Metadata) -> Stat {
+        Stat {
+            is_dir: metadata.is_dir(),
+            is_block_device: false,
+            len: metadata.len(),
+            mtime: crate::cache::mtime(metadata),
+            device: 0,
+            links: 1,
+            inode: None,
+        }
+    }
+}
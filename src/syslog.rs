@@ -0,0 +1,66 @@
+/// Facility names accepted by --syslog-facility
+pub const FACILITIES: &[&str] = &[
+    "user", "daemon", "local0", "local1", "local2", "local3", "local4", "local5", "local6",
+    "local7",
+];
+
+/// Send `lines` to the system log with the given facility at notice level.
+/// Where there is no syslog they are printed to stderr instead.
+#[cfg(unix)]
+pub fn send(facility: &str, lines: &[String]) {
+    use std::ffi::CString;
+
+    // Without a syslog daemon listening the messages would silently go nowhere
+    if cfg!(target_os = "linux") && !std::path::Path::new("/dev/log").exists() {
+        fallback(lines);
+        return;
+    }
+
+    let facility = match facility {
+        "daemon" => libc::LOG_DAEMON,
+        "local0" => libc::LOG_LOCAL0,
+        "local1" => libc::LOG_LOCAL1,
+        "local2" => libc::LOG_LOCAL2,
+        "local3" => libc::LOG_LOCAL3,
+        "local4" => libc::LOG_LOCAL4,
+        "local5" => libc::LOG_LOCAL5,
+        "local6" => libc::LOG_LOCAL6,
+        "local7" => libc::LOG_LOCAL7,
+        _ => libc::LOG_USER,
+    };
+    // SAFETY: the ident is a static string, as openlog keeps the pointer
+    unsafe { libc::openlog(c"lff".as_ptr(), libc::LOG_PID, facility) };
+    for line in lines {
+        // Interior NULs can not be passed on, the rest of the line still can
+        let message = CString::new(line.replace('\0', "")).unwrap_or_default();
+        // SAFETY: the message goes through %s, so it is never read as a format string
+        unsafe { libc::syslog(libc::LOG_NOTICE, c"%s".as_ptr(), message.as_ptr()) };
+    }
+    unsafe { libc::closelog() };
+}
+
+#[cfg(not(unix))]
+pub fn send(_facility: &str, lines: &[String]) {
+    fallback(lines);
+}
+
+fn fallback(lines: &[String]) {
+    log::warn!("syslog is not available, printing the summary instead");
+    for line in lines {
+        eprintln!("{}", line);
+    }
+}
+
+/// The name of this machine, to tell scans from different hosts apart in a central log
+pub fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        // SAFETY: the length leaves room for the terminating NUL
+        if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len() - 1) } == 0 {
+            let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+            return String::from_utf8_lossy(&buf[..len]).into_owned();
+        }
+    }
+    "unknown".to_owned()
+}
@@ -0,0 +1,63 @@
+/// Whether stdout is an interactive terminal
+pub fn stdout_is_tty() -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: isatty only inspects the descriptor
+        unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Columns of the terminal, else `$COLUMNS`, else 80. Stderr is asked too since stdout is
+/// a pipe when the output goes through the pager.
+pub fn width() -> usize {
+    #[cfg(unix)]
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: winsize is plain data that TIOCGWINSZ fills in
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+            return size.ws_col as usize;
+        }
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|columns| *columns > 0)
+        .unwrap_or(80)
+}
+
+/// Shorten `path` to at most `max` characters by replacing components in the middle with
+/// `...`, keeping as many leading and trailing components as fit. The file name is always
+/// kept whole, even when that alone is longer than `max`.
+pub fn truncate_path(path: &str, max: usize) -> String {
+    if path.chars().count() <= max {
+        return path.to_owned();
+    }
+    let parts: Vec<&str> = path.split('/').collect();
+    let name = parts[parts.len() - 1];
+    if parts.len() < 3 {
+        return path.to_owned();
+    }
+
+    let len = |parts: &[&str]| parts.iter().map(|part| part.chars().count() + 1).sum::<usize>();
+    // "/.../" between the kept head and tail
+    let fits = |head: &[&str], tail: &[&str]| len(head) + 4 + len(tail) - 1 <= max;
+    let (mut head, mut tail) = (1, parts.len() - 1);
+    if !fits(&parts[..head], &parts[tail..]) {
+        return format!(".../{}", name);
+    }
+    // Grow from the end first, the components next to the file name say the most about it
+    loop {
+        if head < tail - 1 && fits(&parts[..head], &parts[tail - 1..]) {
+            tail -= 1;
+        } else if head < tail - 1 && fits(&parts[..head + 1], &parts[tail..]) {
+            head += 1;
+        } else {
+            break;
+        }
+    }
+    format!("{}/.../{}", parts[..head].join("/"), parts[tail..].join("/"))
+}
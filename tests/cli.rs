@@ -0,0 +1,287 @@
+//! Runs the lff binary against trees built in temporary directories
+
+mod common;
+
+use common::{lff, lff_in, stderr, stdout, TempDir};
+
+#[test]
+fn missing_root_is_a_clean_error() {
+    let dir = TempDir::new();
+    let missing = dir.path().join("missing");
+    let output = lff(&[missing.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("no such directory"), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("panicked"), "{}", stderr(&output));
+}
+
+#[test]
+fn no_directory_scans_the_current_one() {
+    let dir = TempDir::new();
+    dir.file("big.bin", 2000);
+    dir.file("sub/small.bin", 100);
+    let output = lff_in(dir.path(), &["--percent", "0"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert!(lines[0].ends_with(" d ."), "{}", listing);
+    assert!(lines.iter().any(|line| line.ends_with(" f ./big.bin")), "{}", listing);
+    assert!(lines.iter().any(|line| line.ends_with(" f ./sub/small.bin")), "{}", listing);
+    assert!(listing.contains("Total size: 2 KiB"), "{}", listing);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_root_is_scanned_with_dereference_root() {
+    let dir = TempDir::new();
+    dir.file("real/a.bin", 3000);
+    dir.file("real/sub/b.bin", 1000);
+    std::os::unix::fs::symlink("real", dir.path().join("link")).unwrap();
+    let link = dir.path().join("link");
+    let output = lff(&["--percent", "0", "--dereference-root", link.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    let shown = |relative: &str| format!(" f {}", link.join(relative).display());
+    assert!(listing.lines().any(|line| line.ends_with(&shown("a.bin"))), "{}", listing);
+    assert!(listing.lines().any(|line| line.ends_with(&shown("sub/b.bin"))), "{}", listing);
+    assert!(listing.contains("Total size: 3 KiB"), "{}", listing);
+}
+
+#[test]
+fn dotfiles_are_counted_unless_excluded() {
+    let dir = TempDir::new();
+    dir.file(".hidden", 3000);
+    dir.file("shown", 100);
+    let root = dir.path().to_str().unwrap();
+
+    let output = lff(&["--percent", "0", root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    assert!(listing.contains("/.hidden\n"), "{}", listing);
+    assert!(listing.contains("Total size: 3 KiB"), "{}", listing);
+
+    let output = lff(&["--percent", "0", "--exclude-hidden", root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    assert!(!listing.contains(".hidden"), "{}", listing);
+    assert!(listing.contains("/shown\n"), "{}", listing);
+    assert!(listing.contains("Total size: 100 B"), "{}", listing);
+
+    let output = lff(&["--percent", "0", "--ignore", "true", "--include-hidden", root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    assert!(listing.contains("/.hidden\n"), "{}", listing);
+}
+
+#[test]
+fn top_is_written_in_the_selected_format() {
+    let dir = TempDir::new();
+    dir.file("big.bin", 3000);
+    dir.file("small.bin", 100);
+    let root = dir.path().to_str().unwrap();
+    let big = dir.path().join("big.bin");
+
+    let output = lff(&["--top", "1", "--format", "json", root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let expected = format!(
+        "{{\"schema_version\":1,\"root\":\"{}\",\"total_bytes\":3100,\"entries\":[{{\"path\":\"{}\",\"type\":\"file\",\"size\":3000}}]}}\n",
+        root,
+        big.display()
+    );
+    assert_eq!(stdout(&output), expected);
+
+    let output = lff(&["--top", "1", "--format", "canonical", root]);
+    assert_eq!(stdout(&output), format!("0000000000003000 {}\n", big.display()));
+
+    let output = lff(&["--top", "1", "--format", "treemap", root]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("--top can not be written as --format treemap"));
+}
+
+#[test]
+fn extensions_are_grouped_ignoring_case() {
+    let dir = TempDir::new();
+    dir.file("a.JPG", 1000);
+    dir.file("b.jpg", 1000);
+    dir.file("sub/c.Jpg", 1000);
+    dir.file("d.png", 10);
+    let root = dir.path().to_str().unwrap();
+
+    let output = lff(&["--by-ext", root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    let rows: Vec<Vec<&str>> = listing
+        .lines()
+        .skip(1)
+        .take(2)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows, [vec!["2", "KiB", "3", "jpg"], vec!["10", "B", "1", "png"]], "{}", listing);
+
+    let output = lff(&["--by-ext", "--case-sensitive-ext", root]);
+    let listing = stdout(&output);
+    for ext in ["JPG", "Jpg", "jpg"] {
+        assert!(listing.lines().any(|line| line.ends_with(&format!(" 1  {}", ext))), "{}", listing);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn no_symlinks_leaves_symlinks_out_of_listing_and_totals() {
+    let dir = TempDir::new();
+    dir.file("a.bin", 3000);
+    dir.file("d/b.bin", 100);
+    std::os::unix::fs::symlink("a.bin", dir.path().join("file-link")).unwrap();
+    std::os::unix::fs::symlink("d", dir.path().join("dir-link")).unwrap();
+    let root = dir.path().to_str().unwrap();
+
+    // A symlink on its own is the length of its target path
+    let output = lff(&["--oneline", root]);
+    assert_eq!(stdout(&output), "total=3106 largest=3000 files=4 dirs=2\n");
+
+    let output = lff(&["--oneline", "--no-symlinks", root]);
+    assert_eq!(stdout(&output), "total=3100 largest=3000 files=2 dirs=2\n");
+
+    let output = lff(&["--percent", "0", "--include-zero", "--no-symlinks", root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    assert!(!listing.contains("link"), "{}", listing);
+    assert!(listing.contains("/d/b.bin\n"), "{}", listing);
+}
+
+#[test]
+fn by_ext_is_the_same_with_and_without_a_tree() {
+    let dir = TempDir::new();
+    dir.file("a.mp4", 3000);
+    dir.file("sub/b.MP4", 2000);
+    dir.file("sub/c.log", 10);
+    let root = dir.path().to_str().unwrap();
+
+    let streamed = lff(&["--by-ext", root]);
+    assert!(streamed.status.success(), "{}", stderr(&streamed));
+    assert!(stdout(&streamed).contains(" 2  mp4\n"), "{}", stdout(&streamed));
+    // --verify-total needs the tree, so the totals are summed up from it instead
+    let from_tree = lff(&["--by-ext", "--verify-total", root]);
+    assert!(stdout(&from_tree).starts_with(&stdout(&streamed)), "{}", stdout(&from_tree));
+
+    let output = lff(&["--by-ext", "--format", "json", root]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("--by-ext can only be written as text"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn names_that_are_not_utf8_are_counted() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = TempDir::new();
+    dir.file("good", 100);
+    std::fs::write(dir.path().join(OsStr::from_bytes(b"bad\xff")), vec![0; 3000]).unwrap();
+    let root = dir.path().to_str().unwrap();
+    let output = lff(&["--percent", "0", root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    assert!(listing.contains("/bad\u{fffd}\n"), "{}", listing);
+    assert!(listing.contains("Total size: 3 KiB"), "{}", listing);
+}
+
+#[test]
+fn sqlite_database_is_written_to_output() {
+    let dir = TempDir::new();
+    dir.file("a/big.bin", 3000);
+    dir.file("b/small.bin", 100);
+    let database = dir.path().join("scan.db");
+    let a = dir.path().join("a");
+    let b = dir.path().join("b");
+    let args = ["--format", "sqlite", a.to_str().unwrap(), b.to_str().unwrap()];
+    let output = lff(&[&args[..], &["--output", database.to_str().unwrap()]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // Both roots are in the one database, with its page count in the header
+    let bytes = std::fs::read(&database).unwrap();
+    assert_eq!(&bytes[..16], b"SQLite format 3\0");
+    let pages = u32::from_be_bytes(bytes[28..32].try_into().unwrap());
+    assert_eq!(bytes.len(), pages as usize * 4096);
+    let contains = |s: &str| bytes.windows(s.len()).any(|window| window == s.as_bytes());
+    assert!(contains("big.bin") && contains("small.bin"));
+
+    let output = lff(&args);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("--format sqlite needs --output"), "{}", stderr(&output));
+}
+
+#[cfg(unix)]
+#[test]
+fn hard_links_are_listed_once_with_unique_inodes() {
+    let dir = TempDir::new();
+    dir.file("a.bin", 3000);
+    dir.file("d.bin", 100);
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::hard_link(dir.path().join("a.bin"), dir.path().join("sub/b.bin")).unwrap();
+    std::fs::hard_link(dir.path().join("a.bin"), dir.path().join("c.bin")).unwrap();
+    let root = dir.path().to_str().unwrap();
+    let links = |listing: &str| {
+        let names = ["/a.bin", "/sub/b.bin", "/c.bin"];
+        listing.lines().filter(|line| names.iter().any(|name| line.contains(name))).count()
+    };
+
+    let all = lff(&["--percent", "0", root]);
+    assert!(all.status.success(), "{}", stderr(&all));
+    assert_eq!(links(&stdout(&all)), 3, "{}", stdout(&all));
+
+    let collapsed = lff(&["--percent", "0", "--unique-inodes", root]);
+    assert!(collapsed.status.success(), "{}", stderr(&collapsed));
+    let listing = stdout(&collapsed);
+    assert_eq!(links(&listing), 1, "{}", listing);
+    assert!(listing.lines().any(|line| line.ends_with(" (+2 more links)")), "{}", listing);
+    assert!(listing.lines().any(|line| line.ends_with("/d.bin")), "{}", listing);
+    // Only the listing changes, not the totals
+    let total = |listing: &str| {
+        listing.lines().find(|line| line.starts_with("Total size")).unwrap().to_owned()
+    };
+    assert_eq!(total(&listing), total(&stdout(&all)));
+}
+
+#[test]
+fn skipped_directory_contributes_nothing() {
+    let dir = TempDir::new();
+    dir.file("keep/a.bin", 1000);
+    dir.file("datasets/big.bin", 50_000);
+    dir.file("datasets/sub/more.bin", 20_000);
+    // Only the exact directory is skipped, not others with the same name
+    dir.file("keep/datasets/c.bin", 100);
+    let root = dir.path().to_str().unwrap();
+    let skipped = dir.path().join("datasets");
+    let output = lff(&[
+        "--percent",
+        "0",
+        "--unit",
+        "b",
+        "--skip-dir",
+        skipped.to_str().unwrap(),
+        root,
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    assert!(!listing.contains("big.bin") && !listing.contains("more.bin"), "{}", listing);
+    assert!(listing.contains("/keep/datasets/c.bin"), "{}", listing);
+    assert!(listing.contains("Total size: 1100 B"), "{}", listing);
+}
+
+#[test]
+fn text_only_reports_refuse_other_formats() {
+    let dir = TempDir::new();
+    dir.file("sub/a.bin", 3000);
+    let root = dir.path().to_str().unwrap();
+    let reports: [&[&str]; 4] =
+        [&["--group-by-dir"], &["--largest-dirs", "3"], &["--duplicates"], &["--heavy-path"]];
+    for report in reports {
+        let output = lff(&[report, &["--format", "json", root]].concat());
+        assert_eq!(output.status.code(), Some(1), "{:?}", report);
+        let expected = format!("{} can only be written as text, not as --format json", report[0]);
+        assert!(stderr(&output).contains(&expected), "{}", stderr(&output));
+        assert!(stdout(&output).is_empty(), "{}", stdout(&output));
+    }
+    let output = lff(&["--group-by-dir", root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
@@ -0,0 +1,124 @@
+//! Scans trees made by --generate-fixture, whose total is known up front
+
+mod common;
+
+use common::{lff, lff_with_env, stderr, stdout, TempDir};
+use std::path::Path;
+
+/// Generate `files` files of up to `max_size` in `dir`, returning the total it reports
+fn generate(dir: &Path, files: &str, max_size: &str) -> u64 {
+    let output = lff(&[
+        "--generate-fixture",
+        dir.to_str().unwrap(),
+        "--files",
+        files,
+        "--max-size",
+        max_size,
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report = stdout(&output);
+    // Created N files in DIR, total size: 1 GiB (1234 bytes)
+    let bytes = report.rsplit('(').next().unwrap().trim_end_matches(" bytes)\n");
+    bytes.parse().unwrap_or_else(|_| panic!("no total in {:?}", report))
+}
+
+/// The `key=value` fields of --oneline
+fn oneline(root: &str, args: &[&str]) -> Vec<(String, u64)> {
+    let output = lff(&[args, &["--oneline", root]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    stdout(&output)
+        .split_whitespace()
+        .map(|field| {
+            let (key, value) = field.split_once('=').unwrap();
+            (key.to_owned(), value.parse().unwrap())
+        })
+        .collect()
+}
+
+/// Sizes of the files of a --format canonical listing, largest first
+fn canonical_file_sizes(args: &[&str]) -> Vec<u64> {
+    let output = lff(&[args, &["--format", "canonical"]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    let mut sizes: Vec<u64> = stdout(&output)
+        .lines()
+        .filter(|line| !line.ends_with('/'))
+        .map(|line| line.split(' ').next().unwrap().parse().unwrap())
+        .collect();
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+    sizes
+}
+
+#[test]
+fn scan_finds_the_generated_total() {
+    let dir = TempDir::new();
+    let total = generate(dir.path(), "500", "1G");
+    let root = dir.path().to_str().unwrap();
+    let fields = oneline(root, &[]);
+    assert_eq!(fields[0], ("total".to_owned(), total));
+    assert_eq!(fields[2], ("files".to_owned(), 500));
+    // dir0 to dir9 with sub0 to sub9 each, and the root
+    assert_eq!(fields[3], ("dirs".to_owned(), 111));
+}
+
+#[test]
+fn fixture_is_reproducible() {
+    let first = TempDir::new();
+    let second = TempDir::new();
+    assert_eq!(generate(first.path(), "200", "10M"), generate(second.path(), "200", "10M"));
+}
+
+#[test]
+fn json_and_canonical_add_up_to_the_generated_total() {
+    let dir = TempDir::new();
+    let total = generate(dir.path(), "300", "100M");
+    let root = dir.path().to_str().unwrap();
+
+    let output = lff(&["--percent", "0", "--include-zero", "--format", "json", root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let json = stdout(&output);
+    assert!(json.contains(&format!("\"total_bytes\":{},", total)), "{}", json);
+
+    let files = canonical_file_sizes(&["--percent", "0", "--include-zero", root]);
+    assert_eq!(files.len(), 300);
+    assert_eq!(files.iter().sum::<u64>(), total);
+}
+
+#[test]
+fn top_lists_the_largest_generated_files() {
+    let dir = TempDir::new();
+    generate(dir.path(), "300", "100M");
+    let root = dir.path().to_str().unwrap();
+    let all = canonical_file_sizes(&["--percent", "0", "--include-zero", root]);
+    assert_eq!(canonical_file_sizes(&["--top", "5", root]), all[..5]);
+}
+
+#[test]
+fn fixture_needs_an_empty_directory() {
+    let dir = TempDir::new();
+    dir.file("existing", 1);
+    let output = lff(&["--generate-fixture", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("is not empty"), "{}", stderr(&output));
+}
+
+#[test]
+fn single_threaded_scans_find_the_same() {
+    let dir = TempDir::new();
+    let total = generate(dir.path(), "400", "10M");
+    let root = dir.path().to_str().unwrap();
+    let args = ["--percent", "0", "--include-zero", "--format", "canonical", root];
+    let parallel = lff(&args);
+    assert!(parallel.status.success(), "{}", stderr(&parallel));
+
+    let sequential = lff(&[&["--no-parallel"], &args[..]].concat());
+    assert!(sequential.status.success(), "{}", stderr(&sequential));
+    assert_eq!(stdout(&sequential), stdout(&parallel));
+
+    // One worker thread still takes the parallel walk
+    let one_thread = lff_with_env("RAYON_NUM_THREADS", "1", &args);
+    assert!(one_thread.status.success(), "{}", stderr(&one_thread));
+    assert_eq!(stdout(&one_thread), stdout(&parallel));
+
+    let fields = oneline(root, &["--no-parallel"]);
+    assert_eq!(fields[0], ("total".to_owned(), total));
+}